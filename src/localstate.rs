@@ -16,4 +16,8 @@ impl LocalState {
     pub fn add_model_moves(&mut self, model: Model) -> () {
         self.models.push(model);
     }
+
+    pub fn find_model_mut(&mut self, name: &str) -> Option<&mut Model> {
+        self.models.iter_mut().find(|model| model.name == name)
+    }
 }
@@ -1,25 +1,30 @@
 #![allow(unused_imports)]
 #![allow(unreachable_patterns)]
+#![cfg_attr(feature = "bench", feature(test))]
 
-#![feature(rustc_private)]
 #[macro_use]
 extern crate log;
 extern crate simple_logger;
 extern crate glfw;
 extern crate gl; 
+extern crate rayon;
+extern crate serde_json;
+extern crate base64;
+#[cfg(feature = "simd")]
 extern crate packed_simd;
 
+#[macro_use]
+mod math;
 mod input;
 mod window;
 mod renderer;
 mod localstate;
-mod math;
 
 use input::{get_inputs, UserInput::CloseRequested};
 use window::WindowState;
 use renderer::{clear_screen, Renderer, load_models_from_local_state, draw_models};
 use localstate::LocalState;
-use math::Mat4;
+use math::{Mat4, Quat, Vec3};
 
 use glfw::Context;
 
@@ -42,6 +47,9 @@ fn main() -> Result<(), &'static str> {
                 _ => {}
             }
         }
+        for model in local_state.models.iter_mut() {
+            model.transform.rotate(Quat::from_axis_angle(Vec3::unit_x(), 0.0001));
+        }
         clear_screen(&mut renderer, &local_state);
         draw_models(&mut renderer, &mut local_state).unwrap();
         window_state.window.swap_buffers();
@@ -0,0 +1,64 @@
+//Tolerance used by the assertion macros when none is given
+pub const DEFAULT_EPSILON: f32 = 1e-5;
+
+//Component-wise comparison with an absolute tolerance
+pub trait ApproxEq {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool;
+}
+
+impl ApproxEq for f32 {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self == other || (self - other).abs() <= epsilon
+    }
+}
+
+impl ApproxEq for [f32] {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.len() == other.len()
+            && self.iter().zip(other.iter()).all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+impl<T: ApproxEq> ApproxEq for Option<T> {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        match (self, other) {
+            (Some(a), Some(b)) => a.approx_eq(b, epsilon),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+//assert_approx_eq!(a, b) or assert_approx_eq!(a, b, epsilon). Works on
+//anything implementing ApproxEq and Debug.
+#[allow(unused_macros)]
+macro_rules! assert_approx_eq {
+    ($left:expr, $right:expr) => {
+        assert_approx_eq!($left, $right, $crate::math::DEFAULT_EPSILON)
+    };
+    ($left:expr, $right:expr, $epsilon:expr) => {{
+        let (left, right, epsilon) = (&$left, &$right, $epsilon);
+        if !$crate::math::ApproxEq::approx_eq(left, right, epsilon) {
+            panic!(
+                "assertion failed: `left ~= right` (epsilon: {})\n  left: {:?}\n right: {:?}",
+                epsilon, left, right,
+            );
+        }
+    }};
+}
+
+#[allow(unused_macros)]
+macro_rules! assert_approx_ne {
+    ($left:expr, $right:expr) => {
+        assert_approx_ne!($left, $right, $crate::math::DEFAULT_EPSILON)
+    };
+    ($left:expr, $right:expr, $epsilon:expr) => {{
+        let (left, right, epsilon) = (&$left, &$right, $epsilon);
+        if $crate::math::ApproxEq::approx_eq(left, right, epsilon) {
+            panic!(
+                "assertion failed: `left !~= right` (epsilon: {})\n  left: {:?}\n right: {:?}",
+                epsilon, left, right,
+            );
+        }
+    }};
+}
@@ -0,0 +1,372 @@
+use super::simd::f32x4;
+use super::{Mat4, Quat, Vec3};
+
+//Batched transforms over structure-of-arrays data. Every lane of an f32x4
+//holds a different point, so four points are transformed per iteration
+//with plain lane-wise multiplies and adds. Tails shorter than four are
+//padded and written back partially.
+
+//Points or directions stored as three separate coordinate streams
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Vec3Soa {
+    pub x : Vec<f32>,
+    pub y : Vec<f32>,
+    pub z : Vec<f32>,
+}
+
+impl Vec3Soa {
+    pub fn new() -> Self {
+        Vec3Soa::default()
+    }
+
+    pub fn with_len(len: usize) -> Self {
+        Vec3Soa {
+            x : vec![0.; len],
+            y : vec![0.; len],
+            z : vec![0.; len],
+        }
+    }
+
+    //Splits interleaved xyz data, e.g. a vertex buffer with a stride of 3
+    pub fn from_interleaved(data: &[f32]) -> Self {
+        let len = data.len() / 3;
+        let mut result = Vec3Soa::with_len(len);
+        for i in 0..len {
+            result.x[i] = data[i * 3];
+            result.y[i] = data[i * 3 + 1];
+            result.z[i] = data[i * 3 + 2];
+        }
+        result
+    }
+
+    pub fn from_vecs(points: &[Vec3]) -> Self {
+        let mut result = Vec3Soa::with_len(points.len());
+        for (i, p) in points.iter().enumerate() {
+            result.x[i] = p.x();
+            result.y[i] = p.y();
+            result.z[i] = p.z();
+        }
+        result
+    }
+
+    pub fn to_interleaved(&self) -> Vec<f32> {
+        let mut out = Vec::with_capacity(self.len() * 3);
+        for i in 0..self.len() {
+            out.push(self.x[i]);
+            out.push(self.y[i]);
+            out.push(self.z[i]);
+        }
+        out
+    }
+
+    pub fn get(&self, i: usize) -> Vec3 {
+        Vec3::new(self.x[i], self.y[i], self.z[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn push(&mut self, p: Vec3) -> () {
+        self.x.push(p.x());
+        self.y.push(p.y());
+        self.z.push(p.z());
+    }
+}
+
+fn load(s: &[f32], i: usize) -> f32x4 {
+    if i + 4 <= s.len() {
+        f32x4::from_slice_unaligned(&s[i..i + 4])
+    } else {
+        let mut tail = [0.;4];
+        tail[..s.len() - i].copy_from_slice(&s[i..]);
+        f32x4::from_slice_unaligned(&tail)
+    }
+}
+
+fn store(v: f32x4, s: &mut [f32], i: usize) -> () {
+    if i + 4 <= s.len() {
+        v.write_to_slice_unaligned(&mut s[i..i + 4]);
+    } else {
+        let mut tail = [0.;4];
+        v.write_to_slice_unaligned(&mut tail);
+        let n = s.len() - i;
+        s[i..].copy_from_slice(&tail[..n]);
+    }
+}
+
+//w is 1.0 for points and 0.0 for directions
+fn transform_soa(m: &Mat4, src: &Vec3Soa, dst: &mut Vec3Soa, w: f32) -> () {
+    assert!(src.y.len() == src.x.len() && src.z.len() == src.x.len());
+    let len = src.len();
+    dst.x.resize(len, 0.);
+    dst.y.resize(len, 0.);
+    dst.z.resize(len, 0.);
+    let e = |r: usize, c: usize| f32x4::splat(m.at(r, c));
+    let row = |r: usize| [e(r, 0), e(r, 1), e(r, 2), f32x4::splat(m.at(r, 3) * w)];
+    let (r0, r1, r2) = (row(0), row(1), row(2));
+    let mut i = 0;
+    while i < len {
+        let x = load(&src.x, i);
+        let y = load(&src.y, i);
+        let z = load(&src.z, i);
+        store(r0[0] * x + r0[1] * y + r0[2] * z + r0[3], &mut dst.x, i);
+        store(r1[0] * x + r1[1] * y + r1[2] * z + r1[3], &mut dst.y, i);
+        store(r2[0] * x + r2[1] * y + r2[2] * z + r2[3], &mut dst.z, i);
+        i += 4;
+    }
+}
+
+//dst is resized to src.len(). The bottom row of m is ignored, i.e. the
+//result is not divided by w.
+pub fn transform_points(m: &Mat4, src: &Vec3Soa, dst: &mut Vec3Soa) -> () {
+    transform_soa(m, src, dst, 1.0);
+}
+
+//Like transform_points but ignores translation. Use the inverse transpose
+//of m for normals if it carries non-uniform scale.
+pub fn transform_directions(m: &Mat4, src: &Vec3Soa, dst: &mut Vec3Soa) -> () {
+    transform_soa(m, src, dst, 0.0);
+}
+
+//Transforms interleaved xyz data in place, converting through SoA blocks
+//of four points at a time so no temporary buffers are needed
+pub fn transform_points_interleaved(m: &Mat4, data: &mut [f32]) -> () {
+    let e = |r: usize, c: usize| f32x4::splat(m.at(r, c));
+    let row = |r: usize| [e(r, 0), e(r, 1), e(r, 2), e(r, 3)];
+    let (r0, r1, r2) = (row(0), row(1), row(2));
+    for block in data.chunks_mut(12) {
+        let n = block.len() / 3;
+        let (mut xs, mut ys, mut zs) = ([0.;4], [0.;4], [0.;4]);
+        for j in 0..n {
+            xs[j] = block[j * 3];
+            ys[j] = block[j * 3 + 1];
+            zs[j] = block[j * 3 + 2];
+        }
+        let x = f32x4::from_slice_unaligned(&xs);
+        let y = f32x4::from_slice_unaligned(&ys);
+        let z = f32x4::from_slice_unaligned(&zs);
+        (r0[0] * x + r0[1] * y + r0[2] * z + r0[3]).write_to_slice_unaligned(&mut xs);
+        (r1[0] * x + r1[1] * y + r1[2] * z + r1[3]).write_to_slice_unaligned(&mut ys);
+        (r2[0] * x + r2[1] * y + r2[2] * z + r2[3]).write_to_slice_unaligned(&mut zs);
+        for j in 0..n {
+            block[j * 3] = xs[j];
+            block[j * 3 + 1] = ys[j];
+            block[j * 3 + 2] = zs[j];
+        }
+    }
+}
+
+//Builds T * R * S for every instance, four at a time. All three slices
+//must have the same length. `out` is cleared first.
+pub fn build_world_matrices(
+    translations : &[Vec3],
+    rotations    : &[Quat],
+    scales       : &[Vec3],
+    out          : &mut Vec<Mat4>,
+) -> () {
+    let len = translations.len();
+    assert!(rotations.len() == len && scales.len() == len);
+    out.clear();
+    out.reserve(len);
+    let mut i = 0;
+    while i < len {
+        let n = (len - i).min(4);
+        //Lanes: rotation xyzw, then scale xyz
+        let mut soa = [[0.;4];7];
+        for j in 0..n {
+            let (r, sc) = (rotations[i + j], scales[i + j]);
+            soa[0][j] = r.x();
+            soa[1][j] = r.y();
+            soa[2][j] = r.z();
+            soa[3][j] = r.w();
+            soa[4][j] = sc.x();
+            soa[5][j] = sc.y();
+            soa[6][j] = sc.z();
+        }
+        let lane = |k: usize| f32x4::from_slice_unaligned(&soa[k]);
+        let (x, y, z, w) = (lane(0), lane(1), lane(2), lane(3));
+        let (sx, sy, sz) = (lane(4), lane(5), lane(6));
+
+        let two = f32x4::splat(2.0);
+        let one = f32x4::splat(1.0);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (w * x, w * y, w * z);
+        let m = [
+            (one - two * (yy + zz)) * sx, two * (xy - wz) * sy,         two * (xz + wy) * sz,
+            two * (xy + wz) * sx,         (one - two * (xx + zz)) * sy, two * (yz - wx) * sz,
+            two * (xz - wy) * sx,         two * (yz + wx) * sy,         (one - two * (xx + yy)) * sz,
+        ];
+        let mut lanes = [[0.;4];9];
+        for (k, v) in m.iter().enumerate() {
+            v.write_to_slice_unaligned(&mut lanes[k]);
+        }
+        for j in 0..n {
+            let t = translations[i + j];
+            let l = |k: usize| lanes[k][j];
+            out.push(Mat4::from_data([
+                l(0), l(1), l(2), t.x(),
+                l(3), l(4), l(5), t.y(),
+                l(6), l(7), l(8), t.z(),
+                0.0,  0.0,  0.0,  1.0,
+            ]));
+        }
+        i += 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Axis;
+
+    fn sample_matrix() -> Mat4 {
+        let mut m = Mat4::identity();
+        m.stretch(1.5, 2.0, 0.5).rotate(0.8, Axis::Y).translate(3.0, -1.0, 2.0);
+        m
+    }
+
+    fn sample_points(n: usize) -> Vec<Vec3> {
+        (0..n).map(|i| {
+            let f = i as f32;
+            Vec3::new(f * 0.5 - 3.0, (f * 1.7).sin(), f * -0.25)
+        }).collect()
+    }
+
+    #[test]
+    fn soa_matches_per_point_multiply() {
+        let m = sample_matrix();
+        //7 exercises one full block and a padded tail
+        let points = sample_points(7);
+        let mut out = Vec3Soa::new();
+        assert!(out.is_empty());
+        transform_points(&m, &Vec3Soa::from_vecs(&points), &mut out);
+        let mut dirs = Vec3Soa::new();
+        transform_directions(&m, &Vec3Soa::from_vecs(&points), &mut dirs);
+        assert_eq!(out.len(), 7);
+        assert!(!out.is_empty());
+        for (i, p) in points.iter().enumerate() {
+            let expected = (&m * p.extend(1.0)).truncate();
+            assert!((out.get(i) - expected).length() < 1e-5);
+            let expected = (&m * p.extend(0.0)).truncate();
+            assert!((dirs.get(i) - expected).length() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn interleaved_matches_soa() {
+        let m = sample_matrix();
+        let points = sample_points(10);
+        let soa = Vec3Soa::from_vecs(&points);
+        let mut expected = Vec3Soa::new();
+        transform_points(&m, &soa, &mut expected);
+        let mut data = soa.to_interleaved();
+        transform_points_interleaved(&m, &mut data);
+        assert_eq!(data, expected.to_interleaved());
+    }
+
+    #[test]
+    fn world_matrices_match_transform() {
+        let n = 6;
+        let translations: Vec<Vec3> = sample_points(n);
+        let rotations: Vec<Quat> = (0..n)
+            .map(|i| Quat::from_euler(i as f32 * 0.3, 0.2, -(i as f32) * 0.1))
+            .collect();
+        let scales: Vec<Vec3> = (0..n).map(|i| Vec3::new(1.0, 2.0, 0.5 + i as f32)).collect();
+        let mut out = Vec::new();
+        build_world_matrices(&translations, &rotations, &scales, &mut out);
+        assert_eq!(out.len(), n);
+        for i in 0..n {
+            let mut t = crate::math::Transform::new(translations[i], rotations[i], scales[i]);
+            let expected = t.matrix().to_array();
+            let actual = out[i].to_array();
+            for k in 0..16 {
+                assert!((actual[k] - expected[k]).abs() < 1e-5);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod bench {
+    extern crate test;
+
+    use super::*;
+    use self::test::{black_box, Bencher};
+    use crate::math::{Axis, Transform, Vec4};
+
+    const N: usize = 10_000;
+
+    fn setup() -> (Mat4, Vec<Vec3>) {
+        let mut m = Mat4::identity();
+        m.rotate(0.3, Axis::X).translate(1.0, 2.0, 3.0);
+        let points = (0..N).map(|i| Vec3::new(i as f32, 1.0, -(i as f32))).collect();
+        (m, points)
+    }
+
+    #[bench]
+    fn points_per_multiply(b: &mut Bencher) {
+        let (m, points) = setup();
+        let mut out: Vec<Vec4> = Vec::with_capacity(N);
+        b.iter(|| {
+            out.clear();
+            for p in points.iter() {
+                out.push(&m * p.extend(1.0));
+            }
+            black_box(&out);
+        });
+    }
+
+    #[bench]
+    fn points_soa(b: &mut Bencher) {
+        let (m, points) = setup();
+        let src = Vec3Soa::from_vecs(&points);
+        let mut dst = Vec3Soa::with_len(N);
+        b.iter(|| {
+            transform_points(&m, &src, &mut dst);
+            black_box(&dst);
+        });
+    }
+
+    #[bench]
+    fn points_interleaved(b: &mut Bencher) {
+        let (m, points) = setup();
+        let mut data = Vec3Soa::from_vecs(&points).to_interleaved();
+        b.iter(|| {
+            transform_points_interleaved(&m, &mut data);
+            black_box(&data);
+        });
+    }
+
+    #[bench]
+    fn world_matrices_per_transform(b: &mut Bencher) {
+        let (_, points) = setup();
+        let rot = Quat::from_euler(0.1, 0.2, 0.3);
+        let mut out = Vec::with_capacity(N);
+        b.iter(|| {
+            out.clear();
+            for p in points.iter() {
+                let mut t = Transform::new(*p, rot, Vec3::splat(2.0));
+                out.push(t.matrix().clone());
+            }
+            black_box(&out);
+        });
+    }
+
+    #[bench]
+    fn world_matrices_batched(b: &mut Bencher) {
+        let (_, points) = setup();
+        let rotations = vec![Quat::from_euler(0.1, 0.2, 0.3); N];
+        let scales = vec![Vec3::splat(2.0); N];
+        let mut out = Vec::with_capacity(N);
+        b.iter(|| {
+            build_world_matrices(&points, &rotations, &scales, &mut out);
+            black_box(&out);
+        });
+    }
+}
@@ -0,0 +1,453 @@
+use super::{ApproxEq, Mat4, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min : Vec3,
+    pub max : Vec3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sphere {
+    pub center : Vec3,
+    pub radius : f32,
+}
+
+//All points p with normal.dot(p) + d == 0. The normal points to the
+//positive half-space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal : Vec3,
+    pub d      : f32,
+}
+
+//Hits are reported as the ray parameter t, the hit point is origin + t * direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin    : Vec3,
+    pub direction : Vec3,
+}
+
+//Planes face inwards: a point is inside when it is on the positive side
+//of all six. Order is left, right, bottom, top, near, far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes : [Plane;6],
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    //An inverted box that any union or point grows into a valid one
+    pub fn empty() -> Self {
+        Aabb {
+            min : Vec3::splat(f32::INFINITY),
+            max : Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_center_extents(center: Vec3, extents: Vec3) -> Self {
+        Aabb::new(center - extents, center + extents)
+    }
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let mut result = Aabb::empty();
+        for p in points.iter() {
+            result.grow(*p);
+        }
+        result
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    //Half the size along each axis
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn grow(&mut self, p: Vec3) -> () {
+        self.min = self.min.min(p);
+        self.max = self.max.max(p);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        p.x() >= self.min.x() && p.x() <= self.max.x()
+            && p.y() >= self.min.y() && p.y() <= self.max.y()
+            && p.z() >= self.min.z() && p.z() <= self.max.z()
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x() <= other.max.x() && self.max.x() >= other.min.x()
+            && self.min.y() <= other.max.y() && self.max.y() >= other.min.y()
+            && self.min.z() <= other.max.z() && self.max.z() >= other.min.z()
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let closest = sphere.center.max(self.min).min(self.max);
+        (closest - sphere.center).length_squared() <= sphere.radius * sphere.radius
+    }
+
+    //Tightest box around the transformed box (Arvo's method). Only the
+    //affine part of m is used.
+    pub fn transform(&self, m: &Mat4) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = self.center();
+        let extents = self.extents();
+        let (cx, cy, cz) = (center.x(), center.y(), center.z());
+        let (ex, ey, ez) = (extents.x(), extents.y(), extents.z());
+        let row = |r: usize| (
+            m.at(r, 0) * cx + m.at(r, 1) * cy + m.at(r, 2) * cz + m.at(r, 3),
+            m.at(r, 0).abs() * ex + m.at(r, 1).abs() * ey + m.at(r, 2).abs() * ez,
+        );
+        let (c0, e0) = row(0);
+        let (c1, e1) = row(1);
+        let (c2, e2) = row(2);
+        Aabb::from_center_extents(Vec3::new(c0, c1, c2), Vec3::new(e0, e1, e2))
+    }
+}
+
+impl Sphere {
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        Sphere { center, radius }
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        (p - self.center).length_squared() <= self.radius * self.radius
+    }
+
+    pub fn intersects(&self, other: &Sphere) -> bool {
+        let r = self.radius + other.radius;
+        (other.center - self.center).length_squared() <= r * r
+    }
+}
+
+impl Plane {
+    //`normal` must be unit length
+    pub fn new(normal: Vec3, d: f32) -> Self {
+        Plane { normal, d }
+    }
+
+    pub fn from_point_normal(point: Vec3, normal: Vec3) -> Self {
+        let normal = normal.normalize();
+        Plane::new(normal, -normal.dot(point))
+    }
+
+    //Counter-clockwise points give a normal facing the viewer
+    pub fn from_points(a: Vec3, b: Vec3, c: Vec3) -> Self {
+        Plane::from_point_normal(a, (b - a).cross(c - a))
+    }
+
+    //Scales a plane given as raw (a, b, c, d) coefficients to a unit normal.
+    //Without a normal there is no plane, e.g. the far plane of an infinite
+    //projection, so every point is put on it and passes all the tests.
+    pub fn from_coefficients(a: f32, b: f32, c: f32, d: f32) -> Self {
+        let normal = Vec3::new(a, b, c);
+        let len = normal.length();
+        if len == 0.0 {
+            return Plane::new(Vec3::zero(), 0.0);
+        }
+        Plane::new(normal / len, d / len)
+    }
+
+    pub fn signed_distance(&self, p: Vec3) -> f32 {
+        self.normal.dot(p) + self.d
+    }
+}
+
+impl Ray {
+    //`direction` is normalized so t is a distance
+    pub fn new(origin: Vec3, direction: Vec3) -> Self {
+        Ray { origin, direction : direction.normalize() }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.direction * t
+    }
+
+    //Slab test. Returns the entry distance, or 0 when the origin is inside.
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let inv = Vec3::splat(1.0) / self.direction;
+        let t0 = (aabb.min - self.origin) * inv;
+        let t1 = (aabb.max - self.origin) * inv;
+        let near = t0.min(t1);
+        let far = t0.max(t1);
+        let t_enter = near.x().max(near.y()).max(near.z());
+        let t_exit = far.x().min(far.y()).min(far.z());
+        if t_exit < 0.0 || t_enter > t_exit {
+            return None;
+        }
+        Some(t_enter.max(0.0))
+    }
+
+    //Returns the nearest hit in front of the origin, or 0 when the origin
+    //is inside
+    pub fn intersect_sphere(&self, sphere: &Sphere) -> Option<f32> {
+        let oc = self.origin - sphere.center;
+        let b = oc.dot(self.direction);
+        let c = oc.length_squared() - sphere.radius * sphere.radius;
+        if c > 0.0 && b > 0.0 {
+            return None;
+        }
+        let disc = b * b - c;
+        if disc < 0.0 {
+            return None;
+        }
+        Some((-b - disc.sqrt()).max(0.0))
+    }
+
+    pub fn intersect_plane(&self, plane: &Plane) -> Option<f32> {
+        let denom = plane.normal.dot(self.direction);
+        if denom.abs() < f32::EPSILON {
+            return None;
+        }
+        let t = -plane.signed_distance(self.origin) / denom;
+        if t < 0.0 {
+            return None;
+        }
+        Some(t)
+    }
+
+    //Möller-Trumbore, hits both faces of the triangle
+    pub fn intersect_triangle(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+        let e1 = b - a;
+        let e2 = c - a;
+        let p = self.direction.cross(e2);
+        let det = e1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(e1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = e2.dot(q) * inv_det;
+        if t < 0.0 {
+            return None;
+        }
+        Some(t)
+    }
+}
+
+impl Frustum {
+    //Extracts the planes of a projection * view matrix using the OpenGL
+    //[-1, 1] depth range
+    pub fn from_matrix(m: &Mat4) -> Self {
+        let row = |r: usize| [m.at(r, 0), m.at(r, 1), m.at(r, 2), m.at(r, 3)];
+        let (r2, r3) = (row(2), row(3));
+        let mut f = Frustum::side_planes(m);
+        f.planes[4] = plane_from(r3, r2, 1.0);
+        f.planes[5] = plane_from(r3, r2, -1.0);
+        f
+    }
+
+    //Same as from_matrix for matrices with a [0, 1] depth range, which
+    //includes the reversed-Z projections. For those near and far swap.
+    pub fn from_matrix_zero_to_one(m: &Mat4) -> Self {
+        let row = |r: usize| [m.at(r, 0), m.at(r, 1), m.at(r, 2), m.at(r, 3)];
+        let (r2, r3) = (row(2), row(3));
+        let mut f = Frustum::side_planes(m);
+        f.planes[4] = Plane::from_coefficients(r2[0], r2[1], r2[2], r2[3]);
+        f.planes[5] = plane_from(r3, r2, -1.0);
+        f
+    }
+
+    fn side_planes(m: &Mat4) -> Self {
+        let row = |r: usize| [m.at(r, 0), m.at(r, 1), m.at(r, 2), m.at(r, 3)];
+        let (r0, r1, r3) = (row(0), row(1), row(3));
+        let unused = Plane::new(Vec3::zero(), 0.0);
+        Frustum {
+            planes : [
+                plane_from(r3, r0, 1.0),
+                plane_from(r3, r0, -1.0),
+                plane_from(r3, r1, 1.0),
+                plane_from(r3, r1, -1.0),
+                unused,
+                unused,
+            ],
+        }
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(p) >= 0.0)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    //Conservative: boxes near the frustum corners may be reported as
+    //intersecting even though they are outside
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let extents = aabb.extents();
+        self.planes.iter().all(|plane| {
+            let r = extents.dot(plane.normal.abs());
+            plane.signed_distance(center) >= -r
+        })
+    }
+}
+
+impl ApproxEq for Aabb {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.min.approx_eq(&other.min, epsilon) && self.max.approx_eq(&other.max, epsilon)
+    }
+}
+
+impl ApproxEq for Sphere {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.center.approx_eq(&other.center, epsilon)
+            && self.radius.approx_eq(&other.radius, epsilon)
+    }
+}
+
+impl ApproxEq for Plane {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.normal.approx_eq(&other.normal, epsilon) && self.d.approx_eq(&other.d, epsilon)
+    }
+}
+
+impl ApproxEq for Ray {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.origin.approx_eq(&other.origin, epsilon)
+            && self.direction.approx_eq(&other.direction, epsilon)
+    }
+}
+
+impl ApproxEq for Frustum {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.planes.iter().zip(other.planes.iter()).all(|(a, b)| a.approx_eq(b, epsilon))
+    }
+}
+
+//Plane with the coefficients a + sign * b
+fn plane_from(a: [f32;4], b: [f32;4], sign: f32) -> Plane {
+    Plane::from_coefficients(
+        a[0] + sign * b[0],
+        a[1] + sign * b[1],
+        a[2] + sign * b[2],
+        a[3] + sign * b[3],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Axis;
+
+    #[test]
+    fn ray_hits() {
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::unit_z());
+        let aabb = Aabb::new(Vec3::splat(-1.0), Vec3::splat(1.0));
+        assert_eq!(ray.intersect_aabb(&aabb), Some(4.0));
+        assert_eq!(ray.intersect_sphere(&Sphere::new(Vec3::zero(), 2.0)), Some(3.0));
+        let t = ray.intersect_triangle(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_eq!(t, Some(5.0));
+
+        let inside = Ray::new(Vec3::zero(), Vec3::unit_x());
+        assert_eq!(inside.intersect_aabb(&aabb), Some(0.0));
+    }
+
+    #[test]
+    fn ray_misses() {
+        let ray = Ray::new(Vec3::new(0.0, 3.0, 5.0), -Vec3::unit_z());
+        let aabb = Aabb::new(Vec3::splat(-1.0), Vec3::splat(1.0));
+        assert!(ray.intersect_aabb(&aabb).is_none());
+        assert!(ray.intersect_sphere(&Sphere::new(Vec3::zero(), 2.0)).is_none());
+        assert!(ray.intersect_triangle(
+            Vec3::new(-1.0, -1.0, 0.0),
+            Vec3::new(1.0, -1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ).is_none());
+
+        //Boxes behind the origin do not count
+        let away = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::unit_z());
+        assert!(away.intersect_aabb(&aabb).is_none());
+        assert!(away.intersect_sphere(&Sphere::new(Vec3::zero(), 2.0)).is_none());
+    }
+
+    #[test]
+    fn aabb_overlap() {
+        let a = Aabb::new(Vec3::zero(), Vec3::splat(2.0));
+        assert!(a.intersects(&Aabb::new(Vec3::splat(1.0), Vec3::splat(3.0))));
+        assert!(a.intersects(&Aabb::new(Vec3::splat(2.0), Vec3::splat(3.0))));
+        assert!(!a.intersects(&Aabb::new(Vec3::new(2.5, 0.0, 0.0), Vec3::splat(3.0))));
+    }
+
+    #[test]
+    fn aabb_transform() {
+        let aabb = Aabb::new(Vec3::new(-1.0, -2.0, -3.0), Vec3::new(1.0, 2.0, 3.0));
+        let mut m = Mat4::identity();
+        m.rotate(std::f32::consts::FRAC_PI_2, Axis::Z).translate(10.0, 0.0, 0.0);
+        let moved = aabb.transform(&m);
+        assert!((moved.min - Vec3::new(8.0, -1.0, -3.0)).length() < 1e-5);
+        assert!((moved.max - Vec3::new(12.0, 1.0, 3.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn frustum_culling() {
+        let proj = Mat4::perspective(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+        let frustum = Frustum::from_matrix(&proj);
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -10.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -200.0)));
+
+        assert!(frustum.intersects_sphere(&Sphere::new(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        assert!(frustum.intersects_sphere(&Sphere::new(Vec3::new(10.5, 0.0, -10.0), 1.0)));
+        assert!(!frustum.intersects_sphere(&Sphere::new(Vec3::new(20.0, 0.0, -10.0), 1.0)));
+
+        let aabb = |x: f32| Aabb::from_center_extents(Vec3::new(x, 0.0, -10.0), Vec3::splat(1.0));
+        assert!(frustum.intersects_aabb(&aabb(0.0)));
+        assert!(frustum.intersects_aabb(&aabb(10.5)));
+        assert!(!frustum.intersects_aabb(&aabb(20.0)));
+
+        let rev = Mat4::perspective_reversed_z(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 100.0);
+        let frustum = Frustum::from_matrix_zero_to_one(&rev);
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -10.0)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -0.5)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -200.0)));
+    }
+
+    #[test]
+    fn infinite_reversed_z_has_no_far_plane() {
+        let proj = Mat4::perspective_infinite_reversed_z(std::f32::consts::FRAC_PI_2, 1.0, 1.0);
+        let frustum = Frustum::from_matrix_zero_to_one(&proj);
+        assert!(frustum.planes.iter().all(|p| p.normal.length().is_finite() && p.d.is_finite()));
+
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -10.0)));
+        assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -1e6)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -0.5)));
+        assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, 10.0)));
+        assert!(!frustum.contains_point(Vec3::new(20.0, 0.0, -10.0)));
+
+        assert!(frustum.intersects_sphere(&Sphere::new(Vec3::new(0.0, 0.0, -1e4), 1.0)));
+        assert!(!frustum.intersects_sphere(&Sphere::new(Vec3::new(20.0, 0.0, -10.0), 1.0)));
+        let far = Aabb::from_center_extents(Vec3::new(0.0, 0.0, -5000.0), Vec3::splat(1.0));
+        assert!(frustum.intersects_aabb(&far));
+        let behind = Aabb::from_center_extents(Vec3::new(0.0, 0.0, 10.0), Vec3::splat(1.0));
+        assert!(!frustum.intersects_aabb(&behind));
+    }
+}
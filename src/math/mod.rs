@@ -1,22 +1,45 @@
 #![allow(dead_code)]
 
-use packed_simd::{
-    f32x4,
-    m32x4,
-    cptrx4,
-};
-
+use std::fmt;
 use std::ops::Mul;
 
-const MASK3T1F : m32x4 = m32x4::new(true, true, true, false);
-const MASK4T   : m32x4 = m32x4::new(true, true, true, true);
+#[macro_use]
+mod approx;
+mod simd;
+mod vec;
+mod quat;
+mod transform;
+mod geometry;
+pub mod batch;
+
+pub use vec::{Vec2, Vec3, Vec4};
+pub use quat::Quat;
+pub use transform::Transform;
+pub use geometry::{Aabb, Sphere, Plane, Ray, Frustum};
+pub use approx::{ApproxEq, DEFAULT_EPSILON};
+
+use simd::f32x4;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Axis {
     X,
     Y,
     Z,
+    //Must be unit length
+    Arbitrary(Vec3),
 }
 
+//Convention: matrices are stored row-major and act on column vectors,
+//v' = M * v. uniform_matrix4f uploads with transpose = TRUE, so GLSL sees
+//the same matrix. Translation lives in the last column (data[3], data[7],
+//data[11]).
+//
+//The builders (translate, stretch, rotate, rotate_radians) pre-multiply,
+//so every call is applied after what is already in the matrix:
+//
+//    Mat4::identity().stretch(2., 2., 2.).rotate(r, Axis::Y).translate(1., 0., 0.)
+//
+//is T * R * S: scale first, then rotate, then translate.
 #[derive(Clone)]
 pub struct Mat4 {
     data  : [f32;16],
@@ -46,6 +69,15 @@ impl Mat4 {
         }
     }
 
+    //`data` is left stale until the next flush
+    fn from_rows(rows: [f32x4;4]) -> Self {
+        Mat4 {
+            data  : [0.; 16],
+            rows  : rows,
+            dirty : true,
+        }
+    }
+
     pub fn identity() -> Self {
         Mat4 {
             data : [
@@ -68,49 +100,311 @@ impl Mat4 {
         if self.dirty {
             self.flush_rows();
         }
+        debug_assert!(self.is_consistent());
         &self.data[..]
     }
 
+    //`rows` is always current; `data` has to match it unless `dirty` is set
+    pub fn is_consistent(&self) -> bool {
+        self.dirty || self.data == self.to_array()
+    }
+
     pub fn flush_rows(&mut self) -> () {
-        self.rows[0].write_to_slice_aligned(&mut self.data[0..4]);
-        self.rows[1].write_to_slice_aligned(&mut self.data[4..8]);
-        self.rows[2].write_to_slice_aligned(&mut self.data[8..12]);
-        self.rows[3].write_to_slice_aligned(&mut self.data[12..]);
+        self.rows[0].write_to_slice_unaligned(&mut self.data[0..4]);
+        self.rows[1].write_to_slice_unaligned(&mut self.data[4..8]);
+        self.rows[2].write_to_slice_unaligned(&mut self.data[8..12]);
+        self.rows[3].write_to_slice_unaligned(&mut self.data[12..]);
         self.dirty = false;
     }
 
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self {
+        Mat4::from_data([
+            1.0, 0.0, 0.0, x,
+            0.0, 1.0, 0.0, y,
+            0.0, 0.0, 1.0, z,
+            0.0, 0.0, 0.0, 1.0,
+        ])
+    }
+
+    pub fn from_scale(x: f32, y: f32, z: f32) -> Self {
+        Mat4::from_data([
+            x,   0.0, 0.0, 0.0,
+            0.0, y,   0.0, 0.0,
+            0.0, 0.0, z,   0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ])
+    }
+
+    pub fn from_rotation(radians: f32, axis: Axis) -> Self {
+        match axis {
+            Axis::X => x_axis_rotation(radians),
+            Axis::Y => y_axis_rotation(radians),
+            Axis::Z => z_axis_rotation(radians),
+            Axis::Arbitrary(axis) => arbitrary_axis_rotation(radians, axis),
+        }
+    }
+
+    //M = T * M. Only the bottom row feeds into the translation column.
     pub fn translate(&mut self, x : f32, y: f32, z: f32) -> &mut Self {
-        //TODO: Incorrect
-        let vec = f32x4::new(x, y, z, 0.0); 
-        self.rows[3] += vec;
+        let bottom = self.rows[3];
+        self.rows[0] += bottom * x;
+        self.rows[1] += bottom * y;
+        self.rows[2] += bottom * z;
         self.dirty = true;
         self
     }
 
+    //M = S * M
     pub fn stretch(&mut self, x : f32, y : f32, z: f32) -> &mut Self {
-        //@Inefficient?
-        self.rows[0] = self.rows[0].replace(0, self.rows[0].extract(0) * x);
-        self.rows[1] = self.rows[1].replace(1, self.rows[1].extract(1) * y);
-        self.rows[2] = self.rows[2].replace(2, self.rows[2].extract(2) * z);
+        self.rows[0] *= x;
+        self.rows[1] *= y;
+        self.rows[2] *= z;
         self.dirty = true;
         self
     }
 
+    //M = R * M
+    pub fn rotate(&mut self, radians: f32, axis: Axis) -> &mut Self {
+        *self = Mat4::from_rotation(radians, axis) * self.clone();
+        self
+    }
+
     pub fn rotate_radians(self, radians: f32, axis: Axis) -> Self { 
-        match axis {
-            Axis::X => {
-                x_axis_rotation(radians) * self
-            },
-            Axis::Y => {
-                y_axis_rotation(radians) * self
-            },
-            Axis::Z => {
-                z_axis_rotation(radians) * self
-            },
+        Mat4::from_rotation(radians, axis) * self
+    }
+
+    //Right-handed perspective projection looking down -Z. Depth is mapped
+    //to the OpenGL clip range [-1, 1] with near at -1.
+    pub fn perspective(fov_y_radians: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let f = 1.0 / (fov_y_radians * 0.5).tan();
+        let nf = 1.0 / (near - far);
+        Mat4::from_data([
+            f / aspect, 0.0, 0.0,                0.0,
+            0.0,        f,   0.0,                0.0,
+            0.0,        0.0, (far + near) * nf,  2.0 * far * near * nf,
+            0.0,        0.0, -1.0,               0.0,
+        ])
+    }
+
+    //Reversed-Z perspective: near maps to depth 1 and far to depth 0.
+    //Needs a [0, 1] clip range (glClipControl(LOWER_LEFT, ZERO_TO_ONE)),
+    //a GREATER depth test and a depth clear value of 0.
+    pub fn perspective_reversed_z(fov_y_radians: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let f = 1.0 / (fov_y_radians * 0.5).tan();
+        let fn_ = 1.0 / (far - near);
+        Mat4::from_data([
+            f / aspect, 0.0, 0.0,         0.0,
+            0.0,        f,   0.0,         0.0,
+            0.0,        0.0, near * fn_,  far * near * fn_,
+            0.0,        0.0, -1.0,        0.0,
+        ])
+    }
+
+    //Reversed-Z perspective with the far plane at infinity
+    pub fn perspective_infinite_reversed_z(fov_y_radians: f32, aspect: f32, near: f32) -> Self {
+        let f = 1.0 / (fov_y_radians * 0.5).tan();
+        Mat4::from_data([
+            f / aspect, 0.0, 0.0,  0.0,
+            0.0,        f,   0.0,  0.0,
+            0.0,        0.0, 0.0,  near,
+            0.0,        0.0, -1.0, 0.0,
+        ])
+    }
+
+    //Depth is mapped to the OpenGL clip range [-1, 1] with near at -1
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let rl = 1.0 / (right - left);
+        let tb = 1.0 / (top - bottom);
+        let fn_ = 1.0 / (far - near);
+        Mat4::from_data([
+            2.0 * rl, 0.0,      0.0,        -(right + left) * rl,
+            0.0,      2.0 * tb, 0.0,        -(top + bottom) * tb,
+            0.0,      0.0,      -2.0 * fn_, -(far + near) * fn_,
+            0.0,      0.0,      0.0,        1.0,
+        ])
+    }
+
+    //Near maps to depth 1 and far to depth 0, see perspective_reversed_z
+    pub fn orthographic_reversed_z(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let rl = 1.0 / (right - left);
+        let tb = 1.0 / (top - bottom);
+        let fn_ = 1.0 / (far - near);
+        Mat4::from_data([
+            2.0 * rl, 0.0,      0.0,  -(right + left) * rl,
+            0.0,      2.0 * tb, 0.0,  -(top + bottom) * tb,
+            0.0,      0.0,      fn_,  far * fn_,
+            0.0,      0.0,      0.0,  1.0,
+        ])
+    }
+
+    //Right-handed view matrix for a camera at `eye` looking at `target`
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let f = (target - eye).normalize();
+        let s = f.cross(up).normalize();
+        let u = s.cross(f);
+        Mat4::from_data([
+            s.x(),  s.y(),  s.z(),  -s.dot(eye),
+            u.x(),  u.y(),  u.z(),  -u.dot(eye),
+            -f.x(), -f.y(), -f.z(), f.dot(eye),
+            0.0,    0.0,    0.0,    1.0,
+        ])
+    }
+
+    pub fn at(&self, row: usize, col: usize) -> f32 {
+        self.rows[row].extract(col)
+    }
+
+    //Row-major copy that does not need a flush
+    pub fn to_array(&self) -> [f32;16] {
+        let mut out = [0.;16];
+        for row in 0..4 {
+            self.rows[row].write_to_slice_unaligned(&mut out[row * 4..row * 4 + 4]);
+        }
+        out
+    }
+
+    pub fn transpose(&self) -> Self {
+        let m = self.to_array();
+        Mat4::from_data([
+            m[0], m[4], m[8],  m[12],
+            m[1], m[5], m[9],  m[13],
+            m[2], m[6], m[10], m[14],
+            m[3], m[7], m[11], m[15],
+        ])
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let m = self.to_array();
+        let c = cofactors(&m);
+        m[0] * c[0] + m[1] * c[4] + m[2] * c[8] + m[3] * c[12]
+    }
+
+    //Returns None for singular matrices
+    pub fn inverse(&self) -> Option<Self> {
+        let m = self.to_array();
+        let adj = cofactors(&m);
+        let det = m[0] * adj[0] + m[1] * adj[4] + m[2] * adj[8] + m[3] * adj[12];
+        if is_singular(det, m.chunks(4)) {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let mut out = [0.;16];
+        for (o, a) in out.iter_mut().zip(adj.iter()) {
+            *o = a * inv_det;
+        }
+        Some(Mat4::from_data(out))
+    }
+
+    //Fast path for matrices whose bottom row is (0, 0, 0, 1), i.e. any
+    //combination of translation, rotation and scale. Only the upper 3x3
+    //block has to be inverted.
+    pub fn inverse_affine(&self) -> Option<Self> {
+        let m = self.to_array();
+        let (a, b, c) = (m[0], m[1], m[2]);
+        let (d, e, f) = (m[4], m[5], m[6]);
+        let (g, h, i) = (m[8], m[9], m[10]);
+        let c00 = e * i - f * h;
+        let c01 = c * h - b * i;
+        let c02 = b * f - c * e;
+        let det = a * c00 + d * c01 + g * c02;
+        if is_singular(det, [&m[0..3], &m[4..7], &m[8..11]].iter().copied()) {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let r = [
+            c00 * inv_det,             c01 * inv_det,             c02 * inv_det,
+            (f * g - d * i) * inv_det, (a * i - c * g) * inv_det, (c * d - a * f) * inv_det,
+            (d * h - e * g) * inv_det, (b * g - a * h) * inv_det, (a * e - b * d) * inv_det,
+        ];
+        let (tx, ty, tz) = (m[3], m[7], m[11]);
+        Some(Mat4::from_data([
+            r[0], r[1], r[2], -(r[0] * tx + r[1] * ty + r[2] * tz),
+            r[3], r[4], r[5], -(r[3] * tx + r[4] * ty + r[5] * tz),
+            r[6], r[7], r[8], -(r[6] * tx + r[7] * ty + r[8] * tz),
+            0.0,  0.0,  0.0,  1.0,
+        ]))
+    }
+
+    //Splits an affine matrix into translation, rotation and scale such that
+    //M = T * R * S. A negative determinant is folded into the x scale.
+    pub fn decompose(&self) -> (Vec3, Quat, Vec3) {
+        let translation = Vec3::new(self.at(0, 3), self.at(1, 3), self.at(2, 3));
+        let col = |c: usize| Vec3::new(self.at(0, c), self.at(1, c), self.at(2, c));
+        let (x, y, z) = (col(0), col(1), col(2));
+        let mut scale = Vec3::new(x.length(), y.length(), z.length());
+        if x.cross(y).dot(z) < 0.0 {
+            scale.set_x(-scale.x());
         }
+        let safe = |v: Vec3, s: f32| if s == 0.0 { v } else { v / s };
+        let rotation = Quat::from_basis(
+            safe(x, scale.x()),
+            safe(y, scale.y()),
+            safe(z, scale.z()),
+        );
+        (translation, rotation, scale)
     }
 }
 
+//|det| can't exceed the product of the row lengths (Hadamard), so the
+//tolerance scales with the rows. A fixed epsilon would call a uniform
+//0.001 scale singular and accept near singular matrices with large entries.
+fn is_singular<'a>(det: f32, rows: impl Iterator<Item = &'a [f32]>) -> bool {
+    let bound: f32 = rows.map(|r| r.iter().map(|v| v * v).sum::<f32>().sqrt()).product();
+    det.abs() <= bound * f32::EPSILON * 4.0
+}
+
+//Transposed cofactor matrix (the adjugate) of a row-major 4x4 matrix.
+//The determinant is the first row of m dotted with the first column of it.
+fn cofactors(m: &[f32;16]) -> [f32;16] {
+    let mut inv = [0.;16];
+    inv[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15]
+        + m[9] * m[7] * m[14] + m[13] * m[6] * m[11] - m[13] * m[7] * m[10];
+    inv[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15]
+        - m[8] * m[7] * m[14] - m[12] * m[6] * m[11] + m[12] * m[7] * m[10];
+    inv[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15]
+        + m[8] * m[7] * m[13] + m[12] * m[5] * m[11] - m[12] * m[7] * m[9];
+    inv[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14]
+        - m[8] * m[6] * m[13] - m[12] * m[5] * m[10] + m[12] * m[6] * m[9];
+    inv[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15]
+        - m[9] * m[3] * m[14] - m[13] * m[2] * m[11] + m[13] * m[3] * m[10];
+    inv[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15]
+        + m[8] * m[3] * m[14] + m[12] * m[2] * m[11] - m[12] * m[3] * m[10];
+    inv[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15]
+        - m[8] * m[3] * m[13] - m[12] * m[1] * m[11] + m[12] * m[3] * m[9];
+    inv[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14]
+        + m[8] * m[2] * m[13] + m[12] * m[1] * m[10] - m[12] * m[2] * m[9];
+    inv[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15]
+        + m[5] * m[3] * m[14] + m[13] * m[2] * m[7] - m[13] * m[3] * m[6];
+    inv[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15]
+        - m[4] * m[3] * m[14] - m[12] * m[2] * m[7] + m[12] * m[3] * m[6];
+    inv[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15]
+        + m[4] * m[3] * m[13] + m[12] * m[1] * m[7] - m[12] * m[3] * m[5];
+    inv[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14]
+        - m[4] * m[2] * m[13] - m[12] * m[1] * m[6] + m[12] * m[2] * m[5];
+    inv[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11]
+        - m[5] * m[3] * m[10] - m[9] * m[2] * m[7] + m[9] * m[3] * m[6];
+    inv[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11]
+        + m[4] * m[3] * m[10] + m[8] * m[2] * m[7] - m[8] * m[3] * m[6];
+    inv[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11]
+        - m[4] * m[3] * m[9] - m[8] * m[1] * m[7] + m[8] * m[3] * m[5];
+    inv[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10]
+        + m[4] * m[2] * m[9] + m[8] * m[1] * m[6] - m[8] * m[2] * m[5];
+    inv
+}
+
+//Rodrigues' rotation formula
+fn arbitrary_axis_rotation(rads: f32, axis: Vec3) -> Mat4 {
+    let (x, y, z) = (axis.x(), axis.y(), axis.z());
+    let (s, c) = rads.sin_cos();
+    let t = 1.0 - c;
+    Mat4::from_data([
+        t * x * x + c,     t * x * y - s * z, t * x * z + s * y, 0.0,
+        t * x * y + s * z, t * y * y + c,     t * y * z - s * x, 0.0,
+        t * x * z - s * y, t * y * z + s * x, t * z * z + c,     0.0,
+        0.0,               0.0,               0.0,               1.0,
+    ])
+}
+
 const X_ROT_DAT: [f32;16] = [
     1.0, 0.0, 0.0, 0.0,
     0.0, 0.0, 0.0, 0.0,
@@ -159,49 +453,446 @@ fn z_axis_rotation(rads: f32) -> Mat4 {
 impl Mul for Mat4 {
     type Output = Self;
 
-    //Note: the right matrix must be flushed
-    //@Inefficient: Need strided loads!!!!!!!!!
+    //Each row of the product is a linear combination of the rows of the
+    //right matrix, so no columns have to be gathered
     fn mul(self, right: Self) -> Self {
-        let rcols = unsafe {
-            let default = f32x4::splat(0.);
-            let ptr0 = cptrx4::new(
-                &right.data[0],
-                &right.data[4],
-                &right.data[8],
-                &right.data[12],
-            );
-            let ptr1 = cptrx4::new(
-                &right.data[1],
-                &right.data[5],
-                &right.data[9],
-                &right.data[13],
-            );
-            let ptr2 = cptrx4::new(
-                &right.data[2],
-                &right.data[6],
-                &right.data[10],
-                &right.data[14],
-            );
-            let ptr3 = cptrx4::new(
-                &right.data[3],
-                &right.data[7],
-                &right.data[11],
-                &right.data[15],
-            );
-            [
-                ptr0.read(MASK4T, default),
-                ptr1.read(MASK4T, default),
-                ptr2.read(MASK4T, default),
-                ptr3.read(MASK4T, default),
-            ]
-        };
-        let mut mul_data = [0.;16];
+        let r = &right.rows;
+        let mut rows = [f32x4::splat(0.);4];
+        for (row, a) in rows.iter_mut().zip(self.rows.iter()) {
+            *row = r[0] * a.extract(0)
+                + r[1] * a.extract(1)
+                + r[2] * a.extract(2)
+                + r[3] * a.extract(3);
+        }
+        Mat4::from_rows(rows)
+    }
+}
+
+//Compares the current rows, so a dirty matrix equals its flushed self
+impl PartialEq for Mat4 {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_array() == other.to_array()
+    }
+}
+
+impl ApproxEq for Mat4 {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.to_array()[..].approx_eq(&other.to_array()[..], epsilon)
+    }
+}
+
+impl fmt::Debug for Mat4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.to_array();
+        f.debug_struct("Mat4")
+            .field("rows", &[&m[0..4], &m[4..8], &m[8..12], &m[12..16]])
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+//One row per line with right-aligned columns. Precision defaults to 4.
+impl fmt::Display for Mat4 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let precision = f.precision().unwrap_or(4);
+        let cells: Vec<String> = self.to_array().iter()
+            .map(|v| format!("{:.*}", precision, v))
+            .collect();
+        let width = cells.iter().map(|c| c.len()).max().unwrap_or(0);
         for row in 0..4 {
-            mul_data[row * 4]     = (self.rows[row] * rcols[0]).sum();
-            mul_data[row * 4 + 1] = (self.rows[row] * rcols[1]).sum();
-            mul_data[row * 4 + 2] = (self.rows[row] * rcols[2]).sum();
-            mul_data[row * 4 + 3] = (self.rows[row] * rcols[3]).sum();
+            write!(f, "[")?;
+            for col in 0..4 {
+                write!(f, " {:>width$}", cells[row * 4 + col], width = width)?;
+            }
+            write!(f, " ]")?;
+            if row < 3 {
+                writeln!(f)?;
+            }
         }
-        Mat4::from_data(mul_data)
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPS: f32 = 1e-5;
+
+    fn assert_mat_eq(a: &Mat4, expected: &[f32;16]) {
+        let a = a.to_array();
+        for i in 0..16 {
+            assert!(
+                (a[i] - expected[i]).abs() < EPS,
+                "element {} differs: {} != {}\n{:?}\n{:?}", i, a[i], expected[i], a, expected[..].to_vec(),
+            );
+        }
+    }
+
+    const GENERAL: [f32;16] = [
+        4.0, 7.0, 2.0, 3.0,
+        0.0, 5.0, 0.0, 1.0,
+        1.0, 0.0, 6.0, 2.0,
+        2.0, 1.0, 0.0, 3.0,
+    ];
+
+    const GENERAL_INV: [f32;16] = [
+        1.0 / 3.0,   -4.0 / 9.0,    -1.0 / 9.0,  -1.0 / 9.0,
+        1.0 / 21.0,  19.0 / 126.0,  -1.0 / 63.0, -11.0 / 126.0,
+        1.0 / 42.0,  -1.0 / 126.0,  10.0 / 63.0, -8.0 / 63.0,
+        -5.0 / 21.0, 31.0 / 126.0,  5.0 / 63.0,  55.0 / 126.0,
+    ];
+
+    //Scale (2, 3, 4) followed by a translation of (1, 2, 3)
+    const AFFINE: [f32;16] = [
+        2.0, 0.0, 0.0, 1.0,
+        0.0, 3.0, 0.0, 2.0,
+        0.0, 0.0, 4.0, 3.0,
+        0.0, 0.0, 0.0, 1.0,
+    ];
+
+    const AFFINE_INV: [f32;16] = [
+        0.5, 0.0,       0.0,  -0.5,
+        0.0, 1.0 / 3.0, 0.0,  -2.0 / 3.0,
+        0.0, 0.0,       0.25, -0.75,
+        0.0, 0.0,       0.0,  1.0,
+    ];
+
+    #[test]
+    fn determinant_fixtures() {
+        assert!((Mat4::from_data(GENERAL).determinant() - 252.0).abs() < EPS);
+        assert!((Mat4::from_data(AFFINE).determinant() - 24.0).abs() < EPS);
+        assert!((Mat4::identity().determinant() - 1.0).abs() < EPS);
+        assert_eq!(Mat4::new().determinant(), 0.0);
+    }
+
+    #[test]
+    fn transpose_fixture() {
+        let t = Mat4::from_data(GENERAL).transpose();
+        assert_mat_eq(&t, &[
+            4.0, 0.0, 1.0, 2.0,
+            7.0, 5.0, 0.0, 1.0,
+            2.0, 0.0, 6.0, 0.0,
+            3.0, 1.0, 2.0, 3.0,
+        ]);
+        assert_mat_eq(&t.transpose(), &GENERAL);
+    }
+
+    #[test]
+    fn inverse_fixtures() {
+        assert_mat_eq(&Mat4::from_data(GENERAL).inverse().unwrap(), &GENERAL_INV);
+        assert_mat_eq(&Mat4::from_data(AFFINE).inverse().unwrap(), &AFFINE_INV);
+        assert!(Mat4::new().inverse().is_none());
+    }
+
+    #[test]
+    fn inverse_times_self_is_identity() {
+        let m = Mat4::from_data(GENERAL);
+        let product = m.clone() * m.inverse().unwrap();
+        assert_mat_eq(&product, &Mat4::identity().to_array());
+    }
+
+    #[test]
+    fn affine_inverse_matches_general_inverse() {
+        assert_mat_eq(&Mat4::from_data(AFFINE).inverse_affine().unwrap(), &AFFINE_INV);
+
+        let rotated = Quat::from_axis_angle(Vec3::new(1.0, 2.0, 2.0) / 3.0, 0.9).to_mat4()
+            * Mat4::from_data(AFFINE);
+        let expected = rotated.inverse().unwrap().to_array();
+        assert_mat_eq(&rotated.inverse_affine().unwrap(), &expected);
+    }
+
+    #[test]
+    fn small_scales_still_invert() {
+        //det is 1e-9 here, far below f32::EPSILON
+        let tiny = Mat4::from_scale(0.001, 0.001, 0.001);
+        let expected = Mat4::from_scale(1000.0, 1000.0, 1000.0).to_array();
+        assert_approx_eq!(tiny.inverse().unwrap().to_array()[..], expected[..], 0.01);
+        assert_approx_eq!(tiny.inverse_affine().unwrap().to_array()[..], expected[..], 0.01);
+
+        let mut small = Mat4::from_translation(0.002, -0.001, 0.0005) * Mat4::from_scale(0.001, 0.002, 0.001);
+        let product = small.clone() * small.inverse_affine().unwrap();
+        assert_mat_eq(&product, &Mat4::identity().to_array());
+        assert!(small.get().iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn singular_matrices_are_rejected_at_any_scale() {
+        for &scale in [0.001, 1.0, 1000.0].iter() {
+            //The last row repeats the first, the z column is empty
+            let m = Mat4::from_data([
+                scale, 2.0 * scale, 0.0, 1.0,
+                0.0,   scale,       0.0, 2.0,
+                0.0,   0.0,         0.0, 3.0,
+                scale, 2.0 * scale, 0.0, 1.0,
+            ]);
+            assert!(m.inverse().is_none(), "scale {}", scale);
+            assert!(m.inverse_affine().is_none(), "scale {}", scale);
+        }
+        assert!(Mat4::from_scale(1e6, 1e6, 0.0).inverse_affine().is_none());
+    }
+
+    #[test]
+    fn decompose_fixtures() {
+        let (t, r, s) = Mat4::from_data(AFFINE).decompose();
+        assert_eq!(t.to_array(), [1.0, 2.0, 3.0]);
+        assert_eq!(s.to_array(), [2.0, 3.0, 4.0]);
+        assert!((r.dot(Quat::identity()).abs() - 1.0).abs() < EPS);
+
+        //90 degrees about Z, scale (2, 3, 4), translation (1, 2, 3)
+        let m = Mat4::from_data([
+            0.0, -3.0, 0.0, 1.0,
+            2.0, 0.0,  0.0, 2.0,
+            0.0, 0.0,  4.0, 3.0,
+            0.0, 0.0,  0.0, 1.0,
+        ]);
+        let (t, r, s) = m.decompose();
+        let expected_r = Quat::from_axis_angle(Vec3::unit_z(), std::f32::consts::FRAC_PI_2);
+        assert_eq!(t.to_array(), [1.0, 2.0, 3.0]);
+        assert_eq!(s.to_array(), [2.0, 3.0, 4.0]);
+        assert!((r.dot(expected_r).abs() - 1.0).abs() < EPS);
+    }
+
+    fn project(m: &Mat4, x: f32, y: f32, z: f32) -> Vec3 {
+        let clip = m * Vec4::new(x, y, z, 1.0);
+        clip.truncate() / clip.w()
+    }
+
+    #[test]
+    fn perspective_depth_ranges() {
+        let fov = std::f32::consts::FRAC_PI_2;
+        let gl = Mat4::perspective(fov, 2.0, 0.5, 100.0);
+        assert!((project(&gl, 0.0, 0.0, -0.5).z() + 1.0).abs() < EPS);
+        assert!((project(&gl, 0.0, 0.0, -100.0).z() - 1.0).abs() < EPS);
+        //fov of 90 degrees: the top of the frustum is at y == -z
+        assert!((project(&gl, 0.0, 3.0, -3.0).y() - 1.0).abs() < EPS);
+        assert!((project(&gl, 6.0, 0.0, -3.0).x() - 1.0).abs() < EPS);
+
+        let rev = Mat4::perspective_reversed_z(fov, 2.0, 0.5, 100.0);
+        assert!((project(&rev, 0.0, 0.0, -0.5).z() - 1.0).abs() < EPS);
+        assert!(project(&rev, 0.0, 0.0, -100.0).z().abs() < EPS);
+
+        let inf = Mat4::perspective_infinite_reversed_z(fov, 2.0, 0.5);
+        assert!((project(&inf, 0.0, 0.0, -0.5).z() - 1.0).abs() < EPS);
+        assert!(project(&inf, 0.0, 0.0, -1e7).z().abs() < EPS);
+    }
+
+    #[test]
+    fn orthographic_depth_ranges() {
+        let gl = Mat4::orthographic(-2.0, 2.0, -1.0, 1.0, 1.0, 11.0);
+        assert_eq!(project(&gl, 2.0, 1.0, -1.0).to_array(), [1.0, 1.0, -1.0]);
+        assert_eq!(project(&gl, -2.0, -1.0, -11.0).to_array(), [-1.0, -1.0, 1.0]);
+
+        let rev = Mat4::orthographic_reversed_z(-2.0, 2.0, -1.0, 1.0, 1.0, 11.0);
+        assert_eq!(project(&rev, 0.0, 0.0, -1.0).z(), 1.0);
+        assert_eq!(project(&rev, 0.0, 0.0, -11.0).z(), 0.0);
+    }
+
+    #[test]
+    fn look_at_fixture() {
+        let view = Mat4::look_at(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::unit_y(),
+        );
+        assert_mat_eq(&view, &[
+            1.0, 0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, -5.0,
+            0.0, 0.0, 0.0, 1.0,
+        ]);
+
+        let view = Mat4::look_at(
+            Vec3::new(3.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::unit_y(),
+        );
+        //The target ends up straight ahead on -Z
+        assert_eq!(project(&view, 0.0, 0.0, 0.0).to_array(), [0.0, 0.0, -3.0]);
+    }
+
+    fn transform(m: &Mat4, p: Vec3) -> Vec3 {
+        (m * p.extend(1.0)).truncate()
+    }
+
+    fn assert_vec_eq(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < EPS, "{:?} != {:?}", a.to_array(), b.to_array());
+    }
+
+    #[test]
+    fn translate_moves_points() {
+        let mut m = Mat4::identity();
+        m.translate(1.0, 2.0, 3.0);
+        assert_mat_eq(&m, &Mat4::from_translation(1.0, 2.0, 3.0).to_array());
+        assert_vec_eq(transform(&m, Vec3::new(1.0, 1.0, 1.0)), Vec3::new(2.0, 3.0, 4.0));
+        //Directions (w = 0) are unaffected
+        assert_eq!((&m * Vec4::new(1.0, 0.0, 0.0, 0.0)).to_array(), [1.0, 0.0, 0.0, 0.0]);
+        //The uploaded data has the translation in the last column
+        assert_eq!(m.get(), &Mat4::from_translation(1.0, 2.0, 3.0).to_array()[..]);
+    }
+
+    #[test]
+    fn stretch_then_translate() {
+        let mut m = Mat4::identity();
+        m.stretch(2.0, 3.0, 4.0).translate(1.0, 1.0, 1.0);
+        assert_vec_eq(transform(&m, Vec3::new(1.0, 1.0, 1.0)), Vec3::new(3.0, 4.0, 5.0));
+        let expected = Mat4::from_translation(1.0, 1.0, 1.0) * Mat4::from_scale(2.0, 3.0, 4.0);
+        assert_mat_eq(&m, &expected.to_array());
+    }
+
+    #[test]
+    fn translate_then_stretch() {
+        let mut m = Mat4::identity();
+        m.translate(1.0, 1.0, 1.0).stretch(2.0, 3.0, 4.0);
+        assert_vec_eq(transform(&m, Vec3::new(1.0, 1.0, 1.0)), Vec3::new(4.0, 6.0, 8.0));
+    }
+
+    #[test]
+    fn scale_rotate_translate_order() {
+        let quarter = std::f32::consts::FRAC_PI_2;
+        let mut m = Mat4::identity();
+        m.stretch(2.0, 2.0, 2.0).rotate(quarter, Axis::Z).translate(10.0, 0.0, 0.0);
+        //(1, 0, 0) -> (2, 0, 0) -> (0, 2, 0) -> (10, 2, 0)
+        assert_vec_eq(transform(&m, Vec3::unit_x()), Vec3::new(10.0, 2.0, 0.0));
+
+        let mut m = Mat4::identity();
+        m.translate(10.0, 0.0, 0.0).rotate(quarter, Axis::Z);
+        //(1, 0, 0) -> (11, 0, 0) -> (0, 11, 0)
+        assert_vec_eq(transform(&m, Vec3::unit_x()), Vec3::new(0.0, 11.0, 0.0));
+
+        let consumed = Mat4::from_translation(10.0, 0.0, 0.0).rotate_radians(quarter, Axis::Z);
+        assert_mat_eq(&consumed, &m.to_array());
+    }
+
+    #[test]
+    fn rotate_after_translate_flushes_operand() {
+        let mut m = Mat4::identity();
+        m.translate(0.0, 5.0, 0.0);
+        let rotated = m.rotate_radians(std::f32::consts::PI, Axis::X);
+        assert_vec_eq(transform(&rotated, Vec3::zero()), Vec3::new(0.0, -5.0, 0.0));
+    }
+
+    #[test]
+    fn arbitrary_axis_matches_fixed_axes() {
+        let r = 0.7;
+        for &(fixed, unit) in [
+            (Axis::X, Vec3::unit_x()),
+            (Axis::Y, Vec3::unit_y()),
+            (Axis::Z, Vec3::unit_z()),
+        ].iter() {
+            let expected = Mat4::from_rotation(r, fixed).to_array();
+            assert_mat_eq(&Mat4::from_rotation(r, Axis::Arbitrary(unit)), &expected);
+        }
+    }
+
+    #[test]
+    fn arbitrary_axis_rotation() {
+        //A third of a turn about (1, 1, 1) cycles the basis vectors
+        let axis = Vec3::new(1.0, 1.0, 1.0).normalize();
+        let m = Mat4::from_rotation(2.0 * std::f32::consts::PI / 3.0, Axis::Arbitrary(axis));
+        assert_vec_eq(transform(&m, Vec3::unit_x()), Vec3::unit_y());
+        assert_vec_eq(transform(&m, Vec3::unit_y()), Vec3::unit_z());
+        assert_vec_eq(transform(&m, axis), axis);
+
+        let q = Quat::from_axis_angle(axis, 0.3);
+        assert_mat_eq(&Mat4::from_rotation(0.3, Axis::Arbitrary(axis)), &q.to_mat4().to_array());
+    }
+
+    #[test]
+    fn data_and_rows_agree_after_every_mutation() {
+        fn check(m: &mut Mat4) {
+            assert!(m.is_consistent());
+            m.get();
+            assert!(!m.dirty);
+            assert!(m.is_consistent());
+            assert_eq!(m.data, m.to_array());
+        }
+
+        let mut m = Mat4::identity();
+        check(&mut m);
+        m.translate(1.0, 2.0, 3.0);
+        assert!(m.dirty);
+        check(&mut m);
+        m.stretch(2.0, 0.5, 4.0);
+        check(&mut m);
+        m.rotate(0.3, Axis::Y);
+        check(&mut m);
+        m.rotate(0.2, Axis::Arbitrary(Vec3::new(0.0, 0.6, 0.8)));
+        check(&mut m);
+        m.translate(-1.0, 0.0, 0.5).stretch(3.0, 3.0, 3.0);
+        check(&mut m);
+
+        let mut m = m.rotate_radians(0.1, Axis::X);
+        check(&mut m);
+        let mut product = Mat4::from_data(GENERAL) * m.clone();
+        check(&mut product);
+        let mut inverse = product.inverse().unwrap();
+        check(&mut inverse);
+        let mut transposed = inverse.transpose();
+        check(&mut transposed);
+    }
+
+    #[test]
+    fn equality_ignores_flush_state() {
+        let mut dirty = Mat4::identity();
+        dirty.translate(1.0, 2.0, 3.0);
+        let clean = Mat4::from_translation(1.0, 2.0, 3.0);
+        assert_eq!(dirty, clean);
+        assert_ne!(dirty, Mat4::identity());
+    }
+
+    #[test]
+    fn approx_eq_uses_epsilon() {
+        let a = Mat4::from_translation(1.0, 2.0, 3.0);
+        let b = Mat4::from_translation(1.0, 2.0, 3.0001);
+        assert_approx_eq!(a, b, 1e-3);
+        assert_approx_ne!(a, b, 1e-5);
+
+        assert_approx_eq!(Vec3::new(1.0, 2.0, 3.0), Vec3::new(1.0, 2.0, 3.0 + 1e-6));
+        assert_approx_ne!(Vec2::new(1.0, 2.0), Vec2::new(1.0, 2.1));
+        assert_approx_eq!(Vec4::splat(0.5), Vec4::new(0.5, 0.5, 0.5, 0.5));
+
+        let q = Quat::from_axis_angle(Vec3::unit_z(), 0.5);
+        assert_approx_ne!(q, -q);
+        assert!(q.approx_eq_rotation(&-q, DEFAULT_EPSILON));
+
+        assert_approx_eq!(Some(1.0f32), Some(1.0000001f32));
+        assert_approx_ne!(Some(1.0f32), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "left ~= right")]
+    fn assert_approx_eq_panics() {
+        assert_approx_eq!(Vec3::zero(), Vec3::unit_x());
+    }
+
+    #[test]
+    fn debug_and_display() {
+        assert_eq!(format!("{:?}", Vec3::new(1.0, 2.5, -3.0)), "Vec3(1.0, 2.5, -3.0)");
+        assert_eq!(format!("{}", Vec2::new(1.0, 2.5)), "(1, 2.5)");
+        assert_eq!(format!("{:.1}", Vec4::new(1.0, 2.0, 3.0, 4.0)), "(1.0, 2.0, 3.0, 4.0)");
+        assert_eq!(format!("{}", Quat::identity()), "(0, 0, 0; 1)");
+
+        let m = Mat4::from_translation(1.0, -20.0, 3.0);
+        assert_eq!(format!("{:.1}", m), concat!(
+            "[   1.0   0.0   0.0   1.0 ]\n",
+            "[   0.0   1.0   0.0 -20.0 ]\n",
+            "[   0.0   0.0   1.0   3.0 ]\n",
+            "[   0.0   0.0   0.0   1.0 ]",
+        ));
+        assert!(format!("{:?}", m).starts_with("Mat4 { rows: [[1.0, 0.0, 0.0, 1.0]"));
+    }
+
+    #[test]
+    fn decompose_mirrored() {
+        let m = Mat4::from_data([
+            -1.0, 0.0, 0.0, 0.0,
+            0.0,  1.0, 0.0, 0.0,
+            0.0,  0.0, 1.0, 0.0,
+            0.0,  0.0, 0.0, 1.0,
+        ]);
+        let (_, r, s) = m.decompose();
+        assert_eq!(s.to_array(), [-1.0, 1.0, 1.0]);
+        assert!((r.dot(Quat::identity()).abs() - 1.0).abs() < EPS);
     }
 }
@@ -0,0 +1,424 @@
+use super::simd::f32x4;
+
+use std::fmt;
+use std::ops::{Mul, MulAssign, Neg};
+
+use super::{ApproxEq, Mat4, Vec3};
+
+//Note: lanes are stored as (x, y, z, w) where w is the scalar part.
+//      Rotations follow the same right-handed, column-vector convention
+//      as Mat4, so q.to_mat4() * v == q * v.
+
+#[derive(Clone, Copy)]
+pub struct Quat {
+    lanes : f32x4,
+}
+
+impl Quat {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Quat { lanes : f32x4::new(x, y, z, w) }
+    }
+
+    pub fn identity() -> Self {
+        Quat::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    //`axis` must be unit length
+    pub fn from_axis_angle(axis: Vec3, radians: f32) -> Self {
+        let half = radians * 0.5;
+        let s = half.sin();
+        Quat::new(axis.x() * s, axis.y() * s, axis.z() * s, half.cos())
+    }
+
+    //Yaw about Y, pitch about X and roll about Z, applied roll first and
+    //yaw last, i.e. q = yaw * pitch * roll.
+    pub fn from_euler(yaw: f32, pitch: f32, roll: f32) -> Self {
+        Quat::from_axis_angle(Vec3::unit_y(), yaw)
+            * Quat::from_axis_angle(Vec3::unit_x(), pitch)
+            * Quat::from_axis_angle(Vec3::unit_z(), roll)
+    }
+
+    //Rotation that points the local -Z axis along `forward` and keeps the
+    //local +Y axis as close to `up` as possible. `forward` and `up` must
+    //not be parallel.
+    pub fn look_rotation(forward: Vec3, up: Vec3) -> Self {
+        let f = forward.normalize();
+        let r = f.cross(up).normalize();
+        let u = r.cross(f);
+        Quat::from_basis(r, u, -f)
+    }
+
+    //Builds the rotation whose matrix has the columns x, y and z.
+    //The columns must be orthonormal.
+    pub(super) fn from_basis(x: Vec3, y: Vec3, z: Vec3) -> Self {
+        let (m00, m01, m02) = (x.x(), y.x(), z.x());
+        let (m10, m11, m12) = (x.y(), y.y(), z.y());
+        let (m20, m21, m22) = (x.z(), y.z(), z.z());
+        let trace = m00 + m11 + m22;
+        let q = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            Quat::new(
+                (m21 - m12) / s,
+                (m02 - m20) / s,
+                (m10 - m01) / s,
+                0.25 * s,
+            )
+        } else if m00 > m11 && m00 > m22 {
+            let s = (1.0 + m00 - m11 - m22).sqrt() * 2.0;
+            Quat::new(
+                0.25 * s,
+                (m01 + m10) / s,
+                (m02 + m20) / s,
+                (m21 - m12) / s,
+            )
+        } else if m11 > m22 {
+            let s = (1.0 + m11 - m00 - m22).sqrt() * 2.0;
+            Quat::new(
+                (m01 + m10) / s,
+                0.25 * s,
+                (m12 + m21) / s,
+                (m02 - m20) / s,
+            )
+        } else {
+            let s = (1.0 + m22 - m00 - m11).sqrt() * 2.0;
+            Quat::new(
+                (m02 + m20) / s,
+                (m12 + m21) / s,
+                0.25 * s,
+                (m10 - m01) / s,
+            )
+        };
+        q.normalize()
+    }
+
+    //Reads the rotation out of the upper 3x3 block. The block must be a
+    //pure rotation; use Mat4::decompose first if it carries scale.
+    pub fn from_mat4(m: &Mat4) -> Self {
+        let col = |c: usize| Vec3::new(
+            m.rows[0].extract(c),
+            m.rows[1].extract(c),
+            m.rows[2].extract(c),
+        );
+        Quat::from_basis(col(0), col(1), col(2))
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        let (x, y, z, w) = (self.x(), self.y(), self.z(), self.w());
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (w * x, w * y, w * z);
+        Mat4::from_data([
+            1.0 - 2.0 * (yy + zz), 2.0 * (xy - wz),       2.0 * (xz + wy),       0.0,
+            2.0 * (xy + wz),       1.0 - 2.0 * (xx + zz), 2.0 * (yz - wx),       0.0,
+            2.0 * (xz - wy),       2.0 * (yz + wx),       1.0 - 2.0 * (xx + yy), 0.0,
+            0.0,                   0.0,                   0.0,                   1.0,
+        ])
+    }
+
+    pub fn x(&self) -> f32 { self.lanes.extract(0) }
+    pub fn y(&self) -> f32 { self.lanes.extract(1) }
+    pub fn z(&self) -> f32 { self.lanes.extract(2) }
+    pub fn w(&self) -> f32 { self.lanes.extract(3) }
+
+    pub fn to_array(&self) -> [f32;4] {
+        [self.x(), self.y(), self.z(), self.w()]
+    }
+
+    //q and -q are the same rotation, approx_eq only compares components
+    pub fn approx_eq_rotation(&self, other: &Quat, epsilon: f32) -> bool {
+        self.approx_eq(other, epsilon) || self.approx_eq(&-*other, epsilon)
+    }
+
+    pub fn vector(&self) -> Vec3 {
+        Vec3::new(self.x(), self.y(), self.z())
+    }
+
+    pub fn dot(&self, other: Quat) -> f32 {
+        (self.lanes * other.lanes).sum()
+    }
+
+    pub fn length(&self) -> f32 {
+        self.dot(*self).sqrt()
+    }
+
+    pub fn normalize(&self) -> Self {
+        let len = self.length();
+        if len == 0.0 {
+            return Quat::identity();
+        }
+        Quat { lanes : self.lanes / len }
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Quat::new(-self.x(), -self.y(), -self.z(), self.w())
+    }
+
+    pub fn inverse(&self) -> Self {
+        let len_sq = self.dot(*self);
+        let c = self.conjugate();
+        Quat { lanes : c.lanes / len_sq }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        //v' = v + 2w(q x v) + 2q x (q x v)
+        let q = self.vector();
+        let t = q.cross(v) * 2.0;
+        v + t * self.w() + q.cross(t)
+    }
+
+    //Normalized linear interpolation along the shortest arc. Cheaper than
+    //slerp but does not move at constant angular velocity.
+    pub fn nlerp(&self, other: Quat, t: f32) -> Self {
+        let other = if self.dot(other) < 0.0 { -other } else { other };
+        Quat { lanes : self.lanes + (other.lanes - self.lanes) * t }.normalize()
+    }
+
+    pub fn slerp(&self, other: Quat, t: f32) -> Self {
+        let mut cos_theta = self.dot(other);
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            -other
+        } else {
+            other
+        };
+        //Nearly identical rotations: sin(theta) is too small to divide by
+        if cos_theta > 0.9995 {
+            return self.nlerp(other, t);
+        }
+        let theta = cos_theta.acos();
+        let sin_theta = theta.sin();
+        let a = ((1.0 - t) * theta).sin() / sin_theta;
+        let b = (t * theta).sin() / sin_theta;
+        Quat { lanes : self.lanes * a + other.lanes * b }
+    }
+}
+
+//Hamilton product: (a * b) rotates by b first, then by a
+impl Mul for Quat {
+    type Output = Self;
+
+    fn mul(self, b: Self) -> Self {
+        let (ax, ay, az, aw) = (self.x(), self.y(), self.z(), self.w());
+        let (bx, by, bz, bw) = (b.x(), b.y(), b.z(), b.w());
+        Quat::new(
+            aw * bx + ax * bw + ay * bz - az * by,
+            aw * by - ax * bz + ay * bw + az * bx,
+            aw * bz + ax * by - ay * bx + az * bw,
+            aw * bw - ax * bx - ay * by - az * bz,
+        )
+    }
+}
+
+impl MulAssign for Quat {
+    fn mul_assign(&mut self, other: Self) -> () {
+        *self = *self * other;
+    }
+}
+
+impl Mul<Vec3> for Quat {
+    type Output = Vec3;
+
+    fn mul(self, v: Vec3) -> Vec3 {
+        self.rotate(v)
+    }
+}
+
+impl Neg for Quat {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Quat { lanes : -self.lanes }
+    }
+}
+
+impl From<Quat> for Mat4 {
+    fn from(q: Quat) -> Self {
+        q.to_mat4()
+    }
+}
+
+impl PartialEq for Quat {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_array() == other.to_array()
+    }
+}
+
+impl ApproxEq for Quat {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.to_array()[..].approx_eq(&other.to_array()[..], epsilon)
+    }
+}
+
+impl fmt::Debug for Quat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Quat")
+            .field("x", &self.x())
+            .field("y", &self.y())
+            .field("z", &self.z())
+            .field("w", &self.w())
+            .finish()
+    }
+}
+
+impl fmt::Display for Quat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        fmt::Display::fmt(&self.x(), f)?;
+        write!(f, ", ")?;
+        fmt::Display::fmt(&self.y(), f)?;
+        write!(f, ", ")?;
+        fmt::Display::fmt(&self.z(), f)?;
+        write!(f, "; ")?;
+        fmt::Display::fmt(&self.w(), f)?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{Axis, Vec4};
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    fn rotate_by_mat(m: &Mat4, v: Vec3) -> Vec3 {
+        (m * v.extend(0.0)).truncate()
+    }
+
+    #[test]
+    fn mat4_round_trip() {
+        //The 180 degree turns take each of the non-trace branches of from_basis
+        let rotations = [
+            Quat::identity(),
+            Quat::from_axis_angle(Vec3::new(1.0, 2.0, 3.0).normalize(), 0.7),
+            Quat::from_axis_angle(Vec3::unit_x(), PI),
+            Quat::from_axis_angle(Vec3::unit_y(), PI),
+            Quat::from_axis_angle(Vec3::unit_z(), PI),
+            Quat::new(0.6, 0.0, 0.0, 0.8),
+        ];
+        let v = Vec3::new(0.3, -1.0, 2.0);
+        for q in rotations.iter() {
+            let m = q.to_mat4();
+            assert_approx_eq!(rotate_by_mat(&m, v), *q * v);
+            assert!(Quat::from_mat4(&m).approx_eq_rotation(q, 1e-5), "{} came back as {}", q, Quat::from_mat4(&m));
+        }
+    }
+
+    #[test]
+    fn round_trip_through_the_other_hemisphere() {
+        //More than half a turn gives a negative w. -q is the same rotation
+        //and from_mat4 is free to return either of them.
+        for &radians in [4.0, 2.0 * PI - 0.5].iter() {
+            let q = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 1.0).normalize(), radians);
+            assert!(q.w() < 0.0);
+            assert_approx_eq!((-q).to_mat4(), q.to_mat4());
+
+            let back = Quat::from_mat4(&q.to_mat4());
+            assert!(back.approx_eq_rotation(&q, 1e-5), "{} came back as {}", q, back);
+            assert_approx_eq!(back.to_mat4(), q.to_mat4());
+        }
+        //A small rotation the long way around comes back as the short one
+        let q = Quat::from_axis_angle(Vec3::unit_x(), 2.0 * PI - 0.5);
+        assert_approx_eq!(Quat::from_mat4(&q.to_mat4()), -q);
+    }
+
+    #[test]
+    fn matches_the_axis_rotations_of_mat4() {
+        let v = Vec3::new(0.3, -1.0, 2.0);
+        for &(axis, unit) in [(Axis::X, Vec3::unit_x()), (Axis::Y, Vec3::unit_y()), (Axis::Z, Vec3::unit_z())].iter() {
+            let m = Mat4::from_rotation(0.5, axis);
+            assert_approx_eq!(rotate_by_mat(&m, v), Quat::from_axis_angle(unit, 0.5) * v);
+        }
+    }
+
+    #[test]
+    fn composition_applies_the_right_side_first() {
+        let yaw = Quat::from_axis_angle(Vec3::unit_y(), FRAC_PI_2);
+        let pitch = Quat::from_axis_angle(Vec3::unit_x(), FRAC_PI_2);
+        //-Z pitched up is +Y, yaw leaves that alone
+        assert_approx_eq!((yaw * pitch) * -Vec3::unit_z(), Vec3::unit_y());
+        //-Z yawed left is -X, pitching about X leaves that alone
+        assert_approx_eq!((pitch * yaw) * -Vec3::unit_z(), -Vec3::unit_x());
+
+        let q = Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0).normalize(), 1.2);
+        assert_approx_eq!(q * q.inverse(), Quat::identity());
+        assert_approx_eq!(q.conjugate(), q.inverse());
+    }
+
+    #[test]
+    fn euler_is_yaw_after_pitch_after_roll() {
+        let (yaw, pitch, roll) = (0.3, 0.2, 0.1);
+        let expected = Mat4::from_rotation(yaw, Axis::Y)
+            * Mat4::from_rotation(pitch, Axis::X)
+            * Mat4::from_rotation(roll, Axis::Z);
+        assert_approx_eq!(Quat::from_euler(yaw, pitch, roll).to_mat4(), expected);
+
+        //Roll about the view axis doesn't move where -Z points
+        let forward = Quat::from_euler(FRAC_PI_2, 0.0, 1.0) * -Vec3::unit_z();
+        assert_approx_eq!(forward, -Vec3::unit_x());
+    }
+
+    #[test]
+    fn look_rotation_points_minus_z_forward() {
+        let forward = Vec3::new(1.0, 0.0, -1.0).normalize();
+        let q = Quat::look_rotation(forward, Vec3::unit_y());
+        assert_approx_eq!(q * -Vec3::unit_z(), forward);
+        assert_approx_eq!(q * Vec3::unit_y(), Vec3::unit_y());
+
+        //Up gets straightened out when it isn't perpendicular to forward
+        let q = Quat::look_rotation(-Vec3::unit_y() - Vec3::unit_z(), Vec3::unit_y());
+        let up = q * Vec3::unit_y();
+        assert_approx_eq!(up.dot(q * -Vec3::unit_z()), 0.0);
+        assert!(up.y() > 0.0);
+        assert_approx_eq!(q.length(), 1.0);
+    }
+
+    #[test]
+    fn slerp_endpoints_and_midpoint() {
+        let a = Quat::from_axis_angle(Vec3::unit_z(), 0.2);
+        let b = Quat::from_axis_angle(Vec3::unit_z(), 1.4);
+        assert_approx_eq!(a.slerp(b, 0.0), a);
+        assert_approx_eq!(a.slerp(b, 1.0), b);
+        assert_approx_eq!(a.slerp(b, 0.5), Quat::from_axis_angle(Vec3::unit_z(), 0.8));
+        //Constant angular velocity
+        assert_approx_eq!(a.slerp(b, 0.25), Quat::from_axis_angle(Vec3::unit_z(), 0.5));
+    }
+
+    #[test]
+    fn slerp_and_nlerp_take_the_shortest_path() {
+        //-b is the same rotation as b, blending towards it must not swing
+        //the long way around
+        let a = Quat::identity();
+        let b = Quat::from_axis_angle(Vec3::unit_y(), 1.0);
+        let half = Quat::from_axis_angle(Vec3::unit_y(), 0.5);
+        assert!(a.slerp(-b, 0.5).approx_eq_rotation(&half, 1e-5));
+        assert!(a.nlerp(-b, 0.5).approx_eq_rotation(&half, 1e-5));
+
+        //Going from 170 to -170 degrees crosses 180, not 0
+        let from = Quat::from_axis_angle(Vec3::unit_z(), 170f32.to_radians());
+        let to = Quat::from_axis_angle(Vec3::unit_z(), -170f32.to_radians());
+        let mid = from.slerp(to, 0.5);
+        assert_approx_eq!(mid * Vec3::unit_x(), -Vec3::unit_x());
+    }
+
+    #[test]
+    fn nlerp_stays_normalized() {
+        let a = Quat::from_axis_angle(Vec3::unit_x(), 0.4);
+        let b = Quat::from_axis_angle(Vec3::unit_y(), 1.1);
+        assert_approx_eq!(a.nlerp(b, 0.0), a);
+        assert_approx_eq!(a.nlerp(b, 1.0), b);
+        for i in 0..=10 {
+            assert_approx_eq!(a.nlerp(b, i as f32 / 10.0).length(), 1.0);
+        }
+        //Symmetric rotations meet in the middle
+        let mid = Quat::from_axis_angle(Vec3::unit_z(), -0.6).nlerp(Quat::from_axis_angle(Vec3::unit_z(), 0.6), 0.5);
+        assert_approx_eq!(mid, Quat::identity());
+    }
+
+    #[test]
+    fn normalize_falls_back_to_identity() {
+        assert_eq!(Quat::new(0.0, 0.0, 0.0, 0.0).normalize(), Quat::identity());
+        assert_approx_eq!(Quat::new(0.0, 0.0, 0.0, 2.0).normalize(), Quat::identity());
+        let v = Vec4::new(1.0, 2.0, 3.0, 4.0).normalize();
+        assert_approx_eq!(Quat::new(1.0, 2.0, 3.0, 4.0).normalize().to_array()[..], v.to_array()[..]);
+    }
+}
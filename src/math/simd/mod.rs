@@ -0,0 +1,93 @@
+//Small 4-lane float abstraction the math types are written against.
+//
+//The packed backend wraps packed_simd and needs a nightly toolchain. The
+//scalar backend is plain [f32;4] and builds on stable. It is used when the
+//`simd` feature is off or the `scalar-math` feature is on.
+//
+//Both backends must produce bit-identical results, so nothing in here may
+//rely on lane-order-dependent hardware behaviour: horizontal sums are
+//always evaluated as (x + y) + (z + w) and there are no fused ops.
+
+#![allow(non_camel_case_types)]
+
+pub mod scalar;
+#[cfg(feature = "simd")]
+pub mod packed;
+
+#[cfg(all(feature = "simd", not(feature = "scalar-math")))]
+pub use self::packed::f32x4;
+#[cfg(any(not(feature = "simd"), feature = "scalar-math"))]
+pub use self::scalar::f32x4;
+
+#[cfg(all(test, feature = "simd"))]
+mod tests {
+    use super::{packed, scalar};
+
+    const INPUTS: [[f32;4];6] = [
+        [1.0, 2.0, 3.0, 4.0],
+        [0.1, 0.2, 0.3, 0.4],
+        [-7.25, 1e-7, 3.3333333, 1e7],
+        [0.0, -0.0, 1e-38, -1e-38],
+        [123.456, -654.321, 0.5, 17.0],
+        [std::f32::consts::PI, std::f32::consts::E, -1.0 / 3.0, std::f32::consts::SQRT_2],
+    ];
+
+    fn bits(a: [f32;4]) -> [u32;4] {
+        [a[0].to_bits(), a[1].to_bits(), a[2].to_bits(), a[3].to_bits()]
+    }
+
+    fn p(a: [f32;4]) -> packed::f32x4 {
+        packed::f32x4::from_slice_unaligned(&a)
+    }
+
+    fn s(a: [f32;4]) -> scalar::f32x4 {
+        scalar::f32x4::from_slice_unaligned(&a)
+    }
+
+    fn p_out(v: packed::f32x4) -> [u32;4] {
+        let mut out = [0.;4];
+        v.write_to_slice_unaligned(&mut out);
+        bits(out)
+    }
+
+    fn s_out(v: scalar::f32x4) -> [u32;4] {
+        let mut out = [0.;4];
+        v.write_to_slice_unaligned(&mut out);
+        bits(out)
+    }
+
+    #[test]
+    fn lane_ops_are_bit_identical() {
+        for a in INPUTS.iter() {
+            for b in INPUTS.iter() {
+                let (pa, pb, sa, sb) = (p(*a), p(*b), s(*a), s(*b));
+                assert_eq!(p_out(pa + pb), s_out(sa + sb));
+                assert_eq!(p_out(pa - pb), s_out(sa - sb));
+                assert_eq!(p_out(pa * pb), s_out(sa * sb));
+                assert_eq!(p_out(pa / pb), s_out(sa / sb));
+                assert_eq!(p_out(pa * b[1]), s_out(sa * b[1]));
+                assert_eq!(p_out(pa / b[2]), s_out(sa / b[2]));
+                assert_eq!(p_out(pa.min(pb)), s_out(sa.min(sb)));
+                assert_eq!(p_out(pa.max(pb)), s_out(sa.max(sb)));
+                assert_eq!((pa * pb).sum().to_bits(), (sa * sb).sum().to_bits());
+            }
+            let (pa, sa) = (p(*a), s(*a));
+            assert_eq!(p_out(-pa), s_out(-sa));
+            assert_eq!(p_out(pa.abs()), s_out(sa.abs()));
+            assert_eq!(pa.sum().to_bits(), sa.sum().to_bits());
+            for lane in 0..4 {
+                assert_eq!(pa.extract(lane).to_bits(), sa.extract(lane).to_bits());
+                assert_eq!(p_out(pa.replace(lane, 9.5)), s_out(sa.replace(lane, 9.5)));
+            }
+        }
+    }
+
+    #[test]
+    fn constructors_are_bit_identical() {
+        assert_eq!(p_out(packed::f32x4::splat(0.3)), s_out(scalar::f32x4::splat(0.3)));
+        assert_eq!(
+            p_out(packed::f32x4::new(1.0, -2.0, 0.1, 1e9)),
+            s_out(scalar::f32x4::new(1.0, -2.0, 0.1, 1e9)),
+        );
+    }
+}
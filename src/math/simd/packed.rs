@@ -0,0 +1,90 @@
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
+
+#[derive(Clone, Copy)]
+pub struct f32x4(packed_simd::f32x4);
+
+impl f32x4 {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        f32x4(packed_simd::f32x4::new(x, y, z, w))
+    }
+
+    pub fn splat(v: f32) -> Self {
+        f32x4(packed_simd::f32x4::splat(v))
+    }
+
+    pub fn from_slice_unaligned(slice: &[f32]) -> Self {
+        f32x4(packed_simd::f32x4::from_slice_unaligned(slice))
+    }
+
+    pub fn write_to_slice_unaligned(self, slice: &mut [f32]) -> () {
+        self.0.write_to_slice_unaligned(slice);
+    }
+
+    pub fn extract(self, idx: usize) -> f32 {
+        self.0.extract(idx)
+    }
+
+    pub fn replace(self, idx: usize, v: f32) -> Self {
+        f32x4(self.0.replace(idx, v))
+    }
+
+    //packed_simd leaves the reduction order unspecified, so it is spelled
+    //out to match the scalar backend bit for bit
+    pub fn sum(self) -> f32 {
+        (self.0.extract(0) + self.0.extract(1)) + (self.0.extract(2) + self.0.extract(3))
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        f32x4(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        f32x4(self.0.max(other.0))
+    }
+
+    pub fn abs(self) -> Self {
+        f32x4(self.0.abs())
+    }
+}
+
+macro_rules! impl_binop {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $sym:tt) => {
+        impl $Op for f32x4 {
+            type Output = Self;
+            fn $op(self, other: Self) -> Self {
+                f32x4(self.0 $sym other.0)
+            }
+        }
+
+        impl $Op<f32> for f32x4 {
+            type Output = Self;
+            fn $op(self, other: f32) -> Self {
+                f32x4(self.0 $sym packed_simd::f32x4::splat(other))
+            }
+        }
+
+        impl $OpAssign for f32x4 {
+            fn $op_assign(&mut self, other: Self) -> () {
+                *self = *self $sym other;
+            }
+        }
+
+        impl $OpAssign<f32> for f32x4 {
+            fn $op_assign(&mut self, other: f32) -> () {
+                *self = *self $sym other;
+            }
+        }
+    };
+}
+
+impl_binop!(Add, add, AddAssign, add_assign, +);
+impl_binop!(Sub, sub, SubAssign, sub_assign, -);
+impl_binop!(Mul, mul, MulAssign, mul_assign, *);
+impl_binop!(Div, div, DivAssign, div_assign, /);
+
+impl Neg for f32x4 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        f32x4(-self.0)
+    }
+}
@@ -0,0 +1,97 @@
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+pub struct f32x4([f32;4]);
+
+impl f32x4 {
+    pub const fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        f32x4([x, y, z, w])
+    }
+
+    pub const fn splat(v: f32) -> Self {
+        f32x4([v;4])
+    }
+
+    pub fn from_slice_unaligned(slice: &[f32]) -> Self {
+        f32x4([slice[0], slice[1], slice[2], slice[3]])
+    }
+
+    pub fn write_to_slice_unaligned(self, slice: &mut [f32]) -> () {
+        slice[..4].copy_from_slice(&self.0);
+    }
+
+    pub fn extract(self, idx: usize) -> f32 {
+        self.0[idx]
+    }
+
+    pub fn replace(mut self, idx: usize, v: f32) -> Self {
+        self.0[idx] = v;
+        self
+    }
+
+    pub fn sum(self) -> f32 {
+        (self.0[0] + self.0[1]) + (self.0[2] + self.0[3])
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        self.zip(other, f32::min)
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        self.zip(other, f32::max)
+    }
+
+    pub fn abs(self) -> Self {
+        let a = self.0;
+        f32x4([a[0].abs(), a[1].abs(), a[2].abs(), a[3].abs()])
+    }
+
+    fn zip(self, other: Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        let (a, b) = (self.0, other.0);
+        f32x4([f(a[0], b[0]), f(a[1], b[1]), f(a[2], b[2]), f(a[3], b[3])])
+    }
+}
+
+macro_rules! impl_binop {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $sym:tt) => {
+        impl $Op for f32x4 {
+            type Output = Self;
+            fn $op(self, other: Self) -> Self {
+                self.zip(other, |a, b| a $sym b)
+            }
+        }
+
+        impl $Op<f32> for f32x4 {
+            type Output = Self;
+            fn $op(self, other: f32) -> Self {
+                self $sym f32x4::splat(other)
+            }
+        }
+
+        impl $OpAssign for f32x4 {
+            fn $op_assign(&mut self, other: Self) -> () {
+                *self = *self $sym other;
+            }
+        }
+
+        impl $OpAssign<f32> for f32x4 {
+            fn $op_assign(&mut self, other: f32) -> () {
+                *self = *self $sym other;
+            }
+        }
+    };
+}
+
+impl_binop!(Add, add, AddAssign, add_assign, +);
+impl_binop!(Sub, sub, SubAssign, sub_assign, -);
+impl_binop!(Mul, mul, MulAssign, mul_assign, *);
+impl_binop!(Div, div, DivAssign, div_assign, /);
+
+impl Neg for f32x4 {
+    type Output = Self;
+    fn neg(self) -> Self {
+        let a = self.0;
+        f32x4([-a[0], -a[1], -a[2], -a[3]])
+    }
+}
@@ -0,0 +1,214 @@
+use super::{ApproxEq, Mat4, Quat, Vec3};
+
+//Translation, rotation and scale of an object. The world matrix is
+//T * R * S and is only rebuilt when it is asked for after a change.
+#[derive(Clone, Debug)]
+pub struct Transform {
+    translation : Vec3,
+    rotation    : Quat,
+    scale       : Vec3,
+    matrix      : Mat4,
+    dirty       : bool,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            translation : Vec3::zero(),
+            rotation    : Quat::identity(),
+            scale       : Vec3::splat(1.0),
+            matrix      : Mat4::identity(),
+            dirty       : false,
+        }
+    }
+
+    pub fn new(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        Transform {
+            translation,
+            rotation,
+            scale,
+            matrix : Mat4::identity(),
+            dirty  : true,
+        }
+    }
+
+    pub fn from_mat4(m: &Mat4) -> Self {
+        let (translation, rotation, scale) = m.decompose();
+        Transform::new(translation, rotation, scale)
+    }
+
+    pub fn translation(&self) -> Vec3 { self.translation }
+    pub fn rotation(&self) -> Quat { self.rotation }
+    pub fn scale(&self) -> Vec3 { self.scale }
+
+    pub fn set_translation(&mut self, translation: Vec3) -> &mut Self {
+        self.translation = translation;
+        self.dirty = true;
+        self
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quat) -> &mut Self {
+        self.rotation = rotation.normalize();
+        self.dirty = true;
+        self
+    }
+
+    pub fn set_scale(&mut self, scale: Vec3) -> &mut Self {
+        self.scale = scale;
+        self.dirty = true;
+        self
+    }
+
+    pub fn translate(&mut self, delta: Vec3) -> &mut Self {
+        self.translation += delta;
+        self.dirty = true;
+        self
+    }
+
+    //Rotates about the world axes, i.e. after the current rotation
+    pub fn rotate(&mut self, rotation: Quat) -> &mut Self {
+        self.set_rotation(rotation * self.rotation)
+    }
+
+    //Rotates about the object's own axes
+    pub fn rotate_local(&mut self, rotation: Quat) -> &mut Self {
+        self.set_rotation(self.rotation * rotation)
+    }
+
+    pub fn look_at(&mut self, target: Vec3, up: Vec3) -> &mut Self {
+        self.set_rotation(Quat::look_rotation(target - self.translation, up))
+    }
+
+    //Local -Z, the direction the object faces
+    pub fn forward(&self) -> Vec3 {
+        self.rotation * -Vec3::unit_z()
+    }
+
+    pub fn right(&self) -> Vec3 {
+        self.rotation * Vec3::unit_x()
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.rotation * Vec3::unit_y()
+    }
+
+    pub fn matrix(&mut self) -> &Mat4 {
+        if self.dirty {
+            self.rebuild();
+        }
+        &self.matrix
+    }
+
+    //Row-major data ready for uniform_matrix4f
+    pub fn get(&mut self) -> &[f32] {
+        if self.dirty {
+            self.rebuild();
+        }
+        self.matrix.get()
+    }
+
+    fn rebuild(&mut self) -> () {
+        let r = self.rotation.to_mat4();
+        let (sx, sy, sz) = (self.scale.x(), self.scale.y(), self.scale.z());
+        let t = self.translation;
+        self.matrix = Mat4::from_data([
+            r.at(0, 0) * sx, r.at(0, 1) * sy, r.at(0, 2) * sz, t.x(),
+            r.at(1, 0) * sx, r.at(1, 1) * sy, r.at(1, 2) * sz, t.y(),
+            r.at(2, 0) * sx, r.at(2, 1) * sy, r.at(2, 2) * sz, t.z(),
+            0.0,             0.0,             0.0,             1.0,
+        ]);
+        self.dirty = false;
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+//Only translation, rotation and scale count, not the cached matrix
+impl PartialEq for Transform {
+    fn eq(&self, other: &Self) -> bool {
+        self.translation == other.translation
+            && self.rotation == other.rotation
+            && self.scale == other.scale
+    }
+}
+
+impl ApproxEq for Transform {
+    fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+        self.translation.approx_eq(&other.translation, epsilon)
+            && self.rotation.approx_eq_rotation(&other.rotation, epsilon)
+            && self.scale.approx_eq(&other.scale, epsilon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn transform_point(t: &mut Transform, p: Vec3) -> Vec3 {
+        (t.matrix() * p.extend(1.0)).truncate()
+    }
+
+    #[test]
+    fn matrix_is_rebuilt_only_after_a_change() {
+        let mut t = Transform::identity();
+        assert!(!t.dirty);
+        assert_eq!(t.matrix().to_array(), Mat4::identity().to_array());
+
+        t.set_translation(Vec3::new(1.0, 2.0, 3.0)).set_scale(Vec3::splat(2.0));
+        assert!(t.dirty);
+        //The cached matrix is stale until someone asks for it
+        assert_eq!(t.matrix.to_array(), Mat4::identity().to_array());
+        assert_approx_eq!(transform_point(&mut t, Vec3::splat(1.0)), Vec3::new(3.0, 4.0, 5.0));
+        assert!(!t.dirty);
+
+        t.translate(Vec3::unit_x());
+        assert!(t.dirty);
+        assert_eq!(t.get()[3], 2.0);
+        assert!(!t.dirty);
+    }
+
+    #[test]
+    fn matrix_is_translation_rotation_scale() {
+        let rotation = Quat::from_axis_angle(Vec3::new(1.0, 2.0, 2.0) / 3.0, 0.8);
+        let mut t = Transform::new(Vec3::new(1.0, -2.0, 0.5), rotation, Vec3::new(2.0, 3.0, 4.0));
+        let expected = Mat4::from_translation(1.0, -2.0, 0.5) * rotation.to_mat4() * Mat4::from_scale(2.0, 3.0, 4.0);
+        assert_approx_eq!(*t.matrix(), expected);
+        assert_approx_eq!(Transform::from_mat4(&expected), t);
+    }
+
+    #[test]
+    fn look_at_faces_the_target() {
+        let mut t = Transform::identity();
+        t.set_translation(Vec3::new(0.0, 0.0, 5.0));
+        t.look_at(Vec3::new(5.0, 0.0, 5.0), Vec3::unit_y());
+        assert_approx_eq!(t.forward(), Vec3::unit_x());
+        assert_approx_eq!(t.up(), Vec3::unit_y());
+        assert_approx_eq!(t.right(), Vec3::unit_z());
+
+        //A point straight ahead in local space lands on the target
+        assert_approx_eq!(transform_point(&mut t, Vec3::new(0.0, 0.0, -5.0)), Vec3::new(5.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn rotate_is_about_world_axes_and_rotate_local_about_own_axes() {
+        let yaw = Quat::from_axis_angle(Vec3::unit_y(), FRAC_PI_2);
+        let pitch = Quat::from_axis_angle(Vec3::unit_x(), FRAC_PI_2);
+
+        //Yawed to face -X, pitching about the world X axis spins it in place
+        let mut world = Transform::identity();
+        world.rotate(yaw).rotate(pitch);
+        assert_approx_eq!(world.forward(), -Vec3::unit_x());
+        assert_approx_eq!(world.up(), Vec3::unit_z());
+
+        //Pitching about its own right axis (now -Z) tips the nose up
+        let mut local = Transform::identity();
+        local.rotate(yaw).rotate_local(pitch);
+        assert_approx_eq!(local.forward(), Vec3::unit_y());
+        assert_approx_eq!(local.up(), Vec3::unit_x());
+    }
+}
@@ -0,0 +1,506 @@
+use super::simd::f32x4;
+
+use std::ops::{
+    Add,
+    AddAssign,
+    Sub,
+    SubAssign,
+    Mul,
+    MulAssign,
+    Div,
+    DivAssign,
+    Neg,
+};
+
+use std::fmt;
+
+use super::{ApproxEq, Mat4};
+
+//Note: every vector lives in a full f32x4. Lanes past the vector's
+//      dimension are kept at 0.0 so that dot products can just sum
+//      all four lanes.
+
+#[derive(Clone, Copy)]
+pub struct Vec2 {
+    lanes : f32x4,
+}
+
+#[derive(Clone, Copy)]
+pub struct Vec3 {
+    lanes : f32x4,
+}
+
+#[derive(Clone, Copy)]
+pub struct Vec4 {
+    lanes : f32x4,
+}
+
+impl Vec2 {
+    pub fn new(x: f32, y: f32) -> Self {
+        Vec2 { lanes : f32x4::new(x, y, 0.0, 0.0) }
+    }
+
+    fn from_lanes(lanes: f32x4) -> Self {
+        Vec2 { lanes : lanes.replace(2, 0.0).replace(3, 0.0) }
+    }
+
+    pub fn splat(v: f32) -> Self {
+        Vec2::new(v, v)
+    }
+
+    pub fn x(&self) -> f32 { self.lanes.extract(0) }
+    pub fn y(&self) -> f32 { self.lanes.extract(1) }
+
+    pub fn set_x(&mut self, x: f32) -> () { self.lanes = self.lanes.replace(0, x); }
+    pub fn set_y(&mut self, y: f32) -> () { self.lanes = self.lanes.replace(1, y); }
+
+    //The z component of the 3D cross product of (x, y, 0) vectors
+    pub fn cross(&self, other: Vec2) -> f32 {
+        self.x() * other.y() - self.y() * other.x()
+    }
+
+    pub fn perp(&self) -> Self {
+        Vec2::new(-self.y(), self.x())
+    }
+
+    pub fn extend(&self, z: f32) -> Vec3 {
+        Vec3 { lanes : self.lanes.replace(2, z) }
+    }
+
+    pub fn to_array(&self) -> [f32;2] {
+        [self.x(), self.y()]
+    }
+}
+
+impl Vec3 {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        Vec3 { lanes : f32x4::new(x, y, z, 0.0) }
+    }
+
+    fn from_lanes(lanes: f32x4) -> Self {
+        Vec3 { lanes : lanes.replace(3, 0.0) }
+    }
+
+    pub fn splat(v: f32) -> Self {
+        Vec3::new(v, v, v)
+    }
+
+    pub fn unit_x() -> Self { Vec3::new(1.0, 0.0, 0.0) }
+    pub fn unit_y() -> Self { Vec3::new(0.0, 1.0, 0.0) }
+    pub fn unit_z() -> Self { Vec3::new(0.0, 0.0, 1.0) }
+
+    pub fn x(&self) -> f32 { self.lanes.extract(0) }
+    pub fn y(&self) -> f32 { self.lanes.extract(1) }
+    pub fn z(&self) -> f32 { self.lanes.extract(2) }
+
+    pub fn set_x(&mut self, x: f32) -> () { self.lanes = self.lanes.replace(0, x); }
+    pub fn set_y(&mut self, y: f32) -> () { self.lanes = self.lanes.replace(1, y); }
+    pub fn set_z(&mut self, z: f32) -> () { self.lanes = self.lanes.replace(2, z); }
+
+    pub fn cross(&self, other: Vec3) -> Self {
+        let (ax, ay, az) = (self.x(), self.y(), self.z());
+        let (bx, by, bz) = (other.x(), other.y(), other.z());
+        Vec3::new(
+            ay * bz - az * by,
+            az * bx - ax * bz,
+            ax * by - ay * bx,
+        )
+    }
+
+    pub fn extend(&self, w: f32) -> Vec4 {
+        Vec4 { lanes : self.lanes.replace(3, w) }
+    }
+
+    pub fn truncate(&self) -> Vec2 {
+        Vec2::from_lanes(self.lanes)
+    }
+
+    pub fn to_array(&self) -> [f32;3] {
+        [self.x(), self.y(), self.z()]
+    }
+}
+
+impl Vec4 {
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Vec4 { lanes : f32x4::new(x, y, z, w) }
+    }
+
+    fn from_lanes(lanes: f32x4) -> Self {
+        Vec4 { lanes }
+    }
+
+    pub fn splat(v: f32) -> Self {
+        Vec4 { lanes : f32x4::splat(v) }
+    }
+
+    pub fn x(&self) -> f32 { self.lanes.extract(0) }
+    pub fn y(&self) -> f32 { self.lanes.extract(1) }
+    pub fn z(&self) -> f32 { self.lanes.extract(2) }
+    pub fn w(&self) -> f32 { self.lanes.extract(3) }
+
+    pub fn set_x(&mut self, x: f32) -> () { self.lanes = self.lanes.replace(0, x); }
+    pub fn set_y(&mut self, y: f32) -> () { self.lanes = self.lanes.replace(1, y); }
+    pub fn set_z(&mut self, z: f32) -> () { self.lanes = self.lanes.replace(2, z); }
+    pub fn set_w(&mut self, w: f32) -> () { self.lanes = self.lanes.replace(3, w); }
+
+    pub fn truncate(&self) -> Vec3 {
+        Vec3::from_lanes(self.lanes)
+    }
+
+    pub fn to_array(&self) -> [f32;4] {
+        [self.x(), self.y(), self.z(), self.w()]
+    }
+
+    pub(super) fn lanes(&self) -> f32x4 {
+        self.lanes
+    }
+}
+
+macro_rules! impl_vec_common {
+    ($Vec:ident) => {
+        impl $Vec {
+            pub fn zero() -> Self {
+                $Vec::from_lanes(f32x4::splat(0.0))
+            }
+
+            pub fn dot(&self, other: $Vec) -> f32 {
+                (self.lanes * other.lanes).sum()
+            }
+
+            pub fn length_squared(&self) -> f32 {
+                self.dot(*self)
+            }
+
+            pub fn length(&self) -> f32 {
+                self.length_squared().sqrt()
+            }
+
+            pub fn distance(&self, other: $Vec) -> f32 {
+                (*self - other).length()
+            }
+
+            //Returns the zero vector unchanged instead of filling it with NaNs
+            pub fn normalize(&self) -> Self {
+                let len = self.length();
+                if len == 0.0 {
+                    return *self;
+                }
+                *self / len
+            }
+
+            pub fn lerp(&self, other: $Vec, t: f32) -> Self {
+                *self + (other - *self) * t
+            }
+
+            pub fn min(&self, other: $Vec) -> Self {
+                $Vec::from_lanes(self.lanes.min(other.lanes))
+            }
+
+            pub fn max(&self, other: $Vec) -> Self {
+                $Vec::from_lanes(self.lanes.max(other.lanes))
+            }
+
+            pub fn abs(&self) -> Self {
+                $Vec::from_lanes(self.lanes.abs())
+            }
+        }
+
+        impl Add for $Vec {
+            type Output = Self;
+            fn add(self, other: Self) -> Self {
+                $Vec { lanes : self.lanes + other.lanes }
+            }
+        }
+
+        impl Sub for $Vec {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                $Vec { lanes : self.lanes - other.lanes }
+            }
+        }
+
+        //Component-wise
+        impl Mul for $Vec {
+            type Output = Self;
+            fn mul(self, other: Self) -> Self {
+                $Vec { lanes : self.lanes * other.lanes }
+            }
+        }
+
+        impl Mul<f32> for $Vec {
+            type Output = Self;
+            fn mul(self, s: f32) -> Self {
+                $Vec { lanes : self.lanes * s }
+            }
+        }
+
+        impl Mul<$Vec> for f32 {
+            type Output = $Vec;
+            fn mul(self, v: $Vec) -> $Vec {
+                v * self
+            }
+        }
+
+        //Component-wise. The padding lanes would be 0/0, so they are reset.
+        impl Div for $Vec {
+            type Output = Self;
+            fn div(self, other: Self) -> Self {
+                $Vec::from_lanes(self.lanes / other.lanes)
+            }
+        }
+
+        impl Div<f32> for $Vec {
+            type Output = Self;
+            fn div(self, s: f32) -> Self {
+                $Vec::from_lanes(self.lanes / s)
+            }
+        }
+
+        impl Neg for $Vec {
+            type Output = Self;
+            fn neg(self) -> Self {
+                $Vec { lanes : -self.lanes }
+            }
+        }
+
+        impl AddAssign for $Vec {
+            fn add_assign(&mut self, other: Self) -> () {
+                *self = *self + other;
+            }
+        }
+
+        impl SubAssign for $Vec {
+            fn sub_assign(&mut self, other: Self) -> () {
+                *self = *self - other;
+            }
+        }
+
+        impl MulAssign<f32> for $Vec {
+            fn mul_assign(&mut self, s: f32) -> () {
+                *self = *self * s;
+            }
+        }
+
+        impl DivAssign<f32> for $Vec {
+            fn div_assign(&mut self, s: f32) -> () {
+                *self = *self / s;
+            }
+        }
+    };
+}
+
+//The padding lanes never take part in comparisons or formatting
+macro_rules! impl_vec_fmt_cmp {
+    ($Vec:ident) => {
+        impl PartialEq for $Vec {
+            fn eq(&self, other: &Self) -> bool {
+                self.to_array() == other.to_array()
+            }
+        }
+
+        impl ApproxEq for $Vec {
+            fn approx_eq(&self, other: &Self, epsilon: f32) -> bool {
+                self.to_array()[..].approx_eq(&other.to_array()[..], epsilon)
+            }
+        }
+
+        impl fmt::Debug for $Vec {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                let mut t = f.debug_tuple(stringify!($Vec));
+                for c in self.to_array().iter() {
+                    t.field(c);
+                }
+                t.finish()
+            }
+        }
+
+        impl fmt::Display for $Vec {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "(")?;
+                for (i, c) in self.to_array().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    fmt::Display::fmt(c, f)?;
+                }
+                write!(f, ")")
+            }
+        }
+    };
+}
+
+impl_vec_common!(Vec2);
+impl_vec_common!(Vec3);
+impl_vec_common!(Vec4);
+
+impl_vec_fmt_cmp!(Vec2);
+impl_vec_fmt_cmp!(Vec3);
+impl_vec_fmt_cmp!(Vec4);
+
+impl From<[f32;2]> for Vec2 {
+    fn from(a: [f32;2]) -> Self { Vec2::new(a[0], a[1]) }
+}
+
+impl From<[f32;3]> for Vec3 {
+    fn from(a: [f32;3]) -> Self { Vec3::new(a[0], a[1], a[2]) }
+}
+
+impl From<[f32;4]> for Vec4 {
+    fn from(a: [f32;4]) -> Self { Vec4::new(a[0], a[1], a[2], a[3]) }
+}
+
+impl From<f32x4> for Vec4 {
+    fn from(lanes: f32x4) -> Self { Vec4 { lanes } }
+}
+
+impl Mul<Vec4> for &Mat4 {
+    type Output = Vec4;
+
+    //Rows are always current, even while `data` is dirty
+    fn mul(self, v: Vec4) -> Vec4 {
+        let rows = &self.rows;
+        Vec4::new(
+            (rows[0] * v.lanes).sum(),
+            (rows[1] * v.lanes).sum(),
+            (rows[2] * v.lanes).sum(),
+            (rows[3] * v.lanes).sum(),
+        )
+    }
+}
+
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, v: Vec4) -> Vec4 {
+        &self * v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Axis;
+
+    #[test]
+    fn operators_work_per_component() {
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(4.0, -5.0, 6.0);
+        assert_eq!(a + b, Vec3::new(5.0, -3.0, 9.0));
+        assert_eq!(a - b, Vec3::new(-3.0, 7.0, -3.0));
+        assert_eq!(a * b, Vec3::new(4.0, -10.0, 18.0));
+        assert_eq!(b / a, Vec3::new(4.0, -2.5, 2.0));
+        assert_eq!(a * 2.0, 2.0 * a);
+        assert_eq!(a / 2.0, Vec3::new(0.5, 1.0, 1.5));
+        assert_eq!(-a, Vec3::new(-1.0, -2.0, -3.0));
+
+        let mut c = a;
+        c += b;
+        c -= a;
+        c *= 2.0;
+        c /= 4.0;
+        assert_eq!(c, b * 0.5);
+
+        assert_eq!(Vec2::new(1.0, 2.0) + Vec2::splat(1.0), Vec2::new(2.0, 3.0));
+        assert_eq!(Vec4::new(1.0, 2.0, 3.0, 4.0) * Vec4::splat(2.0), Vec4::new(2.0, 4.0, 6.0, 8.0));
+    }
+
+    #[test]
+    fn division_keeps_padding_lanes_zero() {
+        //0/0 in the unused lanes would poison dot and length with NaN
+        let v = Vec2::new(3.0, 4.0) / Vec2::new(1.0, 1.0);
+        assert_eq!(v.length(), 5.0);
+        assert_eq!(Vec3::new(2.0, 0.0, 0.0).dot(Vec3::splat(1.0) / Vec3::splat(1.0)), 2.0);
+    }
+
+    #[test]
+    fn dot_length_and_distance() {
+        assert_eq!(Vec2::new(1.0, 2.0).dot(Vec2::new(3.0, 4.0)), 11.0);
+        assert_eq!(Vec3::new(1.0, 2.0, 3.0).dot(Vec3::new(4.0, 5.0, 6.0)), 32.0);
+        assert_eq!(Vec4::new(1.0, 2.0, 3.0, 4.0).dot(Vec4::splat(1.0)), 10.0);
+        assert_eq!(Vec3::new(2.0, 3.0, 6.0).length(), 7.0);
+        assert_eq!(Vec3::new(2.0, 3.0, 6.0).length_squared(), 49.0);
+        assert_eq!(Vec2::new(1.0, 1.0).distance(Vec2::new(4.0, 5.0)), 5.0);
+    }
+
+    #[test]
+    fn cross_follows_the_right_hand_rule() {
+        assert_eq!(Vec3::unit_x().cross(Vec3::unit_y()), Vec3::unit_z());
+        assert_eq!(Vec3::unit_y().cross(Vec3::unit_z()), Vec3::unit_x());
+        assert_eq!(Vec3::unit_z().cross(Vec3::unit_x()), Vec3::unit_y());
+        assert_eq!(Vec3::unit_y().cross(Vec3::unit_x()), -Vec3::unit_z());
+
+        let a = Vec3::new(1.0, 2.0, 3.0);
+        let b = Vec3::new(-2.0, 0.5, 4.0);
+        assert_eq!(a.cross(b), Vec3::new(6.5, -10.0, 4.5));
+        assert_approx_eq!(a.cross(b).dot(a), 0.0);
+        assert_eq!(a.cross(a), Vec3::zero());
+
+        assert_eq!(Vec2::new(1.0, 0.0).cross(Vec2::new(0.0, 1.0)), 1.0);
+        assert_eq!(Vec2::new(2.0, 1.0).perp(), Vec2::new(-1.0, 2.0));
+    }
+
+    #[test]
+    fn normalize_leaves_zero_alone() {
+        assert_approx_eq!(Vec3::new(3.0, 0.0, 4.0).normalize(), Vec3::new(0.6, 0.0, 0.8));
+        assert_approx_eq!(Vec4::new(1.0, 1.0, 1.0, 1.0).normalize().length(), 1.0);
+        assert_approx_eq!(Vec2::new(0.0, -2.0).normalize(), Vec2::new(0.0, -1.0));
+
+        assert_eq!(Vec2::zero().normalize(), Vec2::zero());
+        assert_eq!(Vec3::zero().normalize(), Vec3::zero());
+        assert_eq!(Vec4::zero().normalize(), Vec4::zero());
+    }
+
+    #[test]
+    fn lerp_min_max_abs() {
+        let a = Vec3::new(0.0, 10.0, -4.0);
+        let b = Vec3::new(2.0, 0.0, 4.0);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.25), Vec3::new(0.5, 7.5, -2.0));
+        assert_eq!(a.min(b), Vec3::new(0.0, 0.0, -4.0));
+        assert_eq!(a.max(b), Vec3::new(2.0, 10.0, 4.0));
+        assert_eq!(a.abs(), Vec3::new(0.0, 10.0, 4.0));
+    }
+
+    #[test]
+    fn extend_and_truncate() {
+        let v = Vec2::new(1.0, 2.0).extend(3.0).extend(4.0);
+        assert_eq!(v, Vec4::new(1.0, 2.0, 3.0, 4.0));
+        assert_eq!(v.truncate(), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(v.truncate().truncate(), Vec2::new(1.0, 2.0));
+        //The dropped lanes don't leak back into later math
+        assert_eq!(v.truncate().length_squared(), 14.0);
+
+        let mut s = Vec3::zero();
+        s.set_y(5.0);
+        assert_eq!(s.to_array(), [0.0, 5.0, 0.0]);
+        assert_eq!(Vec4::from([1.0, 2.0, 3.0, 4.0]).w(), 4.0);
+    }
+
+    #[test]
+    fn mat4_times_vec4() {
+        let translate = Mat4::from_translation(1.0, 2.0, 3.0);
+        //Points move, directions don't
+        assert_eq!(&translate * Vec4::new(1.0, 1.0, 1.0, 1.0), Vec4::new(2.0, 3.0, 4.0, 1.0));
+        assert_eq!(&translate * Vec4::new(1.0, 1.0, 1.0, 0.0), Vec4::new(1.0, 1.0, 1.0, 0.0));
+
+        let general = Mat4::from_data([
+            1.0, 2.0, 3.0, 4.0,
+            5.0, 6.0, 7.0, 8.0,
+            9.0, 10.0, 11.0, 12.0,
+            13.0, 14.0, 15.0, 16.0,
+        ]);
+        let v = Vec4::new(1.0, -1.0, 2.0, 0.5);
+        assert_eq!(&general * v, Vec4::new(7.0, 17.0, 27.0, 37.0));
+        assert_eq!(general * v, Vec4::new(7.0, 17.0, 27.0, 37.0));
+
+        let rotate = Mat4::from_rotation(std::f32::consts::FRAC_PI_2, Axis::Z);
+        assert_approx_eq!(&rotate * Vec4::new(1.0, 0.0, 0.0, 0.0), Vec4::new(0.0, 1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn formats_only_the_used_components() {
+        assert_eq!(format!("{}", Vec3::new(1.0, 2.5, -3.0)), "(1, 2.5, -3)");
+        assert_eq!(format!("{:?}", Vec2::new(1.0, 2.0)), "Vec2(1.0, 2.0)");
+    }
+}
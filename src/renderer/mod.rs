@@ -5,17 +5,29 @@ mod shader;
 pub mod model;
 
 use model::Model;
-use super::math::Mat4;
+use super::math::{Mat4, Vec3};
 use std::path::Path;
 use gpu::{Attribute, ElementBufferObject, VertexBufferObject, VertexArrayObject};
 use shader::{Shader, ShaderProg, ShaderType::*};
 use std::sync::Arc;
 
+//Used for models and submeshes without a material
+const DEFAULT_DIFFUSE: [f32;4] = [0.8, 0.8, 0.8, 1.0];
+//Simplification error allowed on screen, as a fraction of its height.
+//About a pixel at 1080p.
+const DEFAULT_LOD_THRESHOLD: f32 = 0.001;
+//Levels generated per loaded mesh, each with about half the triangles
+const LOD_LEVELS: usize = 4;
+//Largest simplification error of a level, relative to the mesh's size
+const LOD_MAX_ERROR: f32 = 0.05;
+
 pub struct Renderer {
     wireframe     : bool,
     shaders       : Vec<Arc<ShaderProg>>,
     shader_idx    : i32,
-    matrix        : Mat4, 
+    projection    : Mat4,
+    view          : Mat4,
+    lod_threshold : f32,
 }
 
 impl Renderer {
@@ -24,6 +36,7 @@ impl Renderer {
        let (width, height) = window.get_size();
        unsafe {
            gl::Viewport(0, 0, width, height);
+           gl::Enable(gl::DEPTH_TEST);
            gl::DebugMessageCallback(gl_debug_callback, std::ptr::null());
        }
        Ok(
@@ -31,7 +44,18 @@ impl Renderer {
                wireframe     : false,
                shaders       : Vec::new(),
                shader_idx    : -1,
-               matrix        : Mat4::identity(),
+               projection    : Mat4::perspective(
+                   std::f32::consts::FRAC_PI_4,
+                   width as f32 / height as f32,
+                   0.1,
+                   100.0,
+               ),
+               view          : Mat4::look_at(
+                   Vec3::new(0.0, 0.0, 3.0),
+                   Vec3::new(0.0, 0.0, 0.0),
+                   Vec3::unit_y(),
+               ),
+               lod_threshold : DEFAULT_LOD_THRESHOLD,
            }
        )
     }
@@ -41,31 +65,63 @@ impl Renderer {
         if !bound_model.is_loaded() {
             return Err("Model is not loaded");
         }
+        let lod = model::simplify::select_lod(
+            bound_model.lods.iter().map(|lod| lod.error),
+            &bound_model.bounds,
+            bound_model.transform.matrix(),
+            &self.view,
+            &self.projection,
+            self.lod_threshold,
+        );
         if let Some(shader) = self.shaders.get(self.shader_idx as usize) {
             unsafe {
-                self.matrix = self.matrix.clone()
-                    .rotate_radians(0.0001, super::math::Axis::X);
-                //self.matrix.stretch(1.00001, 1.00001, 1.0);
-                //self.matrix.translate(1.000001, 0.0, 0.0);
-                shader.uniform_matrix4f("model", self.matrix.get()).unwrap();
-                shader.uniform_float_array("c", &[0.4]).unwrap();
-                let num_indices = if let Some(ref indices) = bound_model.indices {
-                    indices.num_elems
+                shader.uniform_matrix4f("model", bound_model.transform.get()).unwrap();
+                shader.uniform_matrix4f("view", self.view.get()).unwrap();
+                shader.uniform_matrix4f("projection", self.projection.get()).unwrap();
+                let model_indices = if let Some(ref indices) = bound_model.indices {
+                    indices
                 } else {
                     return Err("Something is wrong with model.is_loaded");
                 };
-                gl::DrawElements(
-                    gl::TRIANGLES, 
+                let (num_indices, submeshes) = match lod {
+                    Some(idx) => {
+                        let lod = &bound_model.lods[idx];
+                        lod.indices.bind();
+                        (lod.indices.num_elems, &lod.submeshes)
+                    }
+                    None => (model_indices.num_elems, &bound_model.submeshes),
+                };
+                if submeshes.is_empty() {
+                    shader.uniform_float_array("diffuse", &DEFAULT_DIFFUSE).unwrap();
                     //model.is_loaded guarantees this will not panic
-                    num_indices as i32,
-                    gl::UNSIGNED_INT, 
-                    std::ptr::null()
-                );
+                    draw_indices(0, num_indices);
+                }
+                for submesh in submeshes.iter() {
+                    let diffuse = match submesh.material.and_then(|m| bound_model.materials.get(m)) {
+                        Some(material) => [
+                            material.diffuse[0],
+                            material.diffuse[1],
+                            material.diffuse[2],
+                            material.dissolve,
+                        ],
+                        None => DEFAULT_DIFFUSE,
+                    };
+                    shader.uniform_float_array("diffuse", &diffuse).unwrap();
+                    draw_indices(submesh.start, submesh.count);
+                }
+                //The element buffer is part of the vertex array's state
+                if lod.is_some() {
+                    model_indices.bind();
+                }
             }
         }
         Ok(())
     }
 
+    pub fn set_lod_threshold(&mut self, threshold: f32) -> () {
+        self.lod_threshold = threshold;
+    }
+
     pub fn use_shader_idx(&mut self, shader_idx: i32) -> Result<(), &'static str> {
         if let Some(shader) = self.shaders.get(shader_idx as usize) {
             self.shader_idx = shader_idx;
@@ -78,6 +134,14 @@ impl Renderer {
         }
     }
 
+    pub fn set_projection(&mut self, projection: Mat4) -> () {
+        self.projection = projection;
+    }
+
+    pub fn set_view(&mut self, view: Mat4) -> () {
+        self.view = view;
+    }
+
     pub fn toggle_wireframe(&mut self) -> () {
         if self.wireframe {
             unsafe {
@@ -93,13 +157,30 @@ impl Renderer {
 
     pub unsafe fn clear(&self, color: [f32; 4]) -> () {
         gl::ClearColor(color[0], color[1], color[2], color[3]);
-        gl::Clear(gl::COLOR_BUFFER_BIT);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
     }
 
 }
 
+unsafe fn draw_indices(start: usize, count: usize) -> () {
+    gl::DrawElements(
+        gl::TRIANGLES,
+        count as i32,
+        gl::UNSIGNED_INT,
+        (start * std::mem::size_of::<gl::types::GLuint>()) as *const std::ffi::c_void
+    );
+}
+
 pub fn load_models_from_local_state(r: &mut Renderer, local: &mut super::localstate::LocalState) -> Result<(), String> {
-    let model = model::obj::load(Path::new("res/sample.obj")).unwrap();
+    let mut meshes = model::obj::load(Path::new("res/sample.obj")).unwrap();
+    for mesh in meshes.iter_mut() {
+        let report = model::optimize::optimize(mesh, 1e-6);
+        info!("Optimized {}: {}", mesh.name, report);
+    }
+    let models: Vec<Model> = meshes.iter().map(|mesh| {
+        let lods = model::simplify::lod_chain(mesh, LOD_LEVELS, LOD_MAX_ERROR);
+        model::upload_with_lods(mesh, &lods)
+    }).collect();
     //let model = Model::from_data_and_layout(
     //    &vec![
     //        0.5 as gl::types::GLfloat, 0.5, 1.0, 1.0, 0.0, 0.0,
@@ -127,7 +208,9 @@ pub fn load_models_from_local_state(r: &mut Renderer, local: &mut super::localst
     //    ]
     //);
 
-    local.add_model_moves(model);
+    for model in models {
+        local.add_model_moves(model);
+    }
 
     let vert_shader = Shader::from_source("./renderer/shaders/vert.glsl", Vertex)?;
     let frag_shader = Shader::from_source("./renderer/shaders/frag.glsl", Fragment)?;
@@ -0,0 +1,1187 @@
+use crate::renderer::model::Submesh;
+use crate::renderer::model::mesh::{Mesh, Semantic, VertexStream};
+use crate::renderer::model::mtl::Material;
+use crate::math::{Mat4, Quat, Transform, Vec3};
+use serde_json::Value;
+use std::io;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+
+type SemanticSet = fn(u32) -> Semantic;
+
+//Everything in a .gltf or .glb file. Indices between the vectors are the
+//same as in the file, e.g. Node::mesh indexes into `meshes`.
+pub struct GltfData {
+    pub meshes     : Vec<GltfMesh>,
+    pub nodes      : Vec<Node>,
+    //Top level nodes of the default scene
+    pub roots      : Vec<usize>,
+    pub materials  : Vec<PbrMaterial>,
+    pub images     : Vec<Image>,
+    pub skins      : Vec<Skin>,
+    pub animations : Vec<AnimationClip>,
+}
+
+pub struct GltfMesh {
+    pub name       : String,
+    pub primitives : Vec<Primitive>,
+}
+
+//The mesh has a position stream first, then TEXCOORD_0, NORMAL and COLOR_0
+//if present, then every other attribute sorted by name. Integer attributes
+//like JOINTS_0 are converted to floats, normalized ones to 0..1 or -1..1.
+//Strips and fans are turned into triangle lists. If the primitive has a
+//material the mesh carries it as its only Phong material.
+pub struct Primitive {
+    pub mesh     : Mesh,
+    //Index into GltfData::materials
+    pub material : Option<usize>,
+}
+
+pub struct Node {
+    pub name      : String,
+    pub parent    : Option<usize>,
+    pub children  : Vec<usize>,
+    //Relative to the parent
+    pub transform : Transform,
+    pub mesh      : Option<usize>,
+    pub skin      : Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImageSource {
+    //Resolved relative to the .gltf file
+    Path(PathBuf),
+    //data: uris and images stored in a buffer view
+    Embedded { mime_type: String, data: Vec<u8> },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub name   : String,
+    pub source : ImageSource,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureRef {
+    //Index into GltfData::images, None for textures without a source
+    pub image     : Option<usize>,
+    //Which TEXCOORD_n the texture is sampled with
+    pub tex_coord : u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+//pbrMetallicRoughness with the defaults from the spec
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial {
+    pub name                       : String,
+    pub base_color                 : [f32;4],
+    pub base_color_texture         : Option<TextureRef>,
+    pub metallic                   : f32,
+    pub roughness                  : f32,
+    pub metallic_roughness_texture : Option<TextureRef>,
+    pub normal_texture             : Option<TextureRef>,
+    pub occlusion_texture          : Option<TextureRef>,
+    pub emissive                   : [f32;3],
+    pub emissive_texture           : Option<TextureRef>,
+    pub alpha_mode                 : AlphaMode,
+    pub alpha_cutoff               : f32,
+    pub double_sided               : bool,
+}
+
+impl PbrMaterial {
+    //The closest Phong material, textures only carry over when they are files
+    pub fn to_material(&self, images: &[Image]) -> Material {
+        let path = |texture: &Option<TextureRef>| {
+            match texture.and_then(|t| t.image).and_then(|i| images.get(i)).map(|i| &i.source) {
+                Some(ImageSource::Path(path)) => Some(path.clone()),
+                _ => None,
+            }
+        };
+        let mut material = Material::new(&self.name);
+        material.diffuse = [self.base_color[0], self.base_color[1], self.base_color[2]];
+        material.dissolve = self.base_color[3];
+        material.emissive = self.emissive;
+        material.specular = [1.0 - self.roughness;3];
+        material.map_kd = path(&self.base_color_texture);
+        material.map_bump = path(&self.normal_texture);
+        material
+    }
+}
+
+pub struct Skin {
+    pub name                  : String,
+    //Node indices
+    pub joints                : Vec<usize>,
+    //One per joint, identity if the file has none
+    pub inverse_bind_matrices : Vec<Mat4>,
+    pub skeleton              : Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelProperty {
+    Translation,
+    Rotation,
+    Scale,
+    Weights,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    CubicSpline,
+}
+
+//Keyframes for one property of one node. `values` holds 3 floats per key
+//for translation and scale, an x y z w quaternion for rotation and one
+//float per morph target for weights. Cubic splines store an in-tangent,
+//the value and an out-tangent for every key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub node          : usize,
+    pub property      : ChannelProperty,
+    pub interpolation : Interpolation,
+    pub times         : Vec<f32>,
+    pub values        : Vec<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationClip {
+    pub name     : String,
+    pub channels : Vec<Channel>,
+    //Last keyframe time of any channel, in seconds
+    pub duration : f32,
+}
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io { path: PathBuf, error: io::Error },
+    Json(serde_json::Error),
+    //Broken .glb container
+    Glb(String),
+    //`pointer` is a JSON pointer to the offending value, e.g. /accessors/3/count
+    Invalid { pointer: String, reason: String },
+    Unsupported(String),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GltfError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            GltfError::Json(e) => write!(f, "invalid json: {}", e),
+            GltfError::Glb(reason) => write!(f, "invalid glb: {}", reason),
+            GltfError::Invalid { pointer, reason } => write!(f, "{}: {}", pointer, reason),
+            GltfError::Unsupported(what) => write!(f, "unsupported: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GltfError::Io { error, .. } => Some(error),
+            GltfError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for GltfError {
+    fn from(e: serde_json::Error) -> Self {
+        GltfError::Json(e)
+    }
+}
+
+fn invalid(pointer: String, reason: &str) -> GltfError {
+    GltfError::Invalid { pointer, reason: reason.to_string() }
+}
+
+//One mesh per primitive of every node with a mesh, named after the glTF
+//mesh, together with the node's world transform
+pub fn load(path: &Path) -> Result<Vec<(Mesh, Transform)>, GltfError> {
+    let data = parse_file(path)?;
+    let mut meshes = Vec::new();
+    for (node_idx, node) in data.nodes.iter().enumerate() {
+        let mesh = match node.mesh.and_then(|m| data.meshes.get(m)) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let world = Transform::from_mat4(&data.world_matrix(node_idx));
+        for primitive in mesh.primitives.iter() {
+            trace!("{}: {} vertices, {} indices", mesh.name, primitive.mesh.vertex_count(), primitive.mesh.indices.len());
+
+            meshes.push((primitive.mesh.clone(), world.clone()));
+        }
+    }
+    Ok(meshes)
+}
+
+pub fn parse_file(path: &Path) -> Result<GltfData, GltfError> {
+    let bytes = std::fs::read(path).map_err(|error| GltfError::Io { path: path.to_path_buf(), error })?;
+    parse(&bytes, path.parent().unwrap_or(Path::new("")))
+}
+
+//Takes .gltf json or a .glb container, external buffers and images are
+//looked up in `base_dir`
+pub fn parse(bytes: &[u8], base_dir: &Path) -> Result<GltfData, GltfError> {
+    let (json, bin) = if bytes.starts_with(&GLB_MAGIC.to_le_bytes()) {
+        split_glb(bytes)?
+    } else {
+        (bytes, None)
+    };
+    let json: Value = serde_json::from_slice(json)?;
+
+    let version = json.pointer("/asset/version").and_then(Value::as_str).unwrap_or("");
+    if !version.starts_with("2.") {
+        return Err(GltfError::Unsupported(format!("glTF version '{}'", version)));
+    }
+    //None of the extensions are implemented, a file that can't be read
+    //without one is refused rather than loaded wrong
+    if let Some(extension) = array(&json, "extensionsRequired").first() {
+        return Err(GltfError::Unsupported(format!("required extension {}", extension)));
+    }
+
+    let buffers = array(&json, "buffers").iter().enumerate().map(|(i, buffer)| {
+        let pointer = format!("/buffers/{}", i);
+        let data = match buffer.get("uri").and_then(Value::as_str) {
+            Some(uri) => read_uri(uri, base_dir, &pointer)?,
+            None => match bin {
+                Some(bin) if i == 0 => bin.to_vec(),
+                _ => return Err(invalid(pointer, "buffer has no uri and there is no glb binary chunk")),
+            },
+        };
+        let byte_length = get_usize(buffer, "byteLength", &pointer)?;
+        if data.len() < byte_length {
+            return Err(invalid(pointer, "buffer is shorter than its byteLength"));
+        }
+        Ok(data)
+    }).collect::<Result<Vec<Vec<u8>>, GltfError>>()?;
+
+    let doc = Document { json: &json, buffers };
+    let nodes = doc.nodes()?;
+    let roots = doc.roots(&nodes);
+    let materials = doc.materials()?;
+    let images = doc.images(base_dir)?;
+    Ok(
+        GltfData {
+            meshes     : doc.meshes(&materials, &images)?,
+            nodes,
+            roots,
+            materials,
+            images,
+            skins      : doc.skins()?,
+            animations : doc.animations()?,
+        }
+    )
+}
+
+impl GltfData {
+    //Local transforms of `node` and all its ancestors combined
+    pub fn world_matrix(&self, node: usize) -> Mat4 {
+        let mut world = self.nodes[node].transform.clone().matrix().clone();
+        let mut parent = self.nodes[node].parent;
+        while let Some(p) = parent {
+            world = self.nodes[p].transform.clone().matrix().clone() * world;
+            parent = self.nodes[p].parent;
+        }
+        world
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+}
+
+//Returns the json chunk and the binary chunk if there is one
+fn split_glb(bytes: &[u8]) -> Result<(&[u8], Option<&[u8]>), GltfError> {
+    let word = |at: usize| -> Result<u32, GltfError> {
+        bytes.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| GltfError::Glb(format!("file ends at byte {}", bytes.len())))
+    };
+    let version = word(4)?;
+    if version != 2 {
+        return Err(GltfError::Unsupported(format!("glb container version {}", version)));
+    }
+    let length = word(8)? as usize;
+    if length > bytes.len() {
+        return Err(GltfError::Glb(format!("header says {} bytes, file has {}", length, bytes.len())));
+    }
+
+    let mut chunks = Vec::new();
+    let mut at = 12;
+    while at + 8 <= length {
+        let chunk_len = word(at)? as usize;
+        let chunk_type = word(at + 4)?;
+        let data = bytes.get(at + 8..at + 8 + chunk_len)
+            .ok_or_else(|| GltfError::Glb(format!("chunk at byte {} runs past the end", at)))?;
+        chunks.push((chunk_type, data));
+        at += 8 + chunk_len;
+    }
+    match chunks.as_slice() {
+        [(GLB_JSON, json), (GLB_BIN, bin), ..] => Ok((json, Some(bin))),
+        [(GLB_JSON, json), ..] => Ok((json, None)),
+        _ => Err(GltfError::Glb("first chunk is not json".to_string())),
+    }
+}
+
+fn read_uri(uri: &str, base_dir: &Path, pointer: &str) -> Result<Vec<u8>, GltfError> {
+    if uri.starts_with("data:") {
+        let (_, data) = decode_data_uri(uri).ok_or_else(|| invalid(pointer.to_string(), "malformed data uri"))?;
+        return Ok(data);
+    }
+    let path = base_dir.join(percent_decode(uri));
+    std::fs::read(&path).map_err(|error| GltfError::Io { path, error })
+}
+
+//Returns the mime type and the decoded bytes of a base64 data: uri
+fn decode_data_uri(uri: &str) -> Option<(String, Vec<u8>)> {
+    let comma = uri.find(',')?;
+    let header = &uri["data:".len()..comma];
+    if !header.ends_with(";base64") {
+        return None;
+    }
+    let mime_type = header[..header.len() - ";base64".len()].to_string();
+    base64::decode(&uri[comma + 1..]).ok().map(|data| (mime_type, data))
+}
+
+//Relative uris may escape spaces and other characters as %XX
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_array).map_or(&[][..], |a| a.as_slice())
+}
+
+fn opt_usize(value: &Value, key: &str) -> Option<usize> {
+    value.get(key).and_then(Value::as_u64).map(|v| v as usize)
+}
+
+fn get_usize(value: &Value, key: &str, pointer: &str) -> Result<usize, GltfError> {
+    opt_usize(value, key).ok_or_else(|| invalid(format!("{}/{}", pointer, key), "expected an index or count"))
+}
+
+//An optional index into a list with `len` entries
+fn index(value: &Value, key: &str, len: usize, pointer: &str) -> Result<Option<usize>, GltfError> {
+    match value.get(key) {
+        None => Ok(None),
+        Some(idx) => idx.as_u64()
+            .map(|idx| idx as usize)
+            .filter(|idx| *idx < len)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("{}/{}", pointer, key), "index is out of range")),
+    }
+}
+
+//Accessors without a buffer view are zeros that sparse values are written
+//over. Nothing in the file bounds how many there are, so this does.
+const MAX_UNBACKED_COUNT: usize = 1 << 24;
+
+//Room for the floats an accessor reads, zeroed. Every element takes at
+//least a byte of its buffer view so the count can't be larger than the view.
+//`pointer` is the object holding the count.
+fn zeroed(count: usize, width: usize, view: Option<&[u8]>, sparse: bool, pointer: &str) -> Result<Vec<f32>, GltfError> {
+    let too_large = |reason: &str| invalid(format!("{}/count", pointer), reason);
+    match view {
+        Some(bytes) if count > bytes.len() => return Err(too_large("count is larger than the buffer view")),
+        None if !sparse => return Err(invalid(pointer.to_string(), "accessor has neither a buffer view nor sparse values")),
+        None if count > MAX_UNBACKED_COUNT => return Err(too_large("count is too large for an accessor without a buffer view")),
+        _ => (),
+    }
+    let len = count.checked_mul(width).ok_or_else(|| too_large("count is too large"))?;
+    let mut values = Vec::new();
+    values.try_reserve_exact(len).map_err(|_| too_large("count is too large"))?;
+    values.resize(len, 0.0);
+    Ok(values)
+}
+
+//`count` unsigned integers of `component_type` starting at `offset` into
+//the buffer view
+fn read_indices(
+    (bytes, stride): (&[u8], usize),
+    offset: usize,
+    component_type: usize,
+    count: usize,
+    pointer: &str,
+) -> Result<Vec<u32>, GltfError> {
+    let size = match component_type {
+        5121 => 1,
+        5123 => 2,
+        5125 => 4,
+        _ => return Err(invalid(format!("{}/componentType", pointer), "indices must be unsigned")),
+    };
+    let stride = if stride == 0 { size } else { stride };
+    (0..count).map(|i| {
+        let b = i.checked_mul(stride)
+            .and_then(|at| at.checked_add(offset))
+            .and_then(|at| bytes.get(at..at.checked_add(size)?))
+            .ok_or_else(|| invalid(pointer.to_string(), "accessor runs past the end of its buffer view"))?;
+        Ok(match size {
+            1 => b[0] as u32,
+            2 => u16::from_le_bytes([b[0], b[1]]) as u32,
+            _ => u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        })
+    }).collect()
+}
+
+fn get_f32(value: &Value, key: &str, default: f32) -> f32 {
+    value.get(key).and_then(Value::as_f64).map_or(default, |v| v as f32)
+}
+
+fn get_name(value: &Value) -> String {
+    value.get("name").and_then(Value::as_str).unwrap_or("").to_string()
+}
+
+//Reads a fixed size number array like "translation": [0, 1, 0]
+fn get_floats(value: &Value, key: &str, out: &mut [f32], pointer: &str) -> Result<(), GltfError> {
+    let values = match value.get(key) {
+        Some(values) => values,
+        None => return Ok(()),
+    };
+    let values = values.as_array()
+        .filter(|values| values.len() == out.len())
+        .ok_or_else(|| invalid(format!("{}/{}", pointer, key), &format!("expected {} numbers", out.len())))?;
+    for (o, v) in out.iter_mut().zip(values.iter()) {
+        *o = v.as_f64().ok_or_else(|| invalid(format!("{}/{}", pointer, key), "expected a number"))? as f32;
+    }
+    Ok(())
+}
+
+fn semantic(name: &str) -> Semantic {
+    match name {
+        "POSITION" => return Semantic::Position,
+        "NORMAL" => return Semantic::Normal,
+        "TANGENT" => return Semantic::Tangent,
+        _ => (),
+    }
+    let sets: [(&str, SemanticSet);4] = [
+        ("TEXCOORD_", Semantic::TexCoord),
+        ("COLOR_", Semantic::Color),
+        ("JOINTS_", Semantic::Joints),
+        ("WEIGHTS_", Semantic::Weights),
+    ];
+    for (prefix, make) in sets.iter() {
+        if let Some(Ok(set)) = name.strip_prefix(prefix).map(str::parse::<u32>) {
+            return make(set);
+        }
+    }
+    Semantic::Custom(name.to_string())
+}
+
+//An accessor read into floats, `width` components per element
+struct AccessorData {
+    values : Vec<f32>,
+    width  : usize,
+}
+
+struct Document<'a> {
+    json    : &'a Value,
+    buffers : Vec<Vec<u8>>,
+}
+
+impl<'a> Document<'a> {
+    fn get(&self, list: &str, idx: usize, pointer: &str) -> Result<&'a Value, GltfError> {
+        array(self.json, list).get(idx)
+            .ok_or_else(|| invalid(pointer.to_string(), &format!("{} {} does not exist", list, idx)))
+    }
+
+    //The bytes a buffer view covers and its stride, 0 if tightly packed
+    fn buffer_view(&self, idx: usize, pointer: &str) -> Result<(&[u8], usize), GltfError> {
+        let view = self.get("bufferViews", idx, pointer)?;
+        let view_pointer = format!("/bufferViews/{}", idx);
+        let buffer = get_usize(view, "buffer", &view_pointer)?;
+        let buffer = self.buffers.get(buffer)
+            .ok_or_else(|| invalid(format!("{}/buffer", view_pointer), "buffer does not exist"))?;
+        let offset = opt_usize(view, "byteOffset").unwrap_or(0);
+        let length = get_usize(view, "byteLength", &view_pointer)?;
+        let bytes = buffer.get(offset..offset + length)
+            .ok_or_else(|| invalid(view_pointer, "buffer view runs past the end of its buffer"))?;
+        Ok((bytes, opt_usize(view, "byteStride").unwrap_or(0)))
+    }
+
+    fn accessor(&self, idx: usize, pointer: &str) -> Result<AccessorData, GltfError> {
+        let accessor = self.get("accessors", idx, pointer)?;
+        let pointer = format!("/accessors/{}", idx);
+        let count = get_usize(accessor, "count", &pointer)?;
+        let component_type = get_usize(accessor, "componentType", &pointer)?;
+        let normalized = accessor.get("normalized").and_then(Value::as_bool).unwrap_or(false);
+        let width = match accessor.get("type").and_then(Value::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(invalid(format!("{}/type", pointer), "unknown accessor type")),
+        };
+
+        let view = match opt_usize(accessor, "bufferView") {
+            Some(view) => Some(self.buffer_view(view, &pointer)?),
+            None => None,
+        };
+        let has_sparse = accessor.get("sparse").is_some();
+        let mut values = zeroed(count, width, view.map(|(bytes, _)| bytes), has_sparse, &pointer)?;
+        if let Some(view) = view {
+            let offset = opt_usize(accessor, "byteOffset").unwrap_or(0);
+            read_components(view, offset, component_type, normalized, width, &mut values, &pointer)?;
+        }
+
+        //Sparse accessors overwrite some elements of the base values
+        if let Some(sparse) = accessor.get("sparse") {
+            let sparse_pointer = format!("{}/sparse", pointer);
+            let sparse_count = get_usize(sparse, "count", &sparse_pointer)?;
+            let indices = sparse.get("indices").ok_or_else(|| invalid(sparse_pointer.clone(), "missing indices"))?;
+            let indices_pointer = format!("{}/indices", sparse_pointer);
+            let indices_view = self.buffer_view(get_usize(indices, "bufferView", &indices_pointer)?, &indices_pointer)?;
+            let sparse_indices = read_indices(
+                indices_view,
+                opt_usize(indices, "byteOffset").unwrap_or(0),
+                get_usize(indices, "componentType", &indices_pointer)?,
+                sparse_count,
+                &indices_pointer,
+            )?;
+            let sparse_values = sparse.get("values").ok_or_else(|| invalid(sparse_pointer.clone(), "missing values"))?;
+            let values_pointer = format!("{}/values", sparse_pointer);
+            let values_view = self.buffer_view(get_usize(sparse_values, "bufferView", &values_pointer)?, &values_pointer)?;
+            let mut replacements = zeroed(sparse_count, width, Some(values_view.0), true, &sparse_pointer)?;
+            read_components(
+                values_view,
+                opt_usize(sparse_values, "byteOffset").unwrap_or(0),
+                component_type, normalized, width, &mut replacements, &values_pointer,
+            )?;
+            for (i, element) in sparse_indices.iter().enumerate() {
+                let element = *element as usize;
+                if element >= count {
+                    return Err(invalid(indices_pointer, "sparse index is out of range"));
+                }
+                values[element * width..(element + 1) * width]
+                    .copy_from_slice(&replacements[i * width..(i + 1) * width]);
+            }
+        }
+        Ok(AccessorData { values, width })
+    }
+
+    //Indices are read separately so values above 2^24 survive
+    fn indices(&self, idx: usize, pointer: &str) -> Result<Vec<u32>, GltfError> {
+        let accessor = self.get("accessors", idx, pointer)?;
+        let pointer = format!("/accessors/{}", idx);
+        let view = get_usize(accessor, "bufferView", &pointer)?;
+        read_indices(
+            self.buffer_view(view, &pointer)?,
+            opt_usize(accessor, "byteOffset").unwrap_or(0),
+            get_usize(accessor, "componentType", &pointer)?,
+            get_usize(accessor, "count", &pointer)?,
+            &pointer,
+        )
+    }
+
+    fn meshes(&self, materials: &[PbrMaterial], images: &[Image]) -> Result<Vec<GltfMesh>, GltfError> {
+        array(self.json, "meshes").iter().enumerate().map(|(mesh_idx, mesh)| {
+            let name = get_name(mesh);
+            let primitives = array(mesh, "primitives").iter().enumerate().map(|(prim_idx, primitive)| {
+                let pointer = format!("/meshes/{}/primitives/{}", mesh_idx, prim_idx);
+                let mut primitive = self.primitive(primitive, &pointer)?;
+                primitive.mesh.name = name.clone();
+                if let Some(material_idx) = primitive.material {
+                    let material = materials.get(material_idx)
+                        .ok_or_else(|| invalid(format!("{}/material", pointer), "material does not exist"))?;
+                    primitive.mesh.materials = vec![material.to_material(images)];
+                    primitive.mesh.submeshes = vec![Submesh {
+                        material_name : Some(material.name.clone()),
+                        material      : Some(0),
+                        start         : 0,
+                        count         : primitive.mesh.indices.len(),
+                    }];
+                }
+                Ok(primitive)
+            }).collect::<Result<Vec<Primitive>, GltfError>>()?;
+            Ok(GltfMesh { name, primitives })
+        }).collect()
+    }
+
+    fn primitive(&self, primitive: &Value, pointer: &str) -> Result<Primitive, GltfError> {
+        let attributes = primitive.get("attributes").and_then(Value::as_object)
+            .ok_or_else(|| invalid(format!("{}/attributes", pointer), "expected an object"))?;
+        if !attributes.contains_key("POSITION") {
+            return Err(invalid(format!("{}/attributes", pointer), "missing POSITION"));
+        }
+
+        let order = |name: &str| match name {
+            "POSITION" => 0,
+            "TEXCOORD_0" => 1,
+            "NORMAL" => 2,
+            "COLOR_0" => 3,
+            _ => 4,
+        };
+        let mut names: Vec<&String> = attributes.keys().collect();
+        names.sort_by(|a, b| (order(a), a).cmp(&(order(b), b)));
+
+        let mut streams = Vec::<AccessorData>::with_capacity(names.len());
+        for name in names.iter() {
+            let attr_pointer = format!("{}/attributes/{}", pointer, name);
+            let accessor = attributes[name.as_str()].as_u64()
+                .ok_or_else(|| invalid(attr_pointer.clone(), "expected an accessor index"))?;
+            streams.push(self.accessor(accessor as usize, &attr_pointer)?);
+        }
+        let vertex_cnt = streams[0].values.len() / streams[0].width;
+        if streams.iter().any(|s| s.values.len() / s.width != vertex_cnt) {
+            return Err(invalid(format!("{}/attributes", pointer), "attributes have different counts"));
+        }
+
+        if streams[0].width != 3 {
+            return Err(invalid(format!("{}/attributes/POSITION", pointer), "expected a VEC3 accessor"));
+        }
+
+        let indices = match opt_usize(primitive, "indices") {
+            Some(accessor) => self.indices(accessor, &format!("{}/indices", pointer))?,
+            None => (0..vertex_cnt as u32).collect(),
+        };
+        if indices.iter().any(|idx| *idx as usize >= vertex_cnt) {
+            return Err(invalid(format!("{}/indices", pointer), "index is out of range"));
+        }
+        let indices = match opt_usize(primitive, "mode").unwrap_or(4) {
+            4 => indices,
+            5 => (2..indices.len()).flat_map(|i| {
+                //Every other triangle of a strip is flipped to keep the winding
+                if i % 2 == 0 {
+                    vec![indices[i - 2], indices[i - 1], indices[i]]
+                } else {
+                    vec![indices[i - 1], indices[i - 2], indices[i]]
+                }
+            }).collect(),
+            6 => (2..indices.len()).flat_map(|i| vec![indices[0], indices[i - 1], indices[i]]).collect(),
+            mode => return Err(GltfError::Unsupported(format!("{}: primitive mode {}", pointer, mode))),
+        };
+
+        let mut streams = names.iter().zip(streams).map(|(name, s)| {
+            VertexStream { semantic: semantic(name), width: s.width, data: s.values }
+        });
+        let mut mesh = Mesh::new("", streams.next().unwrap().data, indices);
+        mesh.streams.extend(streams);
+        Ok(Primitive { mesh, material: opt_usize(primitive, "material") })
+    }
+
+    fn nodes(&self) -> Result<Vec<Node>, GltfError> {
+        let json_nodes = array(self.json, "nodes");
+        let mut nodes = json_nodes.iter().enumerate().map(|(i, node)| {
+            let pointer = format!("/nodes/{}", i);
+            let transform = if node.get("matrix").is_some() {
+                let mut columns = [0.0;16];
+                get_floats(node, "matrix", &mut columns, &pointer)?;
+                //glTF stores matrices column by column
+                Transform::from_mat4(&Mat4::from_data(columns).transpose())
+            } else {
+                let mut translation = [0.0;3];
+                let mut rotation = [0.0, 0.0, 0.0, 1.0];
+                let mut scale = [1.0;3];
+                get_floats(node, "translation", &mut translation, &pointer)?;
+                get_floats(node, "rotation", &mut rotation, &pointer)?;
+                get_floats(node, "scale", &mut scale, &pointer)?;
+                Transform::new(
+                    Vec3::from(translation),
+                    Quat::new(rotation[0], rotation[1], rotation[2], rotation[3]),
+                    Vec3::from(scale),
+                )
+            };
+            let children = array(node, "children").iter().map(|child| {
+                child.as_u64()
+                    .map(|c| c as usize)
+                    .filter(|c| *c < json_nodes.len())
+                    .ok_or_else(|| invalid(format!("{}/children", pointer), "expected a node index"))
+            }).collect::<Result<Vec<usize>, GltfError>>()?;
+            Ok(
+                Node {
+                    name     : get_name(node),
+                    parent   : None,
+                    children,
+                    transform,
+                    mesh     : index(node, "mesh", array(self.json, "meshes").len(), &pointer)?,
+                    skin     : index(node, "skin", array(self.json, "skins").len(), &pointer)?,
+                }
+            )
+        }).collect::<Result<Vec<Node>, GltfError>>()?;
+
+        for i in 0..nodes.len() {
+            for c in nodes[i].children.clone() {
+                if nodes[c].parent.is_some() || c == i {
+                    return Err(invalid(format!("/nodes/{}/children", i), "node has more than one parent"));
+                }
+                nodes[c].parent = Some(i);
+            }
+        }
+        //A parent chain that never ends means a cycle
+        for i in 0..nodes.len() {
+            let mut depth = 0;
+            let mut parent = nodes[i].parent;
+            while let Some(p) = parent {
+                depth += 1;
+                if depth > nodes.len() {
+                    return Err(invalid(format!("/nodes/{}", i), "node hierarchy has a cycle"));
+                }
+                parent = nodes[p].parent;
+            }
+        }
+        Ok(nodes)
+    }
+
+    //The default scene, or the first one, or every node without a parent
+    fn roots(&self, nodes: &[Node]) -> Vec<usize> {
+        let scene = opt_usize(self.json, "scene").unwrap_or(0);
+        match array(self.json, "scenes").get(scene) {
+            Some(scene) => array(scene, "nodes").iter()
+                .filter_map(Value::as_u64)
+                .map(|n| n as usize)
+                .filter(|n| *n < nodes.len())
+                .collect(),
+            None => (0..nodes.len()).filter(|n| nodes[*n].parent.is_none()).collect(),
+        }
+    }
+
+    fn materials(&self) -> Result<Vec<PbrMaterial>, GltfError> {
+        array(self.json, "materials").iter().enumerate().map(|(i, material)| {
+            let pointer = format!("/materials/{}", i);
+            let texture = |value: Option<&Value>| -> Option<TextureRef> {
+                let value = value?;
+                let texture = array(self.json, "textures").get(opt_usize(value, "index")?)?;
+                Some(TextureRef {
+                    image     : opt_usize(texture, "source"),
+                    tex_coord : opt_usize(value, "texCoord").unwrap_or(0) as u32,
+                })
+            };
+            let empty = Value::Null;
+            let pbr = material.get("pbrMetallicRoughness").unwrap_or(&empty);
+            let mut base_color = [1.0;4];
+            get_floats(pbr, "baseColorFactor", &mut base_color, &format!("{}/pbrMetallicRoughness", pointer))?;
+            let mut emissive = [0.0;3];
+            get_floats(material, "emissiveFactor", &mut emissive, &pointer)?;
+            let alpha_mode = match material.get("alphaMode").and_then(Value::as_str) {
+                None | Some("OPAQUE") => AlphaMode::Opaque,
+                Some("MASK") => AlphaMode::Mask,
+                Some("BLEND") => AlphaMode::Blend,
+                Some(_) => return Err(invalid(format!("{}/alphaMode", pointer), "unknown alpha mode")),
+            };
+            Ok(
+                PbrMaterial {
+                    name                       : get_name(material),
+                    base_color,
+                    base_color_texture         : texture(pbr.get("baseColorTexture")),
+                    metallic                   : get_f32(pbr, "metallicFactor", 1.0),
+                    roughness                  : get_f32(pbr, "roughnessFactor", 1.0),
+                    metallic_roughness_texture : texture(pbr.get("metallicRoughnessTexture")),
+                    normal_texture             : texture(material.get("normalTexture")),
+                    occlusion_texture          : texture(material.get("occlusionTexture")),
+                    emissive,
+                    emissive_texture           : texture(material.get("emissiveTexture")),
+                    alpha_mode,
+                    alpha_cutoff               : get_f32(material, "alphaCutoff", 0.5),
+                    double_sided               : material.get("doubleSided").and_then(Value::as_bool).unwrap_or(false),
+                }
+            )
+        }).collect()
+    }
+
+    fn images(&self, base_dir: &Path) -> Result<Vec<Image>, GltfError> {
+        array(self.json, "images").iter().enumerate().map(|(i, image)| {
+            let pointer = format!("/images/{}", i);
+            let mime_type = image.get("mimeType").and_then(Value::as_str).unwrap_or("").to_string();
+            let source = match (image.get("uri").and_then(Value::as_str), opt_usize(image, "bufferView")) {
+                (Some(uri), _) if uri.starts_with("data:") => {
+                    let (mime_type, data) = decode_data_uri(uri)
+                        .ok_or_else(|| invalid(format!("{}/uri", pointer), "malformed data uri"))?;
+                    ImageSource::Embedded { mime_type, data }
+                },
+                (Some(uri), _) => ImageSource::Path(base_dir.join(percent_decode(uri))),
+                (None, Some(view)) => {
+                    let (bytes, _) = self.buffer_view(view, &pointer)?;
+                    ImageSource::Embedded { mime_type, data: bytes.to_vec() }
+                },
+                (None, None) => return Err(invalid(pointer, "image has neither a uri nor a buffer view")),
+            };
+            Ok(Image { name: get_name(image), source })
+        }).collect()
+    }
+
+    fn skins(&self) -> Result<Vec<Skin>, GltfError> {
+        array(self.json, "skins").iter().enumerate().map(|(i, skin)| {
+            let pointer = format!("/skins/{}", i);
+            let node_cnt = array(self.json, "nodes").len();
+            let joints: Vec<usize> = array(skin, "joints").iter()
+                .map(|j| j.as_u64().map(|j| j as usize).filter(|j| *j < node_cnt))
+                .collect::<Option<Vec<usize>>>()
+                .ok_or_else(|| invalid(format!("{}/joints", pointer), "expected node indices"))?;
+            let inverse_bind_matrices = match opt_usize(skin, "inverseBindMatrices") {
+                Some(accessor) => {
+                    let data = self.accessor(accessor, &format!("{}/inverseBindMatrices", pointer))?;
+                    if data.width != 16 || data.values.len() / 16 != joints.len() {
+                        return Err(invalid(format!("{}/inverseBindMatrices", pointer), "expected one MAT4 per joint"));
+                    }
+                    data.values.chunks(16).map(|columns| {
+                        let mut m = [0.0;16];
+                        m.copy_from_slice(columns);
+                        Mat4::from_data(m).transpose()
+                    }).collect()
+                },
+                None => vec![Mat4::identity(); joints.len()],
+            };
+            Ok(
+                Skin {
+                    name     : get_name(skin),
+                    joints,
+                    inverse_bind_matrices,
+                    skeleton : index(skin, "skeleton", node_cnt, &pointer)?,
+                }
+            )
+        }).collect()
+    }
+
+    fn animations(&self) -> Result<Vec<AnimationClip>, GltfError> {
+        array(self.json, "animations").iter().enumerate().map(|(i, animation)| {
+            let pointer = format!("/animations/{}", i);
+            let samplers = array(animation, "samplers");
+            let mut duration = 0.0f32;
+            let mut channels = Vec::new();
+            for (c, channel) in array(animation, "channels").iter().enumerate() {
+                let channel_pointer = format!("{}/channels/{}", pointer, c);
+                let target = channel.get("target")
+                    .ok_or_else(|| invalid(channel_pointer.clone(), "missing target"))?;
+                //Targets without a node are meant for extensions
+                let node = match index(target, "node", array(self.json, "nodes").len(), &format!("{}/target", channel_pointer))? {
+                    Some(node) => node,
+                    None => continue,
+                };
+                let property = match target.get("path").and_then(Value::as_str) {
+                    Some("translation") => ChannelProperty::Translation,
+                    Some("rotation") => ChannelProperty::Rotation,
+                    Some("scale") => ChannelProperty::Scale,
+                    Some("weights") => ChannelProperty::Weights,
+                    _ => return Err(invalid(format!("{}/target/path", channel_pointer), "unknown target path")),
+                };
+                let sampler_idx = get_usize(channel, "sampler", &channel_pointer)?;
+                let sampler = samplers.get(sampler_idx)
+                    .ok_or_else(|| invalid(format!("{}/sampler", channel_pointer), "sampler does not exist"))?;
+                let sampler_pointer = format!("{}/samplers/{}", pointer, sampler_idx);
+                let interpolation = match sampler.get("interpolation").and_then(Value::as_str) {
+                    None | Some("LINEAR") => Interpolation::Linear,
+                    Some("STEP") => Interpolation::Step,
+                    Some("CUBICSPLINE") => Interpolation::CubicSpline,
+                    Some(_) => return Err(invalid(format!("{}/interpolation", sampler_pointer), "unknown interpolation")),
+                };
+                let times = self.accessor(get_usize(sampler, "input", &sampler_pointer)?, &sampler_pointer)?.values;
+                let values = self.accessor(get_usize(sampler, "output", &sampler_pointer)?, &sampler_pointer)?.values;
+                duration = times.iter().cloned().fold(duration, f32::max);
+                channels.push(Channel { node, property, interpolation, times, values });
+            }
+            Ok(AnimationClip { name: get_name(animation), channels, duration })
+        }).collect()
+    }
+}
+
+//Reads `out.len() / width` elements of `width` components starting at
+//`offset` into the buffer view
+fn read_components(
+    (bytes, stride): (&[u8], usize),
+    offset: usize,
+    component_type: usize,
+    normalized: bool,
+    width: usize,
+    out: &mut [f32],
+    pointer: &str,
+) -> Result<(), GltfError> {
+    let size = match component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => return Err(invalid(format!("{}/componentType", pointer), "unknown component type")),
+    };
+    let stride = if stride == 0 { size * width } else { stride };
+    let count = out.len() / width;
+    let end = (count.max(1) - 1).checked_mul(stride)
+        .and_then(|last| last.checked_add(offset))
+        .and_then(|last| last.checked_add(size * width));
+    if count > 0 && !matches!(end, Some(end) if end <= bytes.len()) {
+        return Err(invalid(pointer.to_string(), "accessor runs past the end of its buffer view"));
+    }
+    for (i, element) in out.chunks_mut(width).enumerate() {
+        let start = offset + i * stride;
+        for (j, value) in element.iter_mut().enumerate() {
+            let b = &bytes[start + j * size..];
+            //Normalized integers map to 0..1 or -1..1 as in the spec
+            *value = match component_type {
+                5120 if normalized => (b[0] as i8 as f32 / 127.0).max(-1.0),
+                5120 => b[0] as i8 as f32,
+                5121 if normalized => b[0] as f32 / 255.0,
+                5121 => b[0] as f32,
+                5122 if normalized => (i16::from_le_bytes([b[0], b[1]]) as f32 / 32767.0).max(-1.0),
+                5122 => i16::from_le_bytes([b[0], b[1]]) as f32,
+                5123 if normalized => u16::from_le_bytes([b[0], b[1]]) as f32 / 65535.0,
+                5123 => u16::from_le_bytes([b[0], b[1]]) as f32,
+                5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32,
+                _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            };
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec4;
+
+    fn fixture_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/test/gltf").join(name)
+    }
+
+    fn fixture(name: &str) -> GltfData {
+        parse_file(&fixture_path(name)).unwrap()
+    }
+
+    fn position(primitive: &Primitive, idx: u32) -> [f32;3] {
+        primitive.mesh.position(idx).to_array()
+    }
+
+    fn semantics(primitive: &Primitive) -> Vec<Semantic> {
+        primitive.mesh.streams.iter().map(|s| s.semantic.clone()).collect()
+    }
+
+    #[test]
+    fn external_and_embedded_buffers_match() {
+        for name in ["Triangle.gltf", "TriangleEmbedded.gltf"].iter() {
+            let data = fixture(name);
+            let primitive = &data.meshes[0].primitives[0];
+            assert_eq!(semantics(primitive), vec![Semantic::Position], "{}", name);
+            assert_eq!(primitive.mesh.positions(), &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], "{}", name);
+            assert_eq!(primitive.mesh.indices, vec![0, 1, 2], "{}", name);
+            assert_eq!(data.roots, vec![0], "{}", name);
+        }
+    }
+
+    #[test]
+    fn reads_glb_hierarchy_and_materials() {
+        let data = fixture("Box.glb");
+        let primitive = &data.meshes[0].primitives[0];
+        assert_eq!(semantics(primitive), vec![Semantic::Position, Semantic::Normal]);
+        assert_eq!(primitive.mesh.vertex_count(), 24);
+        assert_eq!(primitive.mesh.indices.len(), 36);
+        //Interleaved in the buffer with the normal first, the layout puts the position first
+        assert_eq!(&primitive.mesh.interleaved()[..6], &[-0.5, -0.5, 0.5, 0.0, 0.0, 1.0]);
+        assert_eq!(primitive.mesh.bounds.max, Vec3::splat(0.5));
+        assert_eq!(primitive.mesh.materials[0].diffuse, [0.8, 0.0, 0.0]);
+
+        assert_eq!(data.roots, vec![0]);
+        assert_eq!(data.nodes[0].children, vec![1]);
+        assert_eq!(data.nodes[1].parent, Some(0));
+        assert_eq!(data.find_node("Box"), Some(1));
+        //The root turns +y into -z
+        assert_approx_eq!(data.world_matrix(1) * Vec4::new(0.0, 1.0, 0.0, 1.0), Vec4::new(0.0, 0.0, -1.0, 1.0));
+
+        let red = &data.materials[primitive.material.unwrap()];
+        assert_eq!(red.name, "Red");
+        assert_eq!(red.base_color, [0.8, 0.0, 0.0, 1.0]);
+        assert_eq!((red.metallic, red.roughness), (0.0, 1.0));
+        assert_eq!(red.alpha_mode, AlphaMode::Opaque);
+        assert_eq!(red.to_material(&data.images).diffuse, [0.8, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_animation_clips() {
+        let data = fixture("AnimatedTriangle.gltf");
+        assert_eq!(data.animations.len(), 1);
+        let clip = &data.animations[0];
+        assert_eq!(clip.duration, 1.0);
+        let channel = &clip.channels[0];
+        assert_eq!((channel.node, channel.property, channel.interpolation),
+                   (0, ChannelProperty::Rotation, Interpolation::Linear));
+        assert_eq!(channel.times, vec![0.0, 0.25, 0.5, 0.75, 1.0]);
+        assert_eq!(channel.values.len(), 5 * 4);
+        assert_eq!(&channel.values[8..12], &[0.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn reads_skins() {
+        let data = fixture("SimpleSkin.gltf");
+        let skin = &data.skins[0];
+        assert_eq!(skin.joints, vec![1, 2]);
+        assert_eq!(data.nodes[0].skin, Some(0));
+        //The inverse bind matrix takes the joint's rest position back to the origin
+        let rest = data.world_matrix(2) * Vec4::new(0.0, 0.0, 0.0, 1.0);
+        assert_approx_eq!(&skin.inverse_bind_matrices[1] * rest, Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+        let primitive = &data.meshes[0].primitives[0];
+        assert_eq!(semantics(primitive), vec![Semantic::Position, Semantic::Joints(0), Semantic::Weights(0)]);
+        let widths: Vec<u8> = primitive.mesh.layout().iter().map(|a| a.width).collect();
+        assert_eq!(widths, vec![3, 4, 4]);
+        //The top row of vertices follows the second joint only
+        assert_eq!(&primitive.mesh.interleaved()[9 * 11 + 3..10 * 11], &[0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(data.animations[0].channels[0].node, 2);
+        assert_eq!(data.animations[0].duration, 2.0);
+    }
+
+    #[test]
+    fn applies_sparse_accessors() {
+        let data = fixture("SimpleSparseAccessor.gltf");
+        let primitive = &data.meshes[0].primitives[0];
+        assert_eq!(position(primitive, 7), [0.0, 1.0, 0.0]);
+        assert_eq!(position(primitive, 8), [1.0, 2.0, 0.0]);
+        assert_eq!(position(primitive, 9), [2.0, 1.0, 0.0]);
+        assert_eq!(position(primitive, 10), [3.0, 3.0, 0.0]);
+        assert_eq!(position(primitive, 12), [5.0, 4.0, 0.0]);
+        //Without a default scene every parentless node is a root
+        assert_eq!(data.roots, vec![0]);
+    }
+
+    #[test]
+    fn converts_strips_normalized_attributes_and_images() {
+        let data = fixture("TexturedQuad.gltf");
+        let primitive = &data.meshes[0].primitives[0];
+        assert_eq!(semantics(primitive), vec![Semantic::Position, Semantic::TexCoord(0), Semantic::Color(0)]);
+        assert_eq!(primitive.mesh.indices, vec![0, 1, 2, 2, 1, 3]);
+        assert_eq!(&primitive.mesh.interleaved()[3 * 9..4 * 9], &[1.0, 1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 128.0 / 255.0]);
+
+        let material = &data.materials[0];
+        assert_eq!(material.base_color_texture, Some(TextureRef { image: Some(0), tex_coord: 0 }));
+        assert_eq!(material.normal_texture, Some(TextureRef { image: Some(1), tex_coord: 1 }));
+        assert_eq!((material.alpha_mode, material.double_sided), (AlphaMode::Mask, true));
+        assert_eq!(data.images[0].source, ImageSource::Path(fixture_path("checker board.png")));
+        match &data.images[1].source {
+            ImageSource::Embedded { mime_type, data } => {
+                assert_eq!(mime_type, "image/png");
+                assert!(data.starts_with(b"\x89PNG"));
+            },
+            other => panic!("expected an embedded image, got {:?}", other),
+        }
+        let phong = material.to_material(&data.images);
+        assert_eq!(phong.map_kd, Some(fixture_path("checker board.png")));
+        assert_eq!(phong.map_bump, None);
+    }
+
+    #[test]
+    fn reports_invalid_files() {
+        let src = br#"{"asset": {"version": "2.0"}, "meshes": [{"primitives": [{"attributes": {"POSITION": 3}}]}]}"#;
+        match parse(src, Path::new("")) {
+            Err(GltfError::Invalid { pointer, .. }) => assert_eq!(pointer, "/meshes/0/primitives/0/attributes/POSITION"),
+            other => panic!("expected an invalid accessor, got {:?}", other.err()),
+        }
+
+        let src = br#"{"asset": {"version": "1.0"}}"#;
+        match parse(src, Path::new("")) {
+            Err(GltfError::Unsupported(_)) => (),
+            other => panic!("expected an unsupported version, got {:?}", other.err()),
+        }
+
+        let mut glb = std::fs::read(fixture_path("Box.glb")).unwrap();
+        glb.truncate(glb.len() - 100);
+        match parse(&glb, Path::new("")) {
+            Err(GltfError::Glb(_)) => (),
+            other => panic!("expected a broken glb, got {:?}", other.err()),
+        }
+
+        let src = br#"{"asset": {"version": "2.0"}, "buffers": [{"uri": "missing.bin", "byteLength": 4}]}"#;
+        match parse(src, Path::new("nowhere")) {
+            Err(GltfError::Io { path, .. }) => assert_eq!(path, Path::new("nowhere/missing.bin")),
+            other => panic!("expected a missing buffer, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_counts_and_indices() {
+        let buffer = r#""buffers": [{"uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAA", "byteLength": 12}],
+                        "bufferViews": [{"buffer": 0, "byteLength": 12}]"#;
+        let accessor = |count: &str| format!(
+            r#"{{"asset": {{"version": "2.0"}}, {},
+                "accessors": [{{"bufferView": 0, "componentType": 5126, "count": {}, "type": "VEC3"}}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}]}}"#,
+            buffer, count,
+        );
+        assert_eq!(parse(accessor("1").as_bytes(), Path::new("")).unwrap().meshes[0].primitives[0].mesh.vertex_count(), 1);
+        for count in ["13", "18446744073709551615"].iter() {
+            match parse(accessor(count).as_bytes(), Path::new("")) {
+                Err(GltfError::Invalid { pointer, .. }) => assert_eq!(pointer, "/accessors/0/count", "{}", count),
+                other => panic!("expected an invalid count for {}, got {:?}", count, other.err()),
+            }
+        }
+
+        //Without a buffer view only sparse accessors make sense, and then the
+        //count has nothing to be checked against but a fixed limit
+        let sparse = |count: &str, index_type: u32| format!(
+            r#"{{"asset": {{"version": "2.0"}},
+                "buffers": [{{"uri": "data:application/octet-stream;base64,AQAAAAAAgD8AAABAAABAQA==", "byteLength": 16}}],
+                "bufferViews": [{{"buffer": 0, "byteLength": 4}}, {{"buffer": 0, "byteOffset": 4, "byteLength": 12}}],
+                "accessors": [{{"componentType": 5126, "count": {}, "type": "VEC3", "sparse": {{"count": 1,
+                    "indices": {{"bufferView": 0, "componentType": {}}}, "values": {{"bufferView": 1}}}}}}],
+                "meshes": [{{"primitives": [{{"attributes": {{"POSITION": 0}}}}]}}]}}"#,
+            count, index_type,
+        );
+        let data = parse(sparse("3", 5125).as_bytes(), Path::new("")).unwrap();
+        assert_eq!(data.meshes[0].primitives[0].mesh.positions(), &[0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 0.0, 0.0, 0.0]);
+        let cases = [
+            (sparse("1099511627776", 5125), "/accessors/0/count"),
+            (sparse("3", 5126), "/accessors/0/sparse/indices/componentType"),
+            (accessor("1").replace(r#""bufferView": 0, "#, ""), "/accessors/0"),
+        ];
+        for (src, expected) in cases.iter() {
+            match parse(src.as_bytes(), Path::new("")) {
+                Err(GltfError::Invalid { pointer, .. }) => assert_eq!(&pointer, expected),
+                other => panic!("expected {} to be invalid, got {:?}", expected, other.err()),
+            }
+        }
+        //Unlike floats, indices keep every bit
+        let bytes = 16_777_217u32.to_le_bytes();
+        assert_eq!(read_indices((&bytes, 0), 0, 5125, 1, "").unwrap(), vec![16_777_217]);
+
+        let cases = [
+            (r#""nodes": [{"mesh": 0}]"#, "/nodes/0/mesh"),
+            (r#""nodes": [{"skin": 0}]"#, "/nodes/0/skin"),
+            (r#""nodes": [{}], "skins": [{"joints": [0, 1]}]"#, "/skins/0/joints"),
+            (r#""nodes": [{}], "skins": [{"joints": [0], "skeleton": 1}]"#, "/skins/0/skeleton"),
+            (r#""nodes": [{}], "animations": [{"channels": [{"sampler": 0, "target": {"node": 1, "path": "scale"}}]}]"#,
+             "/animations/0/channels/0/target/node"),
+        ];
+        for (json, expected) in cases.iter() {
+            let src = format!(r#"{{"asset": {{"version": "2.0"}}, {}}}"#, json);
+            match parse(src.as_bytes(), Path::new("")) {
+                Err(GltfError::Invalid { pointer, .. }) => assert_eq!(&pointer, expected),
+                other => panic!("expected {} to be out of range, got {:?}", expected, other.err()),
+            }
+        }
+
+        let src = br#"{"asset": {"version": "2.0"}, "extensionsUsed": ["KHR_draco_mesh_compression"],
+                       "extensionsRequired": ["KHR_draco_mesh_compression"]}"#;
+        match parse(src, Path::new("")) {
+            Err(GltfError::Unsupported(what)) => assert!(what.contains("KHR_draco_mesh_compression")),
+            other => panic!("expected an unsupported extension, got {:?}", other.err()),
+        }
+        let src = br#"{"asset": {"version": "2.0"}, "extensionsUsed": ["KHR_materials_unlit"]}"#;
+        assert!(parse(src, Path::new("")).is_ok());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::renderer::model::Submesh;
+use crate::renderer::model::mtl::Material;
+use crate::renderer::gpu::Attribute;
+use crate::math::{Aabb, Vec3};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+//What a vertex stream holds. The number picks between several sets, e.g.
+//TexCoord(1) for a lightmap.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Semantic {
+    Position,
+    Normal,
+    Tangent,
+    TexCoord(u32),
+    Color(u32),
+    Joints(u32),
+    Weights(u32),
+    //Anything else a file carries, by the name it has there
+    Custom(String),
+}
+
+//`width` floats per vertex, one after another
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexStream {
+    pub semantic : Semantic,
+    pub width    : usize,
+    pub data     : Vec<f32>,
+}
+
+//Geometry on the CPU side. Nothing here touches GL, upload turns it into
+//a Model. All streams have the same vertex count and the position stream
+//is always first.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub name             : String,
+    //Interleaved in this order by layout and interleaved
+    pub streams          : Vec<VertexStream>,
+    pub indices          : Vec<u32>,
+    //Empty submeshes means the whole index buffer is drawn at once
+    pub submeshes        : Vec<Submesh>,
+    pub materials        : Vec<Material>,
+    //OBJ style smoothing group of every triangle, 0 for a flat one. Empty
+    //when the source has none, then normals::smooth goes by angle alone.
+    pub smoothing_groups : Vec<u32>,
+    //Of the positions, kept up to date by the constructors and update_bounds
+    pub bounds           : Aabb,
+}
+
+impl Mesh {
+    pub fn new(name: &str, positions: Vec<f32>, indices: Vec<u32>) -> Self {
+        let mut mesh = Mesh {
+            name             : name.to_string(),
+            streams          : vec![VertexStream { semantic: Semantic::Position, width: 3, data: positions }],
+            indices,
+            submeshes        : Vec::new(),
+            materials        : Vec::new(),
+            smoothing_groups : Vec::new(),
+            bounds           : Aabb::empty(),
+        };
+        mesh.update_bounds();
+        mesh
+    }
+
+    //Splits interleaved vertices into one stream per (semantic, width) pair.
+    //The first pair has to be the position.
+    pub fn from_interleaved(name: &str, vertices: &[f32], semantics: &[(Semantic, usize)], indices: Vec<u32>) -> Self {
+        assert!(
+            semantics.first().map(|(s, w)| (s, *w)) == Some((&Semantic::Position, 3)),
+            "the first stream of a mesh has to be a 3 float position",
+        );
+        let stride: usize = semantics.iter().map(|(_, width)| width).sum();
+        let vertex_cnt = vertices.len() / stride;
+        let mut offset = 0;
+        let streams = semantics.iter().map(|(semantic, width)| {
+            let mut data = Vec::with_capacity(vertex_cnt * width);
+            for v in vertices.chunks(stride) {
+                data.extend_from_slice(&v[offset..offset + width]);
+            }
+            offset += width;
+            VertexStream { semantic: semantic.clone(), width: *width, data }
+        }).collect();
+
+        let mut mesh = Mesh::new(name, Vec::new(), indices);
+        mesh.streams = streams;
+        mesh.update_bounds();
+        mesh
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.streams[0].data.len() / 3
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn positions(&self) -> &[f32] {
+        &self.streams[0].data
+    }
+
+    pub fn position(&self, idx: u32) -> Vec3 {
+        let p = &self.streams[0].data[idx as usize * 3..];
+        Vec3::new(p[0], p[1], p[2])
+    }
+
+    pub fn stream(&self, semantic: &Semantic) -> Option<&VertexStream> {
+        self.streams.iter().find(|s| &s.semantic == semantic)
+    }
+
+    pub fn stream_mut(&mut self, semantic: &Semantic) -> Option<&mut VertexStream> {
+        self.streams.iter_mut().find(|s| &s.semantic == semantic)
+    }
+
+    //Replaces the stream with the same semantic or appends a new one
+    pub fn set_stream(&mut self, semantic: Semantic, width: usize, data: Vec<f32>) -> () {
+        assert_eq!(data.len(), self.vertex_count() * width, "stream {:?} has the wrong length", semantic);
+        let is_position = semantic == Semantic::Position;
+        let stream = VertexStream { semantic, width, data };
+        match self.streams.iter_mut().find(|s| s.semantic == stream.semantic) {
+            Some(existing) => *existing = stream,
+            None => self.streams.push(stream),
+        }
+        if is_position {
+            self.update_bounds();
+        }
+    }
+
+    //Rebuilds every stream so that new vertex i is a copy of old vertex
+    //sources[i]. Indices are left alone, the caller remaps them.
+    pub fn select_vertices(&mut self, sources: &[u32]) -> () {
+        for stream in self.streams.iter_mut() {
+            let width = stream.width;
+            let mut data = Vec::with_capacity(sources.len() * width);
+            for &src in sources {
+                let start = src as usize * width;
+                data.extend_from_slice(&stream.data[start..start + width]);
+            }
+            stream.data = data;
+        }
+        self.update_bounds();
+    }
+
+    //Gives every corner (entry of the index buffer) a key and splits the
+    //vertices whose corners disagree. The first key seen keeps the vertex,
+    //every other key gets a copy appended at the end. Returns for each
+    //vertex the corner it was kept or copied for, None if nothing uses it.
+    pub fn split_vertices<K: Hash + Eq>(&mut self, keys: &[K]) -> Vec<Option<usize>> {
+        assert_eq!(keys.len(), self.indices.len(), "one key per corner");
+        let vertex_cnt = self.vertex_count();
+        let mut owners: Vec<Option<usize>> = vec![None; vertex_cnt];
+        let mut sources: Vec<u32> = (0..vertex_cnt as u32).collect();
+        let mut copies = HashMap::<(u32, &K), u32>::new();
+        for (corner, key) in keys.iter().enumerate() {
+            let v = self.indices[corner];
+            match owners[v as usize] {
+                None => owners[v as usize] = Some(corner),
+                Some(first) if keys[first] == *key => {},
+                Some(_) => {
+                    let idx = *copies.entry((v, key)).or_insert_with(|| {
+                        sources.push(v);
+                        owners.push(Some(corner));
+                        sources.len() as u32 - 1
+                    });
+                    self.indices[corner] = idx;
+                }
+            }
+        }
+        if sources.len() > vertex_cnt {
+            self.select_vertices(&sources);
+        }
+        owners
+    }
+
+    pub fn update_bounds(&mut self) -> () {
+        let mut bounds = Aabb::empty();
+        for p in self.positions().chunks(3) {
+            bounds.grow(Vec3::new(p[0], p[1], p[2]));
+        }
+        self.bounds = bounds;
+    }
+
+    //Float attributes matching interleaved
+    pub fn layout(&self) -> Vec<Attribute> {
+        let float_size = std::mem::size_of::<gl::types::GLfloat>();
+        let stride = self.streams.iter().map(|s| s.width).sum::<usize>() * float_size;
+        let mut start_idx = 0;
+        self.streams.iter().map(|s| {
+            let attr = Attribute {
+                width: s.width as u8,
+                stride,
+                start_idx,
+                ty: gl::FLOAT,
+            };
+            start_idx += s.width * float_size;
+            attr
+        }).collect()
+    }
+
+    pub fn interleaved(&self) -> Vec<gl::types::GLfloat> {
+        let vertex_cnt = self.vertex_count();
+        let stride: usize = self.streams.iter().map(|s| s.width).sum();
+        let mut vertices = Vec::with_capacity(vertex_cnt * stride);
+        for v in 0..vertex_cnt {
+            for s in self.streams.iter() {
+                vertices.extend_from_slice(&s.data[v * s.width..(v + 1) * s.width]);
+            }
+        }
+        vertices
+    }
+}
+
+//Bit pattern of a vector to hash on, with -0.0 folded into 0.0 so
+//mirrored values match
+pub(crate) fn key(v: Vec3) -> [u32;3] {
+    let bits = |f: f32| if f == 0.0 { 0 } else { f.to_bits() };
+    [bits(v.x()), bits(v.y()), bits(v.z())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad() -> Mesh {
+        let vertices = [
+            0.0, 0.0, 0.0,  0.0, 0.0,
+            2.0, 0.0, 0.0,  1.0, 0.0,
+            0.0, 1.0, -1.0, 0.0, 1.0,
+            2.0, 1.0, -1.0, 1.0, 1.0,
+        ];
+        let semantics = [(Semantic::Position, 3), (Semantic::TexCoord(0), 2)];
+        Mesh::from_interleaved("quad", &vertices, &semantics, vec![0, 1, 2, 2, 1, 3])
+    }
+
+    #[test]
+    fn splits_and_interleaves_streams() {
+        let mesh = quad();
+        assert_eq!(mesh.vertex_count(), 4);
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.stream(&Semantic::TexCoord(0)).unwrap().data, vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0]);
+        assert_eq!(mesh.position(3), Vec3::new(2.0, 1.0, -1.0));
+        assert_eq!(mesh.bounds, Aabb::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(2.0, 1.0, 0.0)));
+
+        assert_eq!(&mesh.interleaved()[5..10], &[2.0, 0.0, 0.0, 1.0, 0.0]);
+        let layout = mesh.layout();
+        assert_eq!(layout.iter().map(|a| (a.width, a.start_idx)).collect::<Vec<_>>(), vec![(3, 0), (2, 12)]);
+        assert!(layout.iter().all(|a| a.stride == 20));
+    }
+
+    #[test]
+    fn set_stream_replaces_or_appends() {
+        let mut mesh = quad();
+        mesh.set_stream(Semantic::Normal, 3, [0.0, 0.0, 1.0].repeat(4));
+        assert_eq!(mesh.streams.len(), 3);
+        assert_eq!(mesh.interleaved().len(), 4 * 8);
+
+        mesh.set_stream(Semantic::Position, 3, [1.0, 1.0, 1.0].repeat(4));
+        assert_eq!(mesh.streams.len(), 3);
+        assert_eq!(mesh.bounds, Aabb::new(Vec3::splat(1.0), Vec3::splat(1.0)));
+    }
+
+    #[test]
+    fn select_vertices_copies_every_stream() {
+        let mut mesh = quad();
+        mesh.select_vertices(&[3, 3, 0]);
+        assert_eq!(mesh.vertex_count(), 3);
+        assert_eq!(mesh.stream(&Semantic::TexCoord(0)).unwrap().data, vec![1.0, 1.0, 1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(mesh.bounds, Aabb::new(Vec3::new(0.0, 0.0, -1.0), Vec3::new(2.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn split_vertices_copies_only_disagreeing_corners() {
+        let mut mesh = quad();
+        //Vertices 1 and 2 are shared, 1 agrees across both triangles, 2 doesn't
+        let owners = mesh.split_vertices(&['a', 'a', 'a', 'b', 'a', 'b']);
+        assert_eq!(mesh.vertex_count(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 4, 1, 3]);
+        assert_eq!(mesh.position(4), mesh.position(2));
+        assert_eq!(owners, vec![Some(0), Some(1), Some(2), Some(5), Some(3)]);
+    }
+}
@@ -1,22 +1,71 @@
 use super::gpu::*;
+use crate::math::{Aabb, Transform};
+use mtl::Material;
+use mesh::Mesh;
+use simplify::Lod;
 use gl::types::*;
 use std::sync::Arc;
 
+pub mod gltf;
+pub mod mesh;
+pub mod mtl;
+pub mod normals;
 pub mod obj;
+pub mod optimize;
+pub mod ply;
+pub mod simplify;
+pub mod stl;
+pub mod tangents;
+pub mod triangulate;
+
+//A run of indices drawn with one material. `material` indexes into
+//Model::materials, None means the renderer's default material.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submesh {
+    pub material_name : Option<String>,
+    pub material      : Option<usize>,
+    pub start         : usize,
+    pub count         : usize,
+}
+
+//A coarser index buffer drawn with the model's own vertex buffer, see
+//simplify::lod_chain
+pub struct ModelLod {
+    pub indices   : Arc<ElementBufferObject>,
+    pub submeshes : Vec<Submesh>,
+    //Relative to the diagonal of Model::bounds
+    pub error     : f32,
+}
 
 pub struct Model {
+    //The o or g name for models loaded from .obj parts, empty otherwise
+    pub name: String,
     pub buffer: Option<Arc<VertexBufferObject>>,
     pub array: Option<Arc<VertexArrayObject>>,
     pub indices: Option<Arc<ElementBufferObject>>,
+    pub transform: Transform,
+    //Empty submeshes means the whole index buffer is drawn at once
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<Material>,
+    //Of the vertices in model space, empty if unknown
+    pub bounds: Aabb,
+    //Finest first, the model itself is the level before all of them
+    pub lods: Vec<ModelLod>,
     is_loaded: bool,
 }
 
 impl Model {
     pub fn new_unloaded() -> Self {
         Self { 
+            name      : String::new(),
             buffer    : None, 
             indices   : None,
             array     : None, 
+            transform : Transform::identity(),
+            submeshes : Vec::new(),
+            materials : Vec::new(),
+            bounds    : Aabb::empty(),
+            lods      : Vec::new(),
             is_loaded : false,
         }
     }
@@ -25,9 +74,15 @@ impl Model {
         let vbo = VertexBufferObject::from_data(data, data.len());
         let ebo = ElementBufferObject::from_indices(indices, indices.len()); 
         Self {
+            name    : String::new(),
             buffer  : Some(Arc::new(vbo)),
             array   : Some(Arc::new(vao)),
             indices : Some(Arc::new(ebo)),
+            transform : Transform::identity(),
+            submeshes : Vec::new(),
+            materials : Vec::new(),
+            bounds    : Aabb::empty(),
+            lods      : Vec::new(),
             is_loaded: true,
         }
     }
@@ -62,3 +117,22 @@ impl Model {
     }
 }
 
+//The only place a Mesh meets GL, everything before this runs without a context
+pub fn upload(mesh: &Mesh) -> Model {
+    let mut model = Model::from_data_and_layout(&mesh.interleaved(), &mesh.indices, &mesh.layout());
+    model.name = mesh.name.clone();
+    model.submeshes = mesh.submeshes.clone();
+    model.materials = mesh.materials.clone();
+    model.bounds = mesh.bounds;
+    model
+}
+
+pub fn upload_with_lods(mesh: &Mesh, lods: &[Lod]) -> Model {
+    let mut model = upload(mesh);
+    model.lods = lods.iter().filter(|lod| !lod.indices.is_empty()).map(|lod| ModelLod {
+        indices   : Arc::new(ElementBufferObject::from_indices(&lod.indices, lod.indices.len())),
+        submeshes : lod.submeshes.clone(),
+        error     : lod.error,
+    }).collect();
+    model
+}
@@ -0,0 +1,279 @@
+use super::obj::{handle_line_error, lossy, parse_ascii, read_floats, read_name, Cursor, LineResult, Token};
+use super::obj::{ObjError, ObjErrorReason, ParseMode};
+use std::io::{self, BufReader, BufRead, BufWriter, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+//One newmtl block of a .mtl file. Texture paths are resolved relative to
+//the directory of the .mtl file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Material {
+    pub name      : String,
+    pub ambient   : [f32;3],
+    pub diffuse   : [f32;3],
+    pub specular  : [f32;3],
+    pub emissive  : [f32;3],
+    pub shininess : f32,
+    pub optical_density : f32,
+    //1.0 is fully opaque
+    pub dissolve  : f32,
+    pub illum     : u32,
+    pub map_ka    : Option<PathBuf>,
+    pub map_kd    : Option<PathBuf>,
+    pub map_ks    : Option<PathBuf>,
+    pub map_ns    : Option<PathBuf>,
+    pub map_d     : Option<PathBuf>,
+    pub map_bump  : Option<PathBuf>,
+}
+
+impl Material {
+    pub fn new(name: &str) -> Self {
+        Material {
+            name      : name.to_string(),
+            ambient   : [0.0, 0.0, 0.0],
+            diffuse   : [0.8, 0.8, 0.8],
+            specular  : [0.0, 0.0, 0.0],
+            emissive  : [0.0, 0.0, 0.0],
+            shininess : 0.0,
+            optical_density : 1.0,
+            dissolve  : 1.0,
+            illum     : 1,
+            map_ka    : None,
+            map_kd    : None,
+            map_ks    : None,
+            map_ns    : None,
+            map_d     : None,
+            map_bump  : None,
+        }
+    }
+}
+
+pub fn load(path: &Path, mode: ParseMode, warnings: &mut Vec<ObjError>) -> Result<Vec<Material>, ObjError> {
+    let mtl_file = File::open(path)
+        .map_err(|e| ObjError::new(0, 0, ObjErrorReason::Io(e)).with_path(path))?;
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    parse(BufReader::new(mtl_file), base_dir, mode, warnings).map_err(|e| e.with_path(path))
+}
+
+//Lines skipped in lenient mode are appended to `warnings`
+pub fn parse<R: BufRead>(
+    mut mtl_file: R,
+    base_dir: &Path,
+    mode: ParseMode,
+    warnings: &mut Vec<ObjError>,
+) -> Result<Vec<Material>, ObjError> {
+    let mut bytes = Vec::new();
+    mtl_file.read_to_end(&mut bytes).map_err(|e| ObjError::new(0, 0, ObjErrorReason::Io(e)))?;
+    let mut materials = Vec::<Material>::new();
+    let mut cursor = Cursor::new(&bytes);
+    loop {
+        if let Some(keyword) = cursor.next_token() {
+            let line = cursor.line + 1;
+            let result = parse_statement(keyword, &mut cursor, base_dir, &mut materials);
+            handle_line_error(mode, line, result, warnings)?;
+        }
+        if !cursor.next_line() {
+            break;
+        }
+    }
+    Ok(materials)
+}
+
+fn parse_statement(keyword: Token, cursor: &mut Cursor, base_dir: &Path, materials: &mut Vec<Material>) -> LineResult<()> {
+    if keyword.bytes == b"newmtl" {
+        materials.push(Material::new(&read_name(cursor)));
+        return Ok(());
+    }
+    let material = match materials.last_mut() {
+        Some(material) => material,
+        None => {
+            return Err((keyword.column, ObjErrorReason::StatementBeforeNewmtl(lossy(keyword.bytes))));
+        }
+    };
+    match keyword.bytes {
+        b"Ka" => material.ambient = read_color(cursor)?,
+        b"Kd" => material.diffuse = read_color(cursor)?,
+        b"Ks" => material.specular = read_color(cursor)?,
+        b"Ke" => material.emissive = read_color(cursor)?,
+        b"Ns" => material.shininess = read_float(cursor)?,
+        b"Ni" => material.optical_density = read_float(cursor)?,
+        //"d -halo 0.5" is allowed, the factor is last
+        b"d" => material.dissolve = read_last_float(cursor)?,
+        b"Tr" => material.dissolve = 1.0 - read_last_float(cursor)?,
+        b"illum" => material.illum = read_float(cursor)? as u32,
+        _ => {
+            let map = match keyword.bytes.to_ascii_lowercase().as_slice() {
+                b"map_ka" => &mut material.map_ka,
+                b"map_kd" => &mut material.map_kd,
+                b"map_ks" => &mut material.map_ks,
+                b"map_ns" => &mut material.map_ns,
+                b"map_d" => &mut material.map_d,
+                b"map_bump" | b"bump" => &mut material.map_bump,
+                _ => {
+                    trace!("Ignoring unsupported MTL statement '{}'", lossy(keyword.bytes));
+                    return Ok(());
+                }
+            };
+            //Texture options like -s or -bm come first, the file name is last
+            *map = last_token(cursor).map(|file| base_dir.join(lossy(file.bytes)));
+        },
+    }
+    Ok(())
+}
+
+fn read_float(cursor: &mut Cursor) -> LineResult<f32> {
+    let mut value = [0.];
+    read_floats(cursor, &mut value, 1)?;
+    Ok(value[0])
+}
+
+fn read_last_float(cursor: &mut Cursor) -> LineResult<f32> {
+    let token = last_token(cursor)
+        .ok_or_else(|| (cursor.column(), ObjErrorReason::MissingValues { expected: 1, found: 0 }))?;
+    parse_ascii::<f32>(token.bytes)
+        .ok_or_else(|| (token.column, ObjErrorReason::InvalidFloat(lossy(token.bytes))))
+}
+
+//A single value is a grey, e.g. "Kd 0.5"
+fn read_color(cursor: &mut Cursor) -> LineResult<[f32;3]> {
+    let mut color = [0.;3];
+    match read_floats(cursor, &mut color, 1)? {
+        1 => Ok([color[0];3]),
+        2 => Err((cursor.column(), ObjErrorReason::MissingValues { expected: 3, found: 2 })),
+        _ => Ok(color),
+    }
+}
+
+fn last_token<'a>(cursor: &mut Cursor<'a>) -> Option<Token<'a>> {
+    let mut last = None;
+    while let Some(token) = cursor.next_token() {
+        last = Some(token);
+    }
+    last
+}
+
+//Texture paths under the directory of `path` are written relative to it,
+//anything else as is
+pub fn save(path: &Path, materials: &[Material]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    for (i, m) in materials.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "newmtl {}", m.name)?;
+        writeln!(out, "Ka {} {} {}", m.ambient[0], m.ambient[1], m.ambient[2])?;
+        writeln!(out, "Kd {} {} {}", m.diffuse[0], m.diffuse[1], m.diffuse[2])?;
+        writeln!(out, "Ks {} {} {}", m.specular[0], m.specular[1], m.specular[2])?;
+        writeln!(out, "Ke {} {} {}", m.emissive[0], m.emissive[1], m.emissive[2])?;
+        writeln!(out, "Ns {}", m.shininess)?;
+        writeln!(out, "Ni {}", m.optical_density)?;
+        writeln!(out, "d {}", m.dissolve)?;
+        writeln!(out, "illum {}", m.illum)?;
+        let maps = [
+            ("map_Ka", &m.map_ka),
+            ("map_Kd", &m.map_kd),
+            ("map_Ks", &m.map_ks),
+            ("map_Ns", &m.map_ns),
+            ("map_d", &m.map_d),
+            ("map_Bump", &m.map_bump),
+        ];
+        for (keyword, map) in maps.iter() {
+            if let Some(map) = map {
+                let file = map.strip_prefix(base_dir).unwrap_or(map);
+                writeln!(out, "{} {}", keyword, file.display())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colors_and_maps() {
+        let src = "\
+# two materials
+newmtl red
+Ka 0.1 0.0 0.0
+Kd 1.0 0.0 0.0
+Ks 0.5
+Ns 96.0
+d 0.5
+illum 2
+map_Kd -s 2 2 1 textures/red.png
+
+newmtl glass
+Tr 0.75
+map_bump bump.png
+";
+        let mut warnings = Vec::new();
+        let materials = parse(src.as_bytes(), Path::new("assets"), ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(materials.len(), 2);
+
+        let red = &materials[0];
+        assert_eq!(red.name, "red");
+        assert_eq!(red.ambient, [0.1, 0.0, 0.0]);
+        assert_eq!(red.diffuse, [1.0, 0.0, 0.0]);
+        assert_eq!(red.specular, [0.5, 0.5, 0.5]);
+        assert_eq!(red.shininess, 96.0);
+        assert_eq!(red.dissolve, 0.5);
+        assert_eq!(red.illum, 2);
+        assert_eq!(red.map_kd, Some(PathBuf::from("assets/textures/red.png")));
+
+        let glass = &materials[1];
+        assert_eq!(glass.dissolve, 0.25);
+        assert_eq!(glass.diffuse, Material::new("").diffuse);
+        assert_eq!(glass.map_bump, Some(PathBuf::from("assets/bump.png")));
+    }
+
+    #[test]
+    fn shares_the_obj_tokenizer() {
+        //Tabs, comments and continued lines behave like in .obj files
+        let src = "newmtl\tstone   wall # trailing comment\nKd 0.2 \\\n   0.3\t0.4\nmap_Kd -bm 0.5 \\\n  stone.png\r\nNs 10\n";
+        let mut warnings = Vec::new();
+        let materials = parse(src.as_bytes(), Path::new(""), ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(materials[0].name, "stone wall");
+        assert_eq!(materials[0].diffuse, [0.2, 0.3, 0.4]);
+        assert_eq!(materials[0].map_kd, Some(PathBuf::from("stone.png")));
+        assert_eq!(materials[0].shininess, 10.0);
+    }
+
+    #[test]
+    fn errors_point_at_the_bad_token() {
+        let strict = |src: &str| parse(src.as_bytes(), Path::new(""), ParseMode::Strict, &mut Vec::new()).err().unwrap();
+
+        let e = strict("newmtl a\n\nKd 1 x 1\n");
+        assert_eq!((e.line, e.column), (3, 6));
+        match e.reason {
+            ObjErrorReason::InvalidFloat(ref s) => assert_eq!(s, "x"),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+
+        let e = strict("newmtl a\nKs 1 1\n");
+        assert_eq!((e.line, e.column), (2, 7));
+        match e.reason {
+            ObjErrorReason::MissingValues { expected: 3, found: 2 } => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+
+        let e = strict("  Kd 1 1 1\n");
+        assert_eq!((e.line, e.column), (1, 3));
+        match e.reason {
+            ObjErrorReason::StatementBeforeNewmtl(ref s) => assert_eq!(s, "Kd"),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn lenient_mode_skips_bad_statements() {
+        let src = "newmtl a\nd\nNs oops\nKd 0.5\n";
+        let mut warnings = Vec::new();
+        let materials = parse(src.as_bytes(), Path::new(""), ParseMode::Lenient, &mut warnings).unwrap();
+        assert_eq!(materials[0].diffuse, [0.5, 0.5, 0.5]);
+        assert_eq!(materials[0].dissolve, 1.0);
+        assert_eq!(warnings.iter().map(|w| w.line).collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
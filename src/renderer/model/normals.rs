@@ -0,0 +1,188 @@
+use crate::renderer::model::mesh::{key, Mesh, Semantic};
+use crate::math::Vec3;
+use std::collections::HashMap;
+
+//One normal per triangle. Vertices shared by faces pointing different ways
+//are split, coplanar neighbours keep sharing theirs.
+pub fn flat(mesh: &mut Mesh) -> () {
+    let faces = face_normals(mesh);
+    let corners: Vec<Vec3> = (0..mesh.indices.len()).map(|c| faces[c / 3]).collect();
+    apply(mesh, &corners);
+}
+
+//Averages the normals of the faces around each position, weighted by the
+//angle of the face at that corner. Faces more than crease_angle radians
+//apart don't smooth into each other, so hard edges stay hard. Vertices that
+//were split for another attribute (e.g. a UV seam) still smooth across it.
+//With smoothing groups only faces of the same group are averaged, and faces
+//in group 0 stay flat.
+pub fn smooth(mesh: &mut Mesh, crease_angle: f32) -> () {
+    let faces = face_normals(mesh);
+    let angles = corner_angles(mesh);
+    let min_cos = crease_angle.cos();
+    let groups = &mesh.smoothing_groups;
+    let same_group = |a: usize, b: usize| match groups.get(a) {
+        None => true,
+        Some(0) => a == b,
+        Some(group) => groups[b] == *group,
+    };
+
+    let mut around = HashMap::<[u32;3], Vec<usize>>::new();
+    for (corner, &idx) in mesh.indices.iter().enumerate() {
+        around.entry(key(mesh.position(idx))).or_default().push(corner);
+    }
+
+    let corners: Vec<Vec3> = mesh.indices.iter().enumerate().map(|(corner, &idx)| {
+        let own = faces[corner / 3];
+        let mut sum = Vec3::zero();
+        for &other in around[&key(mesh.position(idx))].iter() {
+            if !same_group(corner / 3, other / 3) {
+                continue;
+            }
+            let face = faces[other / 3];
+            //Degenerate faces take whatever their neighbours agree on
+            if face == Vec3::zero() || (own != Vec3::zero() && own.dot(face) < min_cos) {
+                continue;
+            }
+            sum += face * angles[other];
+        }
+        let normal = sum.normalize();
+        if normal == Vec3::zero() { own } else { normal }
+    }).collect();
+    apply(mesh, &corners);
+}
+
+//Unit normal per triangle, zero for degenerate ones
+pub(crate) fn face_normals(mesh: &Mesh) -> Vec<Vec3> {
+    mesh.indices.chunks(3).map(|t| {
+        let (a, b, c) = (mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2]));
+        (b - a).cross(c - a).normalize()
+    }).collect()
+}
+
+//Interior angle of the triangle at every corner, in radians
+pub(crate) fn corner_angles(mesh: &Mesh) -> Vec<f32> {
+    let mut angles = Vec::with_capacity(mesh.indices.len());
+    for t in mesh.indices.chunks(3) {
+        let p = [mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])];
+        for i in 0..3 {
+            let to_next = p[(i + 1) % 3] - p[i];
+            let to_prev = p[(i + 2) % 3] - p[i];
+            //atan2 stays accurate for the thin triangles where acos doesn't
+            angles.push(to_next.cross(to_prev).length().atan2(to_next.dot(to_prev)));
+        }
+    }
+    angles
+}
+
+//Writes one normal per corner, splitting vertices whose corners disagree
+fn apply(mesh: &mut Mesh, corners: &[Vec3]) -> () {
+    let keys: Vec<[u32;3]> = corners.iter().map(|n| key(*n)).collect();
+    let owners = mesh.split_vertices(&keys);
+    let mut data = Vec::with_capacity(owners.len() * 3);
+    for owner in owners {
+        let normal = owner.map_or(Vec3::zero(), |corner| corners[corner]);
+        data.extend_from_slice(&normal.to_array());
+    }
+    mesh.set_stream(Semantic::Normal, 3, data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::model::stl::{self, Normals};
+    use std::path::PathBuf;
+
+    fn cube() -> Mesh {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/test/stl/cube_ascii.stl");
+        let mut mesh = stl::load(&path, Normals::Smooth).unwrap();
+        mesh.streams.truncate(1);
+        mesh
+    }
+
+    fn normal(mesh: &Mesh, idx: u32) -> Vec3 {
+        let v = &mesh.stream(&Semantic::Normal).unwrap().data[idx as usize * 3..];
+        Vec3::new(v[0], v[1], v[2])
+    }
+
+    #[test]
+    fn flat_normals_split_the_cube_sides() {
+        let mut mesh = cube();
+        flat(&mut mesh);
+        assert_eq!(mesh.vertex_count(), 24);
+        assert_eq!(mesh.triangle_count(), 12);
+        let center = mesh.bounds.center();
+        for t in mesh.indices.chunks(3) {
+            let n = normal(&mesh, t[0]);
+            assert_eq!(n, normal(&mesh, t[1]));
+            assert_eq!(n, normal(&mesh, t[2]));
+            assert_approx_eq!(n.length(), 1.0);
+            //Axis aligned and pointing out
+            assert_approx_eq!(n.abs().x() + n.abs().y() + n.abs().z(), 1.0);
+            assert!(n.dot(mesh.position(t[0]) - center) > 0.0);
+        }
+    }
+
+    #[test]
+    fn crease_angle_decides_which_edges_stay_hard() {
+        let mut sharp = cube();
+        smooth(&mut sharp, 60f32.to_radians());
+        assert_eq!(sharp.vertex_count(), 24);
+
+        let mut soft = cube();
+        smooth(&mut soft, 100f32.to_radians());
+        assert_eq!(soft.vertex_count(), 8);
+        let center = soft.bounds.center();
+        for idx in 0..8 {
+            //Three sides meet at a right angle each, so every corner leans out evenly
+            let out = (soft.position(idx) - center).normalize();
+            assert_approx_eq!(normal(&soft, idx).dot(out), 1.0);
+        }
+    }
+
+    #[test]
+    fn smooths_across_seams_and_weights_by_angle() {
+        //A fan around the origin, the first triangle split off at a UV seam
+        let positions = vec![
+            0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,  0.0, 1.0, 0.0,  -1.0, 0.0, 1.0,
+        ];
+        let mut mesh = Mesh::new("fan", positions, vec![0, 1, 2, 3, 4, 5]);
+        smooth(&mut mesh, std::f32::consts::PI);
+        assert_eq!(mesh.vertex_count(), 6);
+        assert_eq!(normal(&mesh, 0), normal(&mesh, 3));
+
+        //Both faces meet the origin at 90 degrees, so they count the same
+        let expected = (Vec3::unit_z() + Vec3::new(1.0, 0.0, 1.0).normalize()).normalize();
+        assert_approx_eq!(normal(&mesh, 0).dot(expected), 1.0);
+        //Nothing else touches the far corners
+        assert_approx_eq!(normal(&mesh, 1).dot(Vec3::unit_z()), 1.0);
+    }
+
+    #[test]
+    fn smoothing_groups_limit_what_is_averaged() {
+        //Two faces hinged on the y axis, 45 degrees apart
+        let hinge = |groups: Vec<u32>| {
+            let positions = vec![0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0,  -1.0, 0.0, 1.0];
+            let mut mesh = Mesh::new("hinge", positions, vec![0, 1, 2, 0, 2, 3]);
+            mesh.smoothing_groups = groups;
+            smooth(&mut mesh, std::f32::consts::PI);
+            mesh
+        };
+        let left = Vec3::new(1.0, 0.0, 1.0).normalize();
+
+        let shared = hinge(vec![1, 1]);
+        assert_eq!(shared.vertex_count(), 4);
+        assert_approx_eq!(normal(&shared, 0).dot((Vec3::unit_z() + left).normalize()), 1.0);
+
+        for groups in [vec![1, 2], vec![0, 0], vec![3, 0]].iter() {
+            let split = hinge(groups.clone());
+            assert_eq!(split.vertex_count(), 6, "{:?}", groups);
+            for (tri, expected) in split.indices.chunks(3).zip([Vec3::unit_z(), left].iter()) {
+                for &idx in tri {
+                    assert_approx_eq!(normal(&split, idx), *expected);
+                }
+            }
+        }
+    }
+}
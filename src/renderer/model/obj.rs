@@ -1,73 +1,1712 @@
-use crate::renderer::model::Model;
+use crate::renderer::model::Submesh;
+use crate::renderer::model::mesh::{Mesh, Semantic};
+use crate::renderer::model::optimize::ranges;
+use crate::renderer::model::mtl::{self, Material};
 use crate::renderer::gpu::Attribute;
-use std::io::{self, BufReader, BufRead};
+use crate::renderer::model::triangulate::triangulate;
+use crate::math::Vec3;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::io::{self, BufRead, BufWriter, Write};
 use std::fs::File;
-use std::path::Path;
-
-pub fn load(path: &Path) -> io::Result<Model> {
-    let obj_file = File::open(path)?;
-    let obj_file = BufReader::new(obj_file);
-
-    let mut vertices = Vec::<gl::types::GLfloat>::new();
-    let mut indices = Vec::<u32>::new();
-    let mut vert_cnt = 0;
-    for line in obj_file.lines() {
-        match line {
-            Ok(line) => {
-                let mut v = false;
-                let mut f = false;
-                let mut w = false;
-                for (pos, component) in line.split(' ').enumerate() {
-                    if pos == 0 {
-                        if component == "v" {
-                            v = true;
-                            f = false;
-                        } else if component == "f" {
-                            v = false;
-                            f = true;
-                        } else {
-                            break;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+//Every o or g name in the file becomes one mesh, all sharing `layout`.
+//Each vertex is a position, followed by a texture coordinate if any face
+//referenced one, a normal if any face referenced one and an rgb color if
+//any position carried one.
+pub struct ObjData {
+    pub meshes        : Vec<ObjMesh>,
+    pub layout        : Vec<Attribute>,
+    pub has_texcoords : bool,
+    pub has_normals   : bool,
+    pub has_colors    : bool,
+    //mtllib file names as written in the file, relative to the .obj
+    pub material_libs : Vec<String>,
+    //Filled in by parse_file, parse alone does not touch the filesystem
+    pub materials     : Vec<Material>,
+    //Lines skipped in ParseMode::Lenient and problems that never fail a
+    //load, like a missing mtllib
+    pub warnings      : Vec<ObjError>,
+}
+
+//Interleaved vertex data as laid out by ObjData::layout, to_meshes splits it
+//into streams. Indices are grouped by material, one submesh per usemtl name.
+pub struct ObjMesh {
+    pub name             : String,
+    pub vertices         : Vec<gl::types::GLfloat>,
+    pub indices          : Vec<u32>,
+    pub submeshes        : Vec<Submesh>,
+    //The s group of every triangle, 0 when smoothing is off
+    pub smoothing_groups : Vec<u32>,
+    //Whether any face of this mesh referenced a vt or vn. The vertices
+    //still follow ObjData::layout, with zeros where nothing was referenced.
+    pub has_texcoords    : bool,
+    pub has_normals      : bool,
+}
+
+//Faces before the first o or g statement
+pub const DEFAULT_MESH_NAME: &str = "default";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseMode {
+    //Fail on the first malformed line
+    Strict,
+    //Skip malformed lines and collect them in ObjData::warnings
+    Lenient,
+}
+
+#[derive(Debug)]
+pub enum ObjErrorReason {
+    Io(io::Error),
+    InvalidFloat(String),
+    InvalidIndex(String),
+    ZeroIndex,
+    IndexOutOfRange { index: i32, count: usize },
+    InvalidSmoothingGroup(String),
+    MissingValues { expected: usize, found: usize },
+    TooFewFaceVertices(usize),
+    UndefinedMaterial(String),
+    StatementBeforeNewmtl(String),
+}
+
+//Shared by the .obj and .mtl parsers. Line and column are one based,
+//0 means the error is not tied to a line or column.
+#[derive(Debug)]
+pub struct ObjError {
+    pub path   : Option<PathBuf>,
+    pub line   : usize,
+    pub column : usize,
+    pub reason : ObjErrorReason,
+}
+
+impl ObjError {
+    pub fn new(line: usize, column: usize, reason: ObjErrorReason) -> Self {
+        ObjError {
+            path: None,
+            line,
+            column,
+            reason,
+        }
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ObjErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjErrorReason::Io(e) => write!(f, "{}", e),
+            ObjErrorReason::InvalidFloat(s) => write!(f, "could not parse '{}' as a float", s),
+            ObjErrorReason::InvalidIndex(s) => write!(f, "could not parse '{}' as an index", s),
+            ObjErrorReason::ZeroIndex => write!(f, "indices start at 1, found 0"),
+            ObjErrorReason::IndexOutOfRange { index, count } => {
+                write!(f, "index {} is out of range, {} elements are defined so far", index, count)
+            },
+            ObjErrorReason::InvalidSmoothingGroup(s) => {
+                write!(f, "expected a smoothing group number or off, found '{}'", s)
+            },
+            ObjErrorReason::MissingValues { expected, found } => {
+                write!(f, "expected {} values, found {}", expected, found)
+            },
+            ObjErrorReason::TooFewFaceVertices(n) => {
+                write!(f, "a face needs at least 3 vertices, found {}", n)
+            },
+            ObjErrorReason::UndefinedMaterial(name) => {
+                write!(f, "material '{}' is not defined in any mtllib", name)
+            },
+            ObjErrorReason::StatementBeforeNewmtl(s) => write!(f, "'{}' before the first newmtl", s),
+        }
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        if self.line > 0 {
+            write!(f, "{}:", self.line)?;
+        }
+        if self.column > 0 {
+            write!(f, "{}:", self.column)?;
+        }
+        if self.path.is_some() || self.line > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self.reason {
+            ObjErrorReason::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+//Column and reason for a bad line, the caller knows the line number
+pub(super) type LineResult<T> = Result<T, (usize, ObjErrorReason)>;
+
+//Indices into the v, vt and vn lists, zero based
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    v  : u32,
+    vt : Option<u32>,
+    vn : Option<u32>,
+}
+
+//One mesh per o or g statement, carrying every material of the file
+pub fn load(path: &Path) -> Result<Vec<Mesh>, ObjError> {
+    load_with(path, ParseMode::Strict)
+}
+
+pub fn load_with(path: &Path, mode: ParseMode) -> Result<Vec<Mesh>, ObjError> {
+    let data = parse_file(path, mode)?;
+    for mesh in data.meshes.iter() {
+        trace!("{}: {} floats, {} indices", mesh.name, mesh.vertices.len(), mesh.indices.len());
+    }
+    Ok(data.to_meshes())
+}
+
+impl ObjData {
+    pub fn to_meshes(&self) -> Vec<Mesh> {
+        let mut semantics = vec![(Semantic::Position, 3)];
+        if self.has_texcoords {
+            semantics.push((Semantic::TexCoord(0), 2));
+        }
+        if self.has_normals {
+            semantics.push((Semantic::Normal, 3));
+        }
+        if self.has_colors {
+            semantics.push((Semantic::Color(0), 3));
+        }
+        //Files that never turn smoothing on would otherwise come out all flat
+        let has_smoothing = self.meshes.iter().any(|m| m.smoothing_groups.iter().any(|&group| group != 0));
+        self.meshes.iter().map(|obj_mesh| {
+            let mut mesh = Mesh::from_interleaved(&obj_mesh.name, &obj_mesh.vertices, &semantics, obj_mesh.indices.clone());
+            //Parts that never referenced a vt or vn don't get the zero filled stream
+            mesh.streams.retain(|stream| match stream.semantic {
+                Semantic::TexCoord(_) => obj_mesh.has_texcoords,
+                Semantic::Normal => obj_mesh.has_normals,
+                _ => true,
+            });
+            mesh.submeshes = obj_mesh.submeshes.clone();
+            if has_smoothing {
+                mesh.smoothing_groups = obj_mesh.smoothing_groups.clone();
+            }
+            //@Inefficient every part of a file gets its own copy of the materials
+            mesh.materials = self.materials.clone();
+            mesh
+        }).collect()
+    }
+}
+
+//Parses the .obj and loads every mtllib it references. A material library
+//that cannot be opened only produces a warning, malformed ones follow `mode`.
+pub fn parse_file(path: &Path, mode: ParseMode) -> Result<ObjData, ObjError> {
+    parse_file_with_progress(path, mode, &|_| ())
+}
+
+//See parse_bytes for how `progress` is reported
+pub fn parse_file_with_progress(
+    path: &Path,
+    mode: ParseMode,
+    progress: &(dyn Fn(f32) + Sync),
+) -> Result<ObjData, ObjError> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| ObjError::new(0, 0, ObjErrorReason::Io(e)).with_path(path))?;
+    let mut data = parse_bytes(&bytes, mode, progress).map_err(|e| e.with_path(path))?;
+    fill_paths(&mut data.warnings, path);
+
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    for lib in data.material_libs.iter() {
+        let lib_path = base_dir.join(lib);
+        let mut materials = match mtl::load(&lib_path, mode, &mut data.warnings) {
+            Ok(materials) => materials,
+            Err(ObjError { reason: ObjErrorReason::Io(e), line: 0, .. }) => {
+                let warning = ObjError::new(0, 0, ObjErrorReason::Io(e)).with_path(&lib_path);
+                warn!("Could not load material library: {}", warning);
+                data.warnings.push(warning);
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+        fill_paths(&mut data.warnings, &lib_path);
+        data.materials.append(&mut materials);
+    }
+
+    let materials = &data.materials;
+    let warnings = &mut data.warnings;
+    for submesh in data.meshes.iter_mut().flat_map(|mesh| mesh.submeshes.iter_mut()) {
+        submesh.material = submesh.material_name.as_ref().and_then(|name| {
+            let idx = materials.iter().position(|m| &m.name == name);
+            if idx.is_none() {
+                let warning = ObjError::new(0, 0, ObjErrorReason::UndefinedMaterial(name.clone()))
+                    .with_path(path);
+                warn!("{}", warning);
+                warnings.push(warning);
+            }
+            idx
+        });
+    }
+    Ok(data)
+}
+
+fn fill_paths(errors: &mut [ObjError], path: &Path) -> () {
+    for e in errors.iter_mut().filter(|e| e.path.is_none()) {
+        e.path = Some(path.to_path_buf());
+    }
+}
+
+//Either returns the error of a bad line or, in lenient mode, logs it and
+//keeps it as a warning
+pub(super) fn handle_line_error(
+    mode: ParseMode,
+    line: usize,
+    result: LineResult<()>,
+    warnings: &mut Vec<ObjError>,
+) -> Result<(), ObjError> {
+    if let Err((column, reason)) = result {
+        let e = ObjError::new(line, column, reason);
+        match mode {
+            ParseMode::Strict => return Err(e),
+            ParseMode::Lenient => {
+                warn!("Skipping line: {}", e);
+                warnings.push(e);
+            },
+        }
+    }
+    Ok(())
+}
+
+//Statements are tokenized in chunks of about this many bytes, each chunk
+//on whichever worker thread picks it up
+const CHUNK_SIZE: usize = 1 << 20;
+
+//Not streaming: the whole file is read into memory first and only then
+//split into chunks for the worker threads. parse_bytes skips the copy for
+//callers that already have the bytes, e.g. from a memory map.
+pub fn parse<R: BufRead>(mut obj_file: R, mode: ParseMode) -> Result<ObjData, ObjError> {
+    let mut bytes = Vec::new();
+    obj_file.read_to_end(&mut bytes).map_err(|e| ObjError::new(0, 0, ObjErrorReason::Io(e)))?;
+    parse_bytes(&bytes, mode, &|_| ())
+}
+
+//`progress` goes from 0 to 1, the first half while chunks are tokenized
+//and the second half while they are merged into meshes. It can be called
+//from any of the worker threads.
+pub fn parse_bytes(bytes: &[u8], mode: ParseMode, progress: &(dyn Fn(f32) + Sync)) -> Result<ObjData, ObjError> {
+    parse_chunked(bytes, mode, progress, CHUNK_SIZE)
+}
+
+fn parse_chunked(
+    bytes: &[u8],
+    mode: ParseMode,
+    progress: &(dyn Fn(f32) + Sync),
+    chunk_size: usize,
+) -> Result<ObjData, ObjError> {
+    let total = bytes.len().max(1) as f32;
+    let tokenized = AtomicUsize::new(0);
+    //Chunks come back in file order no matter which thread finished first
+    let chunks: Vec<Chunk> = split_chunks(bytes, chunk_size)
+        .into_par_iter()
+        .map(|range| {
+            let chunk = tokenize(&bytes[range], mode);
+            let done = tokenized.fetch_add(chunk.bytes.len(), Ordering::Relaxed) + chunk.bytes.len();
+            progress(done as f32 / total * 0.5);
+            chunk
+        })
+        .collect();
+
+    let mut builder = Builder {
+        positions     : Vec::new(),
+        colors        : Vec::new(),
+        texcoords     : Vec::new(),
+        normals       : Vec::new(),
+        material_libs : Vec::new(),
+        meshes        : vec![ParsedMesh { name: DEFAULT_MESH_NAME.to_string(), groups: Vec::new() }],
+        current_mesh  : 0,
+        material      : None,
+        current_group : None,
+        smoothing     : 0,
+        face          : Vec::new(),
+    };
+    let has_colors = chunks.iter().any(|chunk| chunk.has_colors);
+    for chunk in chunks.iter() {
+        builder.positions.extend_from_slice(&chunk.positions);
+        builder.colors.extend_from_slice(&chunk.colors);
+        builder.texcoords.extend_from_slice(&chunk.texcoords);
+        builder.normals.extend_from_slice(&chunk.normals);
+    }
+
+    //Statements are replayed in file order so relative indices, groups and
+    //errors come out exactly as if the file was read front to back
+    let mut warnings = Vec::<ObjError>::new();
+    let mut offsets = [0;3];
+    let mut line_offset = 0;
+    let mut merged = 0;
+    for chunk in chunks.into_iter() {
+        let Chunk { bytes: chunk_bytes, face_indices, statements, line_cnt, .. } = chunk;
+        for statement in statements.into_iter() {
+            let line = line_offset + statement.line + 1;
+            let result = builder.apply(chunk_bytes, &face_indices, statement, offsets);
+            handle_line_error(mode, line, result, &mut warnings)?;
+        }
+        offsets[0] += chunk.positions.len();
+        offsets[1] += chunk.texcoords.len();
+        offsets[2] += chunk.normals.len();
+        line_offset += line_cnt;
+        merged += chunk_bytes.len();
+        progress(0.5 + merged as f32 / total * 0.5);
+    }
+
+    let Builder { positions, colors, texcoords, normals, material_libs, meshes, .. } = builder;
+
+    let face_vertices = || meshes.iter()
+        .flat_map(|mesh| mesh.groups.iter())
+        .flat_map(|(_, fvs, _)| fvs.iter());
+    let has_texcoords = face_vertices().any(|fv| fv.vt.is_some());
+    let has_normals = face_vertices().any(|fv| fv.vn.is_some());
+
+    let meshes = meshes.par_iter()
+        .filter(|mesh| mesh.groups.iter().any(|(_, fvs, _)| !fvs.is_empty()))
+        .map(|mesh| {
+            //Every distinct v/vt/vn triple becomes one vertex. Indices were
+            //range checked while merging.
+            let mut vertices = Vec::<gl::types::GLfloat>::new();
+            let mut indices = Vec::<u32>::new();
+            let mut submeshes = Vec::<Submesh>::new();
+            let mut smoothing_groups = Vec::<u32>::new();
+            let mut unique = HashMap::<FaceVertex, u32, BuildHasherDefault<FxHasher>>::default();
+            let mut vertex_cnt = 0;
+            let mesh_faces = || mesh.groups.iter().flat_map(|(_, fvs, _)| fvs.iter());
+            for (name, fvs, smoothing) in mesh.groups.iter().filter(|(_, fvs, _)| !fvs.is_empty()) {
+                submeshes.push(Submesh {
+                    material_name : name.clone(),
+                    material      : None,
+                    start         : indices.len(),
+                    count         : fvs.len(),
+                });
+                smoothing_groups.extend_from_slice(smoothing);
+                for fv in fvs.iter() {
+                    if let Some(idx) = unique.get(fv) {
+                        indices.push(*idx);
+                        continue;
+                    }
+                    vertices.extend_from_slice(&positions[fv.v as usize]);
+                    if has_texcoords {
+                        match fv.vt {
+                            Some(vt) => vertices.extend_from_slice(&texcoords[vt as usize]),
+                            None => vertices.extend_from_slice(&[0.0, 0.0]),
+                        }
+                    }
+                    if has_normals {
+                        match fv.vn {
+                            Some(vn) => vertices.extend_from_slice(&normals[vn as usize]),
+                            None => vertices.extend_from_slice(&[0.0, 0.0, 0.0]),
                         }
-                    } else {
-                        if v {
-                            if pos == 4 {
-                                w = true;
-                            }
-                            vertices.push(
-                                component.parse::<f32>().expect("Could not parse float")
-                            );
-                            vert_cnt += 1;
-                        } else if f {
-                            let v_idx = component
-                                .split('/')
-                                .collect::<Vec<&str>>()
-                                .get(0).expect("Could not split on /")
-                                .parse::<i32>().expect("Could not parse i32");
-                            if v_idx == 0 {
-                               panic!("Did not expect 0 in face specification"); 
-                            } else if v_idx < 0 {
-                                indices.push((vert_cnt - v_idx) as u32);
-                            } else {
-                                indices.push((v_idx - 1) as u32);
-                            }
+                    }
+                    if has_colors {
+                        vertices.extend_from_slice(&colors[fv.v as usize]);
+                    }
+                    unique.insert(*fv, vertex_cnt);
+                    indices.push(vertex_cnt);
+                    vertex_cnt += 1;
+                }
+            }
+            ObjMesh {
+                name: mesh.name.clone(),
+                vertices,
+                indices,
+                submeshes,
+                smoothing_groups,
+                has_texcoords : mesh_faces().any(|fv| fv.vt.is_some()),
+                has_normals   : mesh_faces().any(|fv| fv.vn.is_some()),
+            }
+        })
+        .collect();
+
+    Ok(
+        ObjData {
+            meshes,
+            layout: layout(has_texcoords, has_normals, has_colors),
+            has_texcoords,
+            has_normals,
+            has_colors,
+            material_libs,
+            materials: Vec::new(),
+            warnings,
+        }
+    )
+}
+
+//FxHash. The keys are a few small integers, SipHash was most of the time
+//spent assembling large meshes.
+#[derive(Default)]
+pub(crate) struct FxHasher {
+    hash: u64,
+}
+
+impl FxHasher {
+    fn add(&mut self, word: u64) -> () {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) -> () {
+        for b in bytes.iter() {
+            self.add(*b as u64);
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) -> () {
+        self.add(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) -> () {
+        self.add(i);
+    }
+
+    fn write_usize(&mut self, i: usize) -> () {
+        self.add(i as u64);
+    }
+
+    fn write_isize(&mut self, i: isize) -> () {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+//Splits after newlines so no statement, continued or not, straddles two
+//chunks
+fn split_chunks(bytes: &[u8], chunk_size: usize) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let mut end = (start + chunk_size.max(1)).min(bytes.len());
+        while end < bytes.len() {
+            match bytes[end..].iter().position(|b| *b == b'\n') {
+                Some(newline) => {
+                    end += newline + 1;
+                    if !continues_on_next_line(&bytes[start..end - 1]) {
+                        break;
+                    }
+                },
+                None => end = bytes.len(),
+            }
+        }
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+//Whether the last line of `bytes` ends in a backslash outside of a comment
+fn continues_on_next_line(bytes: &[u8]) -> bool {
+    let line_start = bytes.iter().rposition(|b| *b == b'\n').map_or(0, |newline| newline + 1);
+    let mut line = &bytes[line_start..];
+    if let Some(comment) = line.iter().position(|b| *b == b'#') {
+        line = &line[..comment];
+    }
+    line.iter().rev().find(|b| !is_blank(**b)) == Some(&b'\\')
+}
+
+fn is_blank(b: u8) -> bool {
+    b == b' ' || b == b'\t' || b == b'\r' || b == b'\x0b' || b == b'\x0c'
+}
+
+//Everything a chunk parses to without knowing what came before it. Face
+//indices are kept as written and resolved while merging.
+struct Chunk<'a> {
+    bytes        : &'a [u8],
+    positions    : Vec<[f32;3]>,
+    //Parallel to positions, white unless the v line had a color
+    colors       : Vec<[f32;3]>,
+    has_colors   : bool,
+    texcoords    : Vec<[f32;2]>,
+    normals      : Vec<[f32;3]>,
+    //v, vt and vn as written, 0 for a missing vt or vn
+    face_indices : Vec<[i32;3]>,
+    statements   : Vec<Statement>,
+    line_cnt     : usize,
+}
+
+struct Statement {
+    //Zero based within the chunk
+    line  : usize,
+    //Byte offset of the keyword within the chunk
+    start : usize,
+    kind  : StatementKind,
+}
+
+enum StatementKind {
+    //`len` entries of face_indices from `first`. `counts` are how many v,
+    //vt and vn this chunk had when the face was read.
+    Face { first: usize, len: usize, counts: [usize;3] },
+    Object(String),
+    UseMaterial(String),
+    Smoothing(u32),
+    MaterialLibs(Vec<String>),
+    Error(usize, ObjErrorReason),
+}
+
+#[derive(Clone, Copy)]
+pub(super) struct Token<'a> {
+    pub(super) bytes  : &'a [u8],
+    pub(super) start  : usize,
+    pub(super) column : usize,
+}
+
+//Walks a chunk one token at a time without copying. Tokens never cross a
+//line unless it ends in a backslash. The .mtl parser uses it too.
+pub(super) struct Cursor<'a> {
+    bytes           : &'a [u8],
+    pos             : usize,
+    //Zero based, counting every physical line
+    pub(super) line : usize,
+    line_start      : usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Cursor { bytes, pos: 0, line: 0, line_start: 0 }
+    }
+
+    fn at(bytes: &'a [u8], pos: usize) -> Self {
+        let line_start = bytes[..pos].iter().rposition(|b| *b == b'\n').map_or(0, |newline| newline + 1);
+        Cursor { bytes, pos, line: 0, line_start }
+    }
+
+    //None at the end of the (logical) line
+    pub(super) fn next_token(&mut self) -> Option<Token<'a>> {
+        loop {
+            match self.bytes.get(self.pos) {
+                None | Some(b'\n') => return None,
+                Some(b'#') => {
+                    self.skip_to_newline();
+                    return None;
+                },
+                Some(b'\\') if self.continues(self.pos + 1) => {
+                    self.skip_to_newline();
+                    if self.pos < self.bytes.len() {
+                        self.pos += 1;
+                        self.line += 1;
+                        self.line_start = self.pos;
+                    }
+                },
+                Some(b) if is_blank(*b) => self.pos += 1,
+                Some(_) => break,
+            }
+        }
+        let start = self.pos;
+        while let Some(&b) = self.bytes.get(self.pos) {
+            if is_blank(b) || b == b'\n' || b == b'#' || (b == b'\\' && self.continues(self.pos + 1)) {
+                break;
+            }
+            self.pos += 1;
+        }
+        Some(
+            Token {
+                bytes  : &self.bytes[start..self.pos],
+                start,
+                column : start - self.line_start + 1,
+            }
+        )
+    }
+
+    //Skips whatever is left of the statement and moves to the next line.
+    //False at the end of the chunk.
+    pub(super) fn next_line(&mut self) -> bool {
+        while self.next_token().is_some() {}
+        if self.pos >= self.bytes.len() {
+            return false;
+        }
+        self.pos += 1;
+        self.line += 1;
+        self.line_start = self.pos;
+        true
+    }
+
+    pub(super) fn column(&self) -> usize {
+        self.pos - self.line_start + 1
+    }
+
+    //A backslash continues the line if nothing but blanks or a comment
+    //follow it
+    fn continues(&self, pos: usize) -> bool {
+        match self.bytes[pos..].iter().find(|b| !is_blank(**b)) {
+            None | Some(b'\n') | Some(b'#') => true,
+            Some(_) => false,
+        }
+    }
+
+    fn skip_to_newline(&mut self) -> () {
+        self.pos += self.bytes[self.pos..].iter().position(|b| *b == b'\n').unwrap_or(self.bytes.len() - self.pos);
+    }
+}
+
+fn tokenize(bytes: &[u8], mode: ParseMode) -> Chunk<'_> {
+    let mut chunk = Chunk {
+        bytes,
+        positions    : Vec::new(),
+        colors       : Vec::new(),
+        has_colors   : false,
+        texcoords    : Vec::new(),
+        normals      : Vec::new(),
+        face_indices : Vec::new(),
+        statements   : Vec::new(),
+        line_cnt     : 0,
+    };
+    let mut cursor = Cursor::new(bytes);
+    loop {
+        if let Some(keyword) = cursor.next_token() {
+            let line = cursor.line;
+            let kind = match chunk.parse_statement(keyword.bytes, &mut cursor) {
+                Ok(Some(kind)) => Some(kind),
+                Ok(None) => None,
+                Err((column, reason)) => Some(StatementKind::Error(column, reason)),
+            };
+            if let Some(kind) = kind {
+                let is_error = matches!(kind, StatementKind::Error(..));
+                chunk.statements.push(Statement { line, start: keyword.start, kind });
+                //Merging stops at the first error anyway
+                if is_error && mode == ParseMode::Strict {
+                    break;
+                }
+            }
+        }
+        if !cursor.next_line() {
+            break;
+        }
+    }
+    chunk.line_cnt = bytes.iter().filter(|b| **b == b'\n').count();
+    chunk
+}
+
+impl<'a> Chunk<'a> {
+    //Nothing is added to the chunk unless the whole statement parses.
+    //Vertex data is stored directly, everything else is returned.
+    fn parse_statement(&mut self, keyword: &[u8], cursor: &mut Cursor<'a>) -> LineResult<Option<StatementKind>> {
+        match keyword {
+            b"v" => {
+                //x y z, x y z w, x y z r g b or x y z w r g b. w only matters
+                //for rational curves so it is dropped.
+                let mut c = [0.;7];
+                let cnt = read_floats(cursor, &mut c, 3)?;
+                let color = match cnt {
+                    6 => Some([c[3], c[4], c[5]]),
+                    7 => Some([c[4], c[5], c[6]]),
+                    _ => None,
+                };
+                self.positions.push([c[0], c[1], c[2]]);
+                self.colors.push(color.unwrap_or([1.0, 1.0, 1.0]));
+                self.has_colors |= color.is_some();
+            },
+            b"vt" => {
+                //v defaults to 0, the optional w is not used
+                let mut c = [0.;2];
+                read_floats(cursor, &mut c, 1)?;
+                self.texcoords.push(c);
+            },
+            b"vn" => {
+                let mut c = [0.;3];
+                read_floats(cursor, &mut c, 3)?;
+                self.normals.push(c);
+            },
+            b"f" => {
+                let first = self.face_indices.len();
+                while let Some(token) = cursor.next_token() {
+                    match parse_face_indices(token) {
+                        Ok(indices) => self.face_indices.push(indices),
+                        Err(e) => {
+                            self.face_indices.truncate(first);
+                            return Err(e);
                         }
                     }
-                    
                 }
+                let len = self.face_indices.len() - first;
+                if len < 3 {
+                    self.face_indices.truncate(first);
+                    return Err((1, ObjErrorReason::TooFewFaceVertices(len)));
+                }
+                let counts = [self.positions.len(), self.texcoords.len(), self.normals.len()];
+                return Ok(Some(StatementKind::Face { first, len, counts }));
+            },
+            b"o" | b"g" => {
+                let mut name = read_name(cursor);
+                if name.is_empty() {
+                    name = DEFAULT_MESH_NAME.to_string();
+                }
+                return Ok(Some(StatementKind::Object(name)));
+            },
+            b"usemtl" => return Ok(Some(StatementKind::UseMaterial(read_name(cursor)))),
+            b"mtllib" => {
+                let mut libs = Vec::new();
+                while let Some(token) = cursor.next_token() {
+                    libs.push(String::from_utf8_lossy(token.bytes).into_owned());
+                }
+                return Ok(Some(StatementKind::MaterialLibs(libs)));
             },
-            _ => continue,
+            b"s" => {
+                let group = match cursor.next_token() {
+                    None => 0,
+                    Some(token) if token.bytes == b"off" => 0,
+                    Some(token) => parse_ascii::<u32>(token.bytes).ok_or_else(|| {
+                        (token.column, ObjErrorReason::InvalidSmoothingGroup(lossy(token.bytes)))
+                    })?,
+                };
+                return Ok(Some(StatementKind::Smoothing(group)));
+            },
+            _ => (),
         }
+        Ok(None)
     }
-    let layout: Vec<Attribute> = vec![Attribute {
-        width: 3,
-        stride: 3 * std::mem::size_of::<gl::types::GLfloat>(),
-        start_idx: 0,
-        ty: gl::FLOAT,
-    }];
+}
 
-    trace!("DATA: {:?}\n INDICES: {:?}", vertices, indices);
+//Fills `out` from the leading tokens, extra tokens are ignored. At least
+//`required` of them have to be present. Returns how many were read.
+pub(super) fn read_floats(cursor: &mut Cursor, out: &mut [f32], required: usize) -> LineResult<usize> {
+    let mut found = 0;
+    while found < out.len() {
+        let token = match cursor.next_token() {
+            Some(token) => token,
+            None => break,
+        };
+        out[found] = parse_ascii::<f32>(token.bytes)
+            .ok_or_else(|| (token.column, ObjErrorReason::InvalidFloat(lossy(token.bytes))))?;
+        found += 1;
+    }
+    if found < required {
+        return Err((cursor.column(), ObjErrorReason::MissingValues { expected: required, found }));
+    }
+    Ok(found)
+}
 
-    Ok(
-        Model::from_data_and_layout(&vertices, &indices, &layout)
-    )
+//The rest of the statement with runs of whitespace collapsed
+pub(super) fn read_name(cursor: &mut Cursor) -> String {
+    let mut name = String::new();
+    while let Some(token) = cursor.next_token() {
+        if !name.is_empty() {
+            name.push(' ');
+        }
+        name.push_str(&String::from_utf8_lossy(token.bytes));
+    }
+    name
+}
+
+//Handles v, v/vt, v//vn and v/vt/vn
+fn parse_face_indices(token: Token) -> LineResult<[i32;3]> {
+    let mut indices = [0;3];
+    let mut column = token.column;
+    for (i, part) in token.bytes.split(|b| *b == b'/').take(3).enumerate() {
+        if !part.is_empty() || i == 0 {
+            let idx = parse_int(part).ok_or_else(|| (column, ObjErrorReason::InvalidIndex(lossy(part))))?;
+            if idx == 0 {
+                return Err((column, ObjErrorReason::ZeroIndex));
+            }
+            indices[i] = idx;
+        }
+        column += part.len() + 1;
+    }
+    Ok(indices)
+}
+
+//Column of the v, vt or vn (`part`) of the `vertex`th entry of the face
+//statement at `start`. Only needed for errors, so it tokenizes again.
+fn face_index_column(bytes: &[u8], start: usize, vertex: usize, part: usize) -> usize {
+    let mut cursor = Cursor::at(bytes, start);
+    cursor.next_token();
+    for _ in 0..vertex {
+        cursor.next_token();
+    }
+    match cursor.next_token() {
+        Some(token) => token.column + token.bytes
+            .split(|b| *b == b'/')
+            .take(part)
+            .map(|p| p.len() + 1)
+            .sum::<usize>(),
+        None => 0,
+    }
+}
+
+fn parse_int(bytes: &[u8]) -> Option<i32> {
+    let (negative, digits) = match bytes.first() {
+        Some(b'-') => (true, &bytes[1..]),
+        Some(b'+') => (false, &bytes[1..]),
+        _ => (false, bytes),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: i32 = 0;
+    for b in digits.iter() {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add((b - b'0') as i32)?;
+    }
+    Some(if negative { -value } else { value })
+}
+
+pub(super) fn parse_ascii<T: std::str::FromStr>(bytes: &[u8]) -> Option<T> {
+    std::str::from_utf8(bytes).ok()?.parse::<T>().ok()
+}
+
+pub(super) fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+//OBJ indices are one based, negative ones count back from the most
+//recent element of the same kind
+fn resolve_index(idx: i32, cnt: usize) -> Result<u32, ObjErrorReason> {
+    let resolved = if idx < 0 {
+        cnt as i64 + idx as i64
+    } else {
+        idx as i64 - 1
+    };
+    if resolved < 0 || resolved >= cnt as i64 {
+        return Err(ObjErrorReason::IndexOutOfRange { index: idx, count: cnt });
+    }
+    Ok(resolved as u32)
+}
+
+//Applies the statements of every chunk in order
+struct Builder {
+    positions     : Vec<[f32;3]>,
+    colors        : Vec<[f32;3]>,
+    texcoords     : Vec<[f32;2]>,
+    normals       : Vec<[f32;3]>,
+    material_libs : Vec<String>,
+    meshes        : Vec<ParsedMesh>,
+    current_mesh  : usize,
+    //Set by usemtl, carries over into later o and g
+    material      : Option<String>,
+    //Index into the current mesh's groups, None after o, g or usemtl
+    current_group : Option<usize>,
+    smoothing     : u32,
+    //Scratch space for the face being resolved
+    face          : Vec<FaceVertex>,
+}
+
+struct ParsedMesh {
+    name   : String,
+    //Faces per usemtl name in order of first use, with the smoothing
+    //group of each triangle
+    groups : Vec<(Option<String>, Vec<FaceVertex>, Vec<u32>)>,
+}
+
+impl Builder {
+    //`offsets` are the number of v, vt and vn in earlier chunks
+    fn apply(
+        &mut self,
+        bytes: &[u8],
+        face_indices: &[[i32;3]],
+        statement: Statement,
+        offsets: [usize;3],
+    ) -> LineResult<()> {
+        match statement.kind {
+            StatementKind::Face { first, len, counts } => {
+                self.face.clear();
+                for (vertex, indices) in face_indices[first..first + len].iter().enumerate() {
+                    let mut resolved = [None;3];
+                    for part in 0..3 {
+                        if indices[part] == 0 {
+                            continue;
+                        }
+                        let idx = resolve_index(indices[part], offsets[part] + counts[part])
+                            .map_err(|reason| (face_index_column(bytes, statement.start, vertex, part), reason))?;
+                        resolved[part] = Some(idx);
+                    }
+                    self.face.push(FaceVertex {
+                        //The v index is always present
+                        v  : resolved[0].unwrap(),
+                        vt : resolved[1],
+                        vn : resolved[2],
+                    });
+                }
+                let group = self.current_group();
+                let (_, fvs, smoothing_groups) = &mut self.meshes[self.current_mesh].groups[group];
+                let before = fvs.len();
+                push_triangulated(&self.face, &self.positions, fvs);
+                smoothing_groups.resize(smoothing_groups.len() + (fvs.len() - before) / 3, self.smoothing);
+            },
+            StatementKind::Object(name) => {
+                //Coming back to a name adds to the mesh that is already there
+                self.current_mesh = match self.meshes.iter().position(|mesh| mesh.name == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.meshes.push(ParsedMesh { name, groups: Vec::new() });
+                        self.meshes.len() - 1
+                    }
+                };
+                self.current_group = None;
+            },
+            StatementKind::UseMaterial(name) => {
+                self.material = Some(name);
+                self.current_group = None;
+            },
+            StatementKind::Smoothing(group) => self.smoothing = group,
+            StatementKind::MaterialLibs(mut libs) => self.material_libs.append(&mut libs),
+            StatementKind::Error(column, reason) => return Err((column, reason)),
+        }
+        Ok(())
+    }
+
+    //Group of the current mesh for the current material
+    fn current_group(&mut self) -> usize {
+        if let Some(idx) = self.current_group {
+            return idx;
+        }
+        let groups = &mut self.meshes[self.current_mesh].groups;
+        let material = &self.material;
+        let idx = match groups.iter().position(|(name, _, _)| name == material) {
+            Some(idx) => idx,
+            None => {
+                groups.push((material.clone(), Vec::new(), Vec::new()));
+                groups.len() - 1
+            }
+        };
+        self.current_group = Some(idx);
+        idx
+    }
+}
+
+//Writes every mesh as an o statement. If any mesh has materials they go
+//to a .mtl next to `path` with the same file stem, otherwise no mtllib is
+//written. Floats are written with the shortest representation that reads
+//back to the same value, so loading the file again gives the same meshes.
+//Only the streams OBJ has room for are kept: positions, the first uv set,
+//normals and the first color set.
+pub fn save(path: &Path, meshes: &[Mesh]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut materials = Vec::<Material>::new();
+    for material in meshes.iter().flat_map(|mesh| mesh.materials.iter()) {
+        if !materials.iter().any(|m| m.name == material.name) {
+            materials.push(material.clone());
+        }
+    }
+    if !materials.is_empty() {
+        let mtl_path = path.with_extension("mtl");
+        mtl::save(&mtl_path, &materials)?;
+        //Both files are in the same directory, file_name cannot fail
+        writeln!(out, "mtllib {}", mtl_path.file_name().unwrap().to_string_lossy())?;
+    }
+
+    //A usemtl can't be taken back, so everything without a material goes
+    //first. Every mesh is opened there to keep the order they load back in,
+    //the submeshes with a material are added by opening it again later.
+    let mut smoothing = 0;
+    let mut bases = Vec::with_capacity(meshes.len());
+    let mut written = [0;3];
+    for mesh in meshes.iter() {
+        writeln!(out, "o {}", mesh.name)?;
+        let texcoords = mesh.stream(&Semantic::TexCoord(0));
+        let normals = mesh.stream(&Semantic::Normal);
+        let colors = mesh.stream(&Semantic::Color(0));
+        for v in 0..mesh.vertex_count() {
+            let p = mesh.position(v as u32);
+            match colors {
+                Some(colors) => {
+                    let c = &colors.data[v * colors.width..];
+                    writeln!(out, "v {} {} {} {} {} {}", p.x(), p.y(), p.z(), c[0], c[1], c[2])?;
+                },
+                None => writeln!(out, "v {} {} {}", p.x(), p.y(), p.z())?,
+            }
+            if let Some(texcoords) = texcoords {
+                let uv = &texcoords.data[v * texcoords.width..];
+                writeln!(out, "vt {} {}", uv[0], uv[1])?;
+            }
+            if let Some(normals) = normals {
+                let n = &normals.data[v * normals.width..];
+                writeln!(out, "vn {} {} {}", n[0], n[1], n[2])?;
+            }
+        }
+        //Every vertex gets its own v and, if the mesh has them, vt and vn,
+        //so one index offset per kind serves the whole mesh
+        let base = [
+            Some(written[0]),
+            texcoords.map(|_| written[1]),
+            normals.map(|_| written[2]),
+        ];
+        written[0] += mesh.vertex_count();
+        if texcoords.is_some() {
+            written[1] += mesh.vertex_count();
+        }
+        if normals.is_some() {
+            written[2] += mesh.vertex_count();
+        }
+        for (submesh, range) in submesh_ranges(mesh) {
+            if submesh.and_then(|s| s.material_name.as_ref()).is_none() {
+                write_faces(&mut out, mesh, range, base, &mut smoothing)?;
+            }
+        }
+        bases.push(base);
+    }
+
+    for (mesh, base) in meshes.iter().zip(bases) {
+        let mut opened = false;
+        for (submesh, range) in submesh_ranges(mesh) {
+            let name = match submesh.and_then(|s| s.material_name.as_ref()) {
+                Some(name) => name,
+                None => continue,
+            };
+            if !opened {
+                writeln!(out, "o {}", mesh.name)?;
+                opened = true;
+            }
+            writeln!(out, "usemtl {}", name)?;
+            write_faces(&mut out, mesh, range, base, &mut smoothing)?;
+        }
+    }
+    out.flush()
+}
+
+//Each submesh with its index range, or the whole mesh without one
+fn submesh_ranges(mesh: &Mesh) -> Vec<(Option<&Submesh>, (usize, usize))> {
+    let submeshes: Vec<Option<&Submesh>> = if mesh.submeshes.is_empty() {
+        vec![None]
+    } else {
+        mesh.submeshes.iter().map(Some).collect()
+    };
+    submeshes.into_iter().zip(ranges(&mesh.submeshes, mesh.indices.len())).collect()
+}
+
+//`base` is how many v, vt and vn came before the mesh, None for the kinds
+//it has none of
+fn write_faces<W: Write>(
+    out: &mut W,
+    mesh: &Mesh,
+    (start, count): (usize, usize),
+    base: [Option<usize>;3],
+    smoothing: &mut u32,
+) -> io::Result<()> {
+    for (i, tri) in mesh.indices[start..start + count].chunks(3).enumerate() {
+        let group = mesh.smoothing_groups.get(start / 3 + i).cloned().unwrap_or(0);
+        if group != *smoothing {
+            match group {
+                0 => writeln!(out, "s off")?,
+                _ => writeln!(out, "s {}", group)?,
+            }
+            *smoothing = group;
+        }
+        write!(out, "f")?;
+        for idx in tri.iter() {
+            let at = |base: Option<usize>| base.map(|base| base + *idx as usize + 1);
+            match (at(base[0]).unwrap(), at(base[1]), at(base[2])) {
+                (v, None, None) => write!(out, " {}", v)?,
+                (v, Some(vt), None) => write!(out, " {}/{}", v, vt)?,
+                (v, None, Some(vn)) => write!(out, " {}//{}", v, vn)?,
+                (v, Some(vt), Some(vn)) => write!(out, " {}/{}/{}", v, vt, vn)?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+//Quads and n-gons are split up so everything can be drawn as gl::TRIANGLES
+fn push_triangulated(face: &[FaceVertex], positions: &[[f32;3]], out: &mut Vec<FaceVertex>) -> () {
+    if face.len() <= 3 {
+        out.extend_from_slice(face);
+        return;
+    }
+    let points: Vec<Vec3> = face.iter()
+        .map(|fv| {
+            let p = positions[fv.v as usize];
+            Vec3::new(p[0], p[1], p[2])
+        })
+        .collect();
+    for tri in triangulate(&points) {
+        out.push(face[tri[0]]);
+        out.push(face[tri[1]]);
+        out.push(face[tri[2]]);
+    }
+}
+
+//Position at attribute 0, then texture coordinates, normals and colors if
+//present
+fn layout(has_texcoords: bool, has_normals: bool, has_colors: bool) -> Vec<Attribute> {
+    let float_size = std::mem::size_of::<gl::types::GLfloat>();
+    let mut widths = vec![3];
+    if has_texcoords {
+        widths.push(2);
+    }
+    if has_normals {
+        widths.push(3);
+    }
+    if has_colors {
+        widths.push(3);
+    }
+    let stride = widths.iter().sum::<usize>() * float_size;
+    let mut start_idx = 0;
+    widths.iter().map(|width| {
+        let attr = Attribute {
+            width: *width as u8,
+            stride,
+            start_idx,
+            ty: gl::FLOAT,
+        };
+        start_idx += width * float_size;
+        attr
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn fixture(name: &str) -> ObjData {
+        parse(BufReader::new(File::open(fixture_path(name)).unwrap()), ParseMode::Strict).unwrap()
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/test/obj").join(name)
+    }
+
+    fn position(data: &ObjData, idx: u32) -> Vec3 {
+        let stride = data.layout[0].stride / std::mem::size_of::<f32>();
+        let v = &data.meshes[0].vertices[idx as usize * stride..];
+        Vec3::new(v[0], v[1], v[2])
+    }
+
+    //Total area and +Z facing triangles, all fixtures lie in the xy plane
+    fn area_and_winding(data: &ObjData) -> (f32, bool) {
+        let mut area = 0.0;
+        let mut ccw = true;
+        for tri in data.meshes[0].indices.chunks(3) {
+            let (a, b, c) = (position(data, tri[0]), position(data, tri[1]), position(data, tri[2]));
+            let n = (b - a).cross(c - a);
+            area += n.length() * 0.5;
+            ccw &= n.z() > 0.0;
+        }
+        (area, ccw)
+    }
+
+    #[test]
+    fn triangle_passes_through() {
+        let data = fixture("triangle.obj");
+        assert_eq!(data.meshes[0].indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn quad_becomes_two_triangles() {
+        let data = fixture("quad.obj");
+        assert_eq!(data.meshes[0].indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(area_and_winding(&data), (1.0, true));
+    }
+
+    #[test]
+    fn pentagon_becomes_three_triangles() {
+        let data = fixture("pentagon.obj");
+        assert_eq!(data.meshes[0].indices.len(), 9);
+        let (area, ccw) = area_and_winding(&data);
+        assert!((area - 3.0).abs() < 1e-5);
+        assert!(ccw);
+    }
+
+    #[test]
+    fn concave_face_is_ear_clipped() {
+        let data = fixture("concave.obj");
+        //Six sided L shape with an area of 3
+        assert_eq!(data.meshes[0].indices.len(), 12);
+        let (area, ccw) = area_and_winding(&data);
+        assert!((area - 3.0).abs() < 1e-5);
+        assert!(ccw);
+    }
+
+    #[test]
+    fn mixed_faces_share_vertices() {
+        let data = fixture("mixed.obj");
+        //A quad and a triangle sharing an edge, with texture coordinates
+        assert_eq!(data.meshes[0].indices.len(), 9);
+        assert_eq!(data.meshes[0].vertices.len(), 5 * 5);
+    }
+
+    #[test]
+    fn faces_are_grouped_by_material() {
+        let data = parse_file(&fixture_path("materials.obj"), ParseMode::Strict).unwrap();
+        assert!(data.warnings.is_empty());
+        assert_eq!(data.material_libs, vec!["materials.mtl".to_string()]);
+        assert_eq!(data.materials.len(), 2);
+
+        //Both red triangles end up in one submesh ahead of the brick quad
+        let red = &data.meshes[0].submeshes[0];
+        assert_eq!(red.material_name.as_deref(), Some("red"));
+        assert_eq!((red.start, red.count), (0, 6));
+        assert_eq!(data.materials[red.material.unwrap()].diffuse, [1.0, 0.0, 0.0]);
+
+        let brick = &data.meshes[0].submeshes[1];
+        assert_eq!((brick.start, brick.count), (6, 6));
+        let brick = &data.materials[brick.material.unwrap()];
+        assert_eq!(brick.map_kd, Some(fixture_path("textures/brick.png")));
+        assert_eq!(data.meshes[0].submeshes.len(), 2);
+    }
+
+    #[test]
+    fn faces_without_usemtl_have_no_material() {
+        let data = fixture("quad.obj");
+        assert_eq!(data.meshes[0].submeshes, vec![Submesh {
+            material_name : None,
+            material      : None,
+            start         : 0,
+            count         : 6,
+        }]);
+    }
+
+    fn malformed(name: &str) -> ObjError {
+        let path = fixture_path("malformed").join(name);
+        match parse_file(&path, ParseMode::Strict) {
+            Ok(_) => panic!("{} should not parse", name),
+            Err(e) => {
+                assert_eq!(e.path, Some(path));
+                e
+            },
+        }
+    }
+
+    #[test]
+    fn bad_float_reports_line_and_column() {
+        let e = malformed("bad_float.obj");
+        assert_eq!((e.line, e.column), (2, 7));
+        match e.reason {
+            ObjErrorReason::InvalidFloat(ref s) => assert_eq!(s, "abc"),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+        assert!(e.to_string().ends_with("bad_float.obj:2:7: could not parse 'abc' as a float"));
+    }
+
+    #[test]
+    fn zero_index_is_an_error() {
+        let e = malformed("zero_index.obj");
+        assert_eq!((e.line, e.column), (4, 3));
+        match e.reason {
+            ObjErrorReason::ZeroIndex => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn index_past_the_end_is_an_error() {
+        let e = malformed("out_of_range.obj");
+        assert_eq!((e.line, e.column), (4, 7));
+        match e.reason {
+            ObjErrorReason::IndexOutOfRange { index: 4, count: 3 } => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn missing_position_component_is_an_error() {
+        let e = malformed("missing_values.obj");
+        assert_eq!(e.line, 2);
+        match e.reason {
+            ObjErrorReason::MissingValues { expected: 3, found: 2 } => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn face_needs_three_vertices() {
+        let e = malformed("short_face.obj");
+        assert_eq!(e.line, 3);
+        match e.reason {
+            ObjErrorReason::TooFewFaceVertices(2) => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn bad_texcoord_index_reports_its_column() {
+        let e = malformed("bad_texcoord_index.obj");
+        assert_eq!((e.line, e.column), (5, 9));
+        match e.reason {
+            ObjErrorReason::InvalidIndex(ref s) => assert_eq!(s, "x"),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn lenient_mode_skips_bad_lines() {
+        let path = fixture_path("malformed/mixed_errors.obj");
+        let data = parse_file(&path, ParseMode::Lenient).unwrap();
+        //Only the quad survives
+        assert_eq!(data.meshes[0].indices.len(), 6);
+        assert_eq!(area_and_winding(&data), (1.0, true));
+
+        let lines: Vec<usize> = data.warnings.iter().map(|w| w.line).collect();
+        assert_eq!(lines, vec![5, 9, 10, 11, 0]);
+        //The mtllib that could not be opened carries its own path
+        let missing_lib = data.warnings.last().unwrap();
+        assert_eq!(missing_lib.path, Some(fixture_path("malformed/does_not_exist.mtl")));
+        assert!(data.warnings[..4].iter().all(|w| w.path == Some(path.clone())));
+    }
+
+    #[test]
+    fn tabs_comments_and_continuations() {
+        let data = fixture("whitespace.obj");
+        assert!(data.has_texcoords);
+        assert_eq!(data.meshes[0].indices.len(), 6);
+        assert_eq!(area_and_winding(&data), (1.0, true));
+        //The continued v line keeps its w out of the vertex data
+        assert_eq!(position(&data, 2), Vec3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn continuation_keeps_the_first_line_number() {
+        let src = "v 0 0 0\nv 1 0 \\\n 0\nv 0 1 \\\n  x\n";
+        let e = parse(src.as_bytes(), ParseMode::Strict).err().unwrap();
+        //The bad float is on line 5 but the statement starts on line 4
+        assert_eq!(e.line, 4);
+        match e.reason {
+            ObjErrorReason::InvalidFloat(ref s) => assert_eq!(s, "x"),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn every_face_form_builds_its_own_layout() {
+        let head = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n";
+        let forms = [
+            ("f 1 2 3\n", false, false, 3),
+            ("f 1/1 2/2 3/3\n", true, false, 5),
+            ("f 1//1 2//1 3//1\n", false, true, 6),
+            ("f 1/1/1 2/2/1 3/3/1\n", true, true, 8),
+        ];
+        for &(face, has_texcoords, has_normals, stride) in forms.iter() {
+            let src = format!("{}{}", head, face);
+            let data = parse(src.as_bytes(), ParseMode::Strict).unwrap();
+            assert_eq!((data.has_texcoords, data.has_normals), (has_texcoords, has_normals), "{}", face);
+            assert_eq!(data.layout.len(), 1 + has_texcoords as usize + has_normals as usize);
+            assert_eq!(data.layout[0].stride, stride * std::mem::size_of::<f32>());
+            assert_eq!(data.meshes[0].indices, vec![0, 1, 2]);
+            assert_eq!(data.meshes[0].vertices.len(), 3 * stride);
+
+            //The second vertex, whatever parts the form carries
+            let expected = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0];
+            let second = &data.meshes[0].vertices[stride..2 * stride];
+            assert_eq!(&second[..3], &expected[..3]);
+            if has_texcoords {
+                assert_eq!(&second[3..5], &expected[3..5]);
+            }
+            if has_normals {
+                assert_eq!(&second[stride - 3..], &expected[5..]);
+            }
+        }
+    }
+
+    #[test]
+    fn same_index_triple_shares_a_vertex() {
+        //Position 1 shows up with two different uvs, so it becomes two vertices
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvt 1 1\n\
+                   f 1/1 2/2 3/3\nf 1/1 3/3 2/2\nf 1/4 2/2 3/3\n";
+        let data = parse(src.as_bytes(), ParseMode::Strict).unwrap();
+        assert_eq!(data.meshes[0].indices, vec![0, 1, 2, 0, 2, 1, 3, 1, 2]);
+        assert_eq!(data.meshes[0].vertices.len(), 4 * 5);
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_last_element() {
+        let src = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n\
+                   f -3/-3/-1 -2/-2/-1 -1/-1/-1\nf 1//1 -2//-1 -1//1\n";
+        let data = parse(src.as_bytes(), ParseMode::Strict).unwrap();
+        let stride = data.layout[0].stride / std::mem::size_of::<f32>();
+        assert_eq!(stride, 8);
+        let vertex = |corner: usize| {
+            let idx = data.meshes[0].indices[corner] as usize;
+            data.meshes[0].vertices[idx * stride..(idx + 1) * stride].to_vec()
+        };
+        assert_eq!(vertex(0), vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(vertex(2), vec![0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0]);
+        //v//vn in a file with uvs fills the uv with zeros
+        assert_eq!(vertex(4), vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]);
+
+        let e = parse("v 0 0 0\nf -1 -2 -4\n".as_bytes(), ParseMode::Strict).err().unwrap();
+        match e.reason {
+            ObjErrorReason::IndexOutOfRange { .. } => (),
+            ref reason => panic!("unexpected {:?}", reason),
+        }
+    }
+
+    #[test]
+    fn relative_indices_count_back_per_element_kind() {
+        let data = fixture("relative.obj");
+        assert_eq!(data.meshes[0].indices.len(), 6);
+        assert_eq!(area_and_winding(&data), (1.0, true));
+        //Position, uv and the single normal of the last vertex
+        let stride = data.layout[0].stride / std::mem::size_of::<f32>();
+        let last = &data.meshes[0].vertices[data.meshes[0].indices[4] as usize * stride..][..stride];
+        assert_eq!(last, &[1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0][..]);
+    }
+
+    #[test]
+    fn vertex_colors_become_an_attribute() {
+        let data = fixture("colors.obj");
+        assert!(data.has_colors);
+        assert_eq!(data.layout.len(), 2);
+        assert_eq!(data.layout[1].start_idx, 3 * std::mem::size_of::<f32>());
+        assert_eq!(data.meshes[0].vertices, vec![
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+            1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            0.0, 1.0, 0.0, 0.0, 0.0, 1.0,
+        ]);
+    }
+
+    #[test]
+    fn objects_and_groups_become_named_meshes() {
+        let data = fixture("car.obj");
+        let names: Vec<&str> = data.meshes.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["body", "wheel_fl", "wheel_fr"]);
+
+        //Both body triangles are collected into one mesh with its own vertices
+        let body = &data.meshes[0];
+        assert_eq!(body.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(body.vertices.len(), 4 * 3);
+        assert_eq!(body.smoothing_groups, vec![1, 1]);
+
+        assert_eq!(data.meshes[1].smoothing_groups, vec![0]);
+        assert_eq!(data.meshes[2].smoothing_groups, vec![2]);
+        assert_eq!(data.meshes[2].vertices, vec![3.0, -1.0, 0.0, 4.0, -1.0, 0.0, 3.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn meshes_split_vertices_into_streams() {
+        let meshes = fixture("car.obj").to_meshes();
+        let wheel = &meshes[2];
+        assert_eq!(wheel.name, "wheel_fr");
+        assert_eq!(wheel.streams.len(), 1);
+        assert_eq!(wheel.positions(), &[3.0, -1.0, 0.0, 4.0, -1.0, 0.0, 3.5, 0.0, 0.0]);
+        assert_eq!(wheel.bounds.min, Vec3::new(3.0, -1.0, 0.0));
+        assert_eq!(wheel.bounds.max, Vec3::new(4.0, 0.0, 0.0));
+        assert_eq!(meshes[0].smoothing_groups, vec![1, 1]);
+        assert_eq!(meshes[1].smoothing_groups, vec![0]);
+        assert_eq!(wheel.smoothing_groups, vec![2]);
+
+        let data = parse_file(&fixture_path("materials.obj"), ParseMode::Strict).unwrap();
+        let mesh = &data.to_meshes()[0];
+        assert_eq!(mesh.interleaved(), data.meshes[0].vertices);
+        assert_eq!(mesh.submeshes, data.meshes[0].submeshes);
+        assert_eq!(mesh.materials, data.materials);
+        //No s statements at all, so nothing is forced flat
+        assert!(mesh.smoothing_groups.is_empty());
+    }
+
+    #[test]
+    fn faces_before_any_group_use_the_default_name() {
+        let data = fixture("triangle.obj");
+        assert_eq!(data.meshes.len(), 1);
+        assert_eq!(data.meshes[0].name, DEFAULT_MESH_NAME);
+    }
+
+    //A size x size grid of quads in the xy plane with texture coordinates
+    pub(super) fn grid_obj(size: usize) -> String {
+        let mut obj = String::new();
+        for y in 0..=size {
+            for x in 0..=size {
+                obj.push_str(&format!("v {} {} 0\n", x, y));
+                obj.push_str(&format!("vt {} {}\n", x as f32 / size as f32, y as f32 / size as f32));
+            }
+        }
+        let row = size + 1;
+        for y in 0..size {
+            for x in 0..size {
+                let a = y * row + x + 1;
+                let (b, c, d) = (a + 1, a + row + 1, a + row);
+                obj.push_str(&format!("f {}/{} {}/{} {}/{} {}/{}\n", a, a, b, b, c, c, d, d));
+            }
+        }
+        obj
+    }
+
+    type MeshSummary = (String, Vec<f32>, Vec<u32>, Vec<Submesh>, Vec<u32>);
+
+    //Everything but the warning reasons, which are not comparable
+    fn summary(data: &ObjData) -> (Vec<MeshSummary>, Vec<(usize, usize)>) {
+        let meshes = data.meshes.iter()
+            .map(|m| (m.name.clone(), m.vertices.clone(), m.indices.clone(), m.submeshes.clone(), m.smoothing_groups.clone()))
+            .collect();
+        let warnings = data.warnings.iter().map(|w| (w.line, w.column)).collect();
+        (meshes, warnings)
+    }
+
+    #[test]
+    fn chunking_does_not_change_the_result() {
+        let mut sources: Vec<Vec<u8>> = [
+            "car.obj", "concave.obj", "whitespace.obj", "relative.obj",
+            "materials.obj", "colors.obj", "malformed/mixed_errors.obj",
+        ].iter().map(|name| std::fs::read(fixture_path(name)).unwrap()).collect();
+        sources.push(grid_obj(8).into_bytes());
+        for bytes in sources.iter() {
+            let whole = parse_chunked(bytes, ParseMode::Lenient, &|_| (), usize::MAX).unwrap();
+            for &chunk_size in [1, 7, 64].iter() {
+                let chunked = parse_chunked(bytes, ParseMode::Lenient, &|_| (), chunk_size).unwrap();
+                assert!(summary(&whole) == summary(&chunked), "chunk size {}", chunk_size);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_never_split_a_continued_line() {
+        let bytes = b"v 0 0 0\nv 1 \\\n0 0 # a comment \\\nv 0 1 0\nf 1 2 3\n";
+        let ranges = split_chunks(bytes, 1);
+        let starts: Vec<usize> = ranges.iter().map(|r| r.start).collect();
+        //The second v continues onto the third line, the comment does not
+        assert_eq!(starts, vec![0, 8, 32, 40]);
+    }
+
+    #[test]
+    fn strict_mode_reports_the_first_error_across_chunks() {
+        let bytes = std::fs::read(fixture_path("malformed/mixed_errors.obj")).unwrap();
+        let e = parse_chunked(&bytes, ParseMode::Strict, &|_| (), 16).err().unwrap();
+        assert_eq!((e.line, e.column), (5, 5));
+    }
+
+    #[test]
+    fn progress_ends_at_one() {
+        let bytes = grid_obj(16).into_bytes();
+        let reported = std::sync::Mutex::new(Vec::new());
+        let data = parse_chunked(&bytes, ParseMode::Strict, &|p| reported.lock().unwrap().push(p), 256).unwrap();
+        assert_eq!(data.meshes[0].indices.len(), 16 * 16 * 6);
+
+        let reported = reported.into_inner().unwrap();
+        //Once per chunk tokenized and once per chunk merged
+        assert_eq!(reported.len(), 2 * split_chunks(&bytes, 256).len());
+        assert_eq!(*reported.last().unwrap(), 1.0);
+        assert!(reported.iter().all(|p| *p > 0.0 && *p <= 1.0));
+    }
+
+    //Saves into a directory of the test's own, tests run in parallel
+    fn round_trip<T>(test: &str, name: &str, meshes: &[Mesh], read: impl FnOnce(&Path) -> T) -> T {
+        let dir = std::env::temp_dir().join(format!("obj_round_trip_{}_{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        save(&path, meshes).unwrap();
+        let result = read(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn assert_same(a: &Mesh, b: &Mesh, file: &str) -> () {
+        assert_eq!(a.name, b.name);
+        assert!(a.streams == b.streams, "{} in {} changed", a.name, file);
+        assert_eq!(a.indices, b.indices);
+        assert_eq!(a.submeshes, b.submeshes);
+        assert_eq!(a.smoothing_groups, b.smoothing_groups);
+        assert_eq!(a.materials, b.materials);
+    }
+
+    #[test]
+    fn saved_files_load_back_the_same() {
+        for name in ["car.obj", "materials.obj", "colors.obj", "relative.obj", "concave.obj"].iter() {
+            let original = load(&fixture_path(name)).unwrap();
+            let saved = round_trip("load_back", name, &original, |path| load(path).unwrap());
+            assert_eq!(original.len(), saved.len());
+            for (a, b) in original.iter().zip(saved.iter()) {
+                assert_same(a, b, name);
+            }
+        }
+    }
+
+    #[test]
+    fn meshes_without_materials_or_uvs_stay_that_way() {
+        //Saved after meshes with materials, uvs and normals, the procedural
+        //mesh must not pick up the last usemtl or zero filled streams
+        let mut meshes = load(&fixture_path("materials.obj")).unwrap();
+        let materials = meshes[0].materials.clone();
+        let positions = vec![0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0,  1.0, 1.0, 0.5];
+        let mut procedural = Mesh::new("procedural", positions, vec![0, 1, 2, 2, 1, 3]);
+        procedural.materials = materials;
+        meshes.push(procedural);
+        assert!(meshes[0].stream(&Semantic::TexCoord(0)).is_some());
+
+        let saved = round_trip("mixed", "mixed.obj", &meshes, |path| load(path).unwrap());
+        assert_eq!(meshes.len(), saved.len());
+        for (a, b) in meshes[..meshes.len() - 1].iter().zip(saved.iter()) {
+            assert_same(a, b, "mixed.obj");
+        }
+        let (original, loaded) = (&meshes[meshes.len() - 1], &saved[saved.len() - 1]);
+        assert_eq!(loaded.name, "procedural");
+        assert!(loaded.streams == original.streams);
+        assert_eq!(loaded.indices, original.indices);
+        assert_eq!(loaded.submeshes, vec![Submesh { material_name: None, material: None, start: 0, count: 6 }]);
+    }
+
+    #[test]
+    fn saving_writes_a_material_library() {
+        let original = load(&fixture_path("materials.obj")).unwrap();
+        let saved = round_trip("library", "materials.obj", &original, |path| parse_file(path, ParseMode::Strict).unwrap());
+        assert!(saved.warnings.is_empty());
+        assert_eq!(saved.material_libs, vec!["materials.mtl".to_string()]);
+        let brick = &saved.materials[1];
+        assert_eq!(brick.map_kd, Some(fixture_path("textures/brick.png")));
+    }
+
+    #[test]
+    fn meshes_without_materials_get_no_library() {
+        //Built in code, no submeshes and nothing but positions
+        let positions = vec![0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0,  1.0, 1.0, 0.5];
+        let mesh = Mesh::new("procedural", positions, vec![0, 1, 2, 2, 1, 3]);
+        let saved = round_trip("no_library", "procedural.obj", std::slice::from_ref(&mesh), |path| {
+            parse_file(path, ParseMode::Strict).unwrap()
+        });
+        assert!(saved.material_libs.is_empty());
+        assert!(saved.warnings.is_empty());
+
+        let loaded = &saved.to_meshes()[0];
+        assert_eq!(loaded.name, "procedural");
+        assert_eq!(loaded.positions(), mesh.positions());
+        assert_eq!(loaded.indices, mesh.indices);
+        assert_eq!(loaded.streams.len(), 1);
+    }
+}
+
+#[cfg(all(test, feature = "bench"))]
+mod bench {
+    extern crate test;
+
+    use super::*;
+    use super::tests::grid_obj;
+    use self::test::{black_box, Bencher};
+
+    //1000 x 1000 quads, about 45MB of text
+    const GRID: usize = 1000;
+
+    #[bench]
+    fn parse_1m_faces(b: &mut Bencher) {
+        let bytes = grid_obj(GRID).into_bytes();
+        b.iter(|| black_box(parse_bytes(&bytes, ParseMode::Strict, &|_| ()).unwrap()));
+    }
+
+    #[bench]
+    fn parse_1m_faces_single_thread(b: &mut Bencher) {
+        let bytes = grid_obj(GRID).into_bytes();
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        b.iter(|| pool.install(|| black_box(parse_bytes(&bytes, ParseMode::Strict, &|_| ()).unwrap())));
+    }
+
+    #[bench]
+    fn tokenize_1m_faces(b: &mut Bencher) {
+        let bytes = grid_obj(GRID).into_bytes();
+        b.iter(|| {
+            let chunks: Vec<Chunk> = split_chunks(&bytes, CHUNK_SIZE)
+                .into_par_iter()
+                .map(|range| tokenize(&bytes[range], ParseMode::Strict))
+                .collect();
+            black_box(chunks)
+        });
+    }
 }
@@ -0,0 +1,509 @@
+use crate::renderer::model::Submesh;
+use crate::renderer::model::mesh::Mesh;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+
+//Vertices the Forsyth scoring assumes fit in the post-transform cache
+const FORSYTH_CACHE_SIZE: usize = 32;
+//FIFO size used for the statistics, about what current hardware keeps around
+pub const STATS_CACHE_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub vertices  : usize,
+    pub triangles : usize,
+    //Average cache misses per triangle, 0.5 is the best a regular grid can do
+    pub acmr      : f32,
+    //Average transforms per vertex, 1.0 means every vertex is shaded once
+    pub atvr      : f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Report {
+    pub before : Stats,
+    pub after  : Stats,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "vertices {} -> {}, triangles {} -> {}, ACMR {:.3} -> {:.3}, ATVR {:.3} -> {:.3}",
+            self.before.vertices, self.after.vertices,
+            self.before.triangles, self.after.triangles,
+            self.before.acmr, self.after.acmr,
+            self.before.atvr, self.after.atvr,
+        )
+    }
+}
+
+//Runs the whole cleanup pass: weld, drop degenerate and duplicate
+//triangles, reorder for the post-transform cache and then for fetching.
+pub fn optimize(mesh: &mut Mesh, weld_epsilon: f32) -> Report {
+    let before = stats(mesh);
+    weld(mesh, weld_epsilon);
+    remove_degenerate_triangles(mesh);
+    optimize_vertex_cache(mesh);
+    optimize_vertex_fetch(mesh);
+    Report { before, after: stats(mesh) }
+}
+
+pub fn stats(mesh: &Mesh) -> Stats {
+    let misses = cache_misses(&mesh.indices, STATS_CACHE_SIZE);
+    let ratio = |n: usize| if n == 0 { 0.0 } else { misses as f32 / n as f32 };
+    Stats {
+        vertices  : mesh.vertex_count(),
+        triangles : mesh.triangle_count(),
+        acmr      : ratio(mesh.triangle_count()),
+        atvr      : ratio(mesh.vertex_count()),
+    }
+}
+
+//Misses of a FIFO cache holding cache_size vertices
+pub fn cache_misses(indices: &[u32], cache_size: usize) -> usize {
+    let mut cache = VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &idx in indices {
+        if !cache.contains(&idx) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(idx);
+        }
+    }
+    misses
+}
+
+//Merges vertices whose every stream, not just the position, agrees within
+//epsilon, so UV and normal seams survive. Unused vertices are dropped.
+//Returns how many vertices went away.
+pub fn weld(mesh: &mut Mesh, epsilon: f32) -> usize {
+    let vertex_cnt = mesh.vertex_count();
+    //Positions that can't be put on the grid are keyed by their bits, in a
+    //bucket of their own that has no neighbours
+    let cell = |v: u32| -> (bool, [i64;3]) {
+        let p = mesh.position(v).to_array();
+        let exact = epsilon <= 0.0 || p.iter().any(|x| !x.is_finite());
+        let mut cell = [0;3];
+        for (c, &x) in cell.iter_mut().zip(p.iter()) {
+            *c = if !exact {
+                //Saturates for huge coordinates, the neighbour lookup copes
+                (x / epsilon).floor() as i64
+            } else if x == 0.0 {
+                0
+            } else {
+                i64::from(x.to_bits())
+            };
+        }
+        (exact, cell)
+    };
+    let same = |a: u32, b: u32| mesh.streams.iter().all(|s| {
+        let (a, b) = (a as usize * s.width, b as usize * s.width);
+        s.data[a..a + s.width].iter().zip(s.data[b..b + s.width].iter()).all(|(x, y)| (x - y).abs() <= epsilon)
+    });
+
+    //Vertices nothing was merged into, bucketed by position
+    let mut grid = HashMap::<(bool, [i64;3]), Vec<u32>>::new();
+    let mut remap: Vec<u32> = (0..vertex_cnt as u32).collect();
+    for v in 0..vertex_cnt as u32 {
+        let (exact, c) = cell(v);
+        let reach = if exact { 0 } else { 1 };
+        let mut found = None;
+        'search: for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let near = match (c[0].checked_add(dx), c[1].checked_add(dy), c[2].checked_add(dz)) {
+                        (Some(x), Some(y), Some(z)) => grid.get(&(exact, [x, y, z])).map_or(&[][..], |n| &n[..]),
+                        _ => &[][..],
+                    };
+                    if let Some(&kept) = near.iter().find(|&&kept| same(kept, v)) {
+                        found = Some(kept);
+                        break 'search;
+                    }
+                }
+            }
+        }
+        match found {
+            Some(kept) => remap[v as usize] = kept,
+            None => grid.entry((exact, c)).or_default().push(v),
+        }
+    }
+    for idx in mesh.indices.iter_mut() {
+        *idx = remap[*idx as usize];
+    }
+    compact(mesh);
+    vertex_cnt - mesh.vertex_count()
+}
+
+//Drops triangles that repeat a vertex or have no area, and triangles that
+//repeat an earlier one in the same submesh with the same winding. Flipped
+//copies stay since they are the back side. Returns the number removed.
+pub fn remove_degenerate_triangles(mesh: &mut Mesh) -> usize {
+    let triangle_cnt = mesh.triangle_count();
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    let mut kept = Vec::with_capacity(triangle_cnt);
+    let mut counts = Vec::new();
+    for (start, count) in ranges(&mesh.submeshes, mesh.indices.len()) {
+        let first = indices.len();
+        let mut seen = HashSet::new();
+        for (i, t) in mesh.indices[start..start + count].chunks(3).enumerate() {
+            if t[0] == t[1] || t[1] == t[2] || t[0] == t[2] {
+                continue;
+            }
+            let (a, b, c) = (mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2]));
+            if (b - a).cross(c - a).length_squared() == 0.0 {
+                continue;
+            }
+            //Rotated so the smallest index is first, which keeps the winding
+            let low = (0..3).min_by_key(|&i| t[i]).unwrap();
+            if seen.insert([t[low], t[(low + 1) % 3], t[(low + 2) % 3]]) {
+                indices.extend_from_slice(t);
+                kept.push(start / 3 + i);
+            }
+        }
+        counts.push((first, indices.len() - first));
+    }
+    mesh.indices = indices;
+    if !mesh.smoothing_groups.is_empty() {
+        mesh.smoothing_groups = kept.iter().map(|&t| mesh.smoothing_groups[t]).collect();
+    }
+    for (submesh, (start, count)) in mesh.submeshes.iter_mut().zip(counts) {
+        submesh.start = start;
+        submesh.count = count;
+    }
+    triangle_cnt - mesh.triangle_count()
+}
+
+//Reorders the triangles of each submesh with Tom Forsyth's linear-speed
+//vertex cache optimisation. Submeshes keep their place in the index buffer.
+pub fn optimize_vertex_cache(mesh: &mut Mesh) -> () {
+    let order = triangle_order(&mesh.indices, &mesh.submeshes, mesh.vertex_count());
+    mesh.indices = reorder(&mesh.indices, &order);
+    if !mesh.smoothing_groups.is_empty() {
+        mesh.smoothing_groups = order.iter().map(|&t| mesh.smoothing_groups[t]).collect();
+    }
+}
+
+//optimize_vertex_cache for index buffers that don't own their vertices,
+//like the levels of a LOD chain
+pub fn optimize_index_order(indices: &mut [u32], submeshes: &[Submesh], vertex_cnt: usize) -> () {
+    let order = triangle_order(indices, submeshes, vertex_cnt);
+    let reordered = reorder(indices, &order);
+    indices.copy_from_slice(&reordered);
+}
+
+//Triangle numbers in the order they should be drawn. Triangles stay in
+//their submesh, anything outside the submeshes stays where it is.
+fn triangle_order(indices: &[u32], submeshes: &[Submesh], vertex_cnt: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..indices.len() / 3).collect();
+    for (start, count) in ranges(submeshes, indices.len()) {
+        let first = start / 3;
+        for (slot, t) in order[first..].iter_mut().zip(forsyth(&indices[start..start + count], vertex_cnt)) {
+            *slot = first + t;
+        }
+    }
+    order
+}
+
+fn reorder(indices: &[u32], order: &[usize]) -> Vec<u32> {
+    order.iter().flat_map(|&t| indices[t * 3..t * 3 + 3].iter().cloned()).collect()
+}
+
+//Renumbers the vertices in the order the index buffer first uses them so
+//fetching walks memory forwards. Unused vertices are dropped.
+pub fn optimize_vertex_fetch(mesh: &mut Mesh) -> () {
+    let mut remap = vec![None; mesh.vertex_count()];
+    let mut sources = Vec::new();
+    for idx in mesh.indices.iter_mut() {
+        *idx = *remap[*idx as usize].get_or_insert_with(|| {
+            sources.push(*idx);
+            sources.len() as u32 - 1
+        });
+    }
+    mesh.select_vertices(&sources);
+}
+
+//Drops vertices no index points at, keeping the order of the rest
+fn compact(mesh: &mut Mesh) -> () {
+    let mut used = vec![false; mesh.vertex_count()];
+    for &idx in mesh.indices.iter() {
+        used[idx as usize] = true;
+    }
+    let mut remap = vec![0; used.len()];
+    let mut sources = Vec::new();
+    for (v, _) in used.iter().enumerate().filter(|(_, &used)| used) {
+        remap[v] = sources.len() as u32;
+        sources.push(v as u32);
+    }
+    if sources.len() == used.len() {
+        return;
+    }
+    for idx in mesh.indices.iter_mut() {
+        *idx = remap[*idx as usize];
+    }
+    mesh.select_vertices(&sources);
+}
+
+//(start, count) of every submesh, or of the whole index buffer without any.
+//Submeshes are expected to split the buffer into consecutive runs, which is
+//what every loader produces.
+pub(crate) fn ranges(submeshes: &[Submesh], index_cnt: usize) -> Vec<(usize, usize)> {
+    if submeshes.is_empty() {
+        vec![(0, index_cnt)]
+    } else {
+        submeshes.iter().map(|s| (s.start, s.count)).collect()
+    }
+}
+
+fn vertex_score(cache_pos: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache = match cache_pos {
+        //The last triangle's vertices get a fixed score so its neighbours
+        //don't always win and strips don't form
+        Some(pos) if pos < 3 => 0.75,
+        Some(pos) => (1.0 - (pos - 3) as f32 / (FORSYTH_CACHE_SIZE - 3) as f32).powf(1.5),
+        None => 0.0,
+    };
+    //Vertices with few triangles left are worth finishing off
+    cache + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+//Returns the triangles of `indices` in the order to draw them
+fn forsyth(indices: &[u32], vertex_cnt: usize) -> Vec<usize> {
+    let triangle_cnt = indices.len() / 3;
+    let mut remaining = vec![0u32; vertex_cnt];
+    for &idx in indices {
+        remaining[idx as usize] += 1;
+    }
+    //Triangles around each vertex, flattened with offsets
+    let mut offsets = vec![0; vertex_cnt + 1];
+    for v in 0..vertex_cnt {
+        offsets[v + 1] = offsets[v] + remaining[v] as usize;
+    }
+    let mut adjacency = vec![0; indices.len()];
+    let mut fill = offsets.clone();
+    for (corner, &idx) in indices.iter().enumerate() {
+        adjacency[fill[idx as usize]] = corner / 3;
+        fill[idx as usize] += 1;
+    }
+
+    let mut cache_pos: Vec<Option<usize>> = vec![None; vertex_cnt];
+    let mut scores: Vec<f32> = (0..vertex_cnt).map(|v| vertex_score(None, remaining[v])).collect();
+    let triangle_score = |t: usize, scores: &[f32]| indices[t * 3..t * 3 + 3].iter().map(|&v| scores[v as usize]).sum::<f32>();
+    let mut triangle_scores: Vec<f32> = (0..triangle_cnt).map(|t| triangle_score(t, &scores)).collect();
+    let mut emitted = vec![false; triangle_cnt];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(FORSYTH_CACHE_SIZE + 3);
+    let mut out = Vec::with_capacity(triangle_cnt);
+    let mut next_unemitted = 0;
+    let mut best = (0..triangle_cnt).max_by(|&a, &b| triangle_scores[a].partial_cmp(&triangle_scores[b]).unwrap());
+    while let Some(t) = best {
+        let triangle = &indices[t * 3..t * 3 + 3];
+        emitted[t] = true;
+        out.push(t);
+        for &v in triangle {
+            remaining[v as usize] -= 1;
+        }
+
+        //Most recently used first, whatever falls past the end is evicted
+        let mut touched: Vec<u32> = triangle.to_vec();
+        touched.extend(cache.iter().filter(|v| !triangle.contains(v)));
+        for (pos, &v) in touched.iter().enumerate() {
+            cache_pos[v as usize] = if pos < FORSYTH_CACHE_SIZE { Some(pos) } else { None };
+            scores[v as usize] = vertex_score(cache_pos[v as usize], remaining[v as usize]);
+        }
+        let mut best_score = f32::NEG_INFINITY;
+        best = None;
+        for &v in touched.iter() {
+            let v = v as usize;
+            for &t in adjacency[offsets[v]..offsets[v + 1]].iter().filter(|&&t| !emitted[t]) {
+                triangle_scores[t] = triangle_score(t, &scores);
+                if triangle_scores[t] > best_score {
+                    best_score = triangle_scores[t];
+                    best = Some(t);
+                }
+            }
+        }
+        touched.truncate(FORSYTH_CACHE_SIZE);
+        cache = touched;
+
+        //Nothing left around the cache, carry on with the next island
+        if best.is_none() {
+            while next_unemitted < triangle_cnt && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            if next_unemitted < triangle_cnt {
+                best = Some(next_unemitted);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::model::mesh::Semantic;
+
+    //n by n quads with the triangles shuffled, the worst case for the cache
+    fn shuffled_grid(n: u32) -> Mesh {
+        let mut positions = Vec::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                positions.extend_from_slice(&[x as f32, y as f32, 0.0]);
+            }
+        }
+        let mut triangles = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let v = y * (n + 1) + x;
+                triangles.push([v, v + 1, v + n + 2]);
+                triangles.push([v, v + n + 2, v + n + 1]);
+            }
+        }
+        let mut seed = 12345u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            triangles.swap(i, (seed >> 8) as usize % (i + 1));
+        }
+        Mesh::new("grid", positions, triangles.concat())
+    }
+
+    //Triangles rotated to start at their smallest position, then sorted, so
+    //two meshes can be compared no matter how they are ordered and numbered
+    fn triangle_set(mesh: &Mesh) -> Vec<[[u32;3];3]> {
+        let mut set: Vec<_> = mesh.indices.chunks(3).map(|t| {
+            let p: Vec<[u32;3]> = t.iter().map(|&i| {
+                let a = mesh.position(i).to_array();
+                [a[0].to_bits(), a[1].to_bits(), a[2].to_bits()]
+            }).collect();
+            let low = (0..3).min_by_key(|&i| p[i]).unwrap();
+            [p[low], p[(low + 1) % 3], p[(low + 2) % 3]]
+        }).collect();
+        set.sort();
+        set
+    }
+
+    #[test]
+    fn welds_within_epsilon_but_keeps_seams() {
+        //Two triangles of a quad, the shared edge written out twice with a
+        //little noise. The last vertex sits on the edge but has another UV.
+        let vertices = [
+            0.0, 0.0, 0.0,     0.0, 0.0,
+            1.0, 0.0, 0.0,     1.0, 0.0,
+            1.0, 1.0, 0.0,     1.0, 1.0,
+            0.0, 0.0, 0.00001, 0.0, 0.0,
+            1.0, 1.0, 0.0,     1.0, 1.0,
+            0.0, 1.0, 0.0,     0.0, 1.0,
+            1.0, 1.0, 0.0,     0.5, 0.5,
+        ];
+        let semantics = [(Semantic::Position, 3), (Semantic::TexCoord(0), 2)];
+        let indices = vec![0, 1, 2, 3, 4, 5, 6, 1, 0];
+        let mut mesh = Mesh::from_interleaved("quad", &vertices, &semantics, indices);
+
+        assert_eq!(weld(&mut mesh.clone(), 0.0), 1);
+        assert_eq!(weld(&mut mesh, 0.001), 2);
+        assert_eq!(mesh.vertex_count(), 5);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 4, 1, 0]);
+        assert_eq!(mesh.stream(&Semantic::TexCoord(0)).unwrap().data[8..], [0.5, 0.5]);
+    }
+
+    #[test]
+    fn welds_far_away_and_infinite_positions_without_overflowing() {
+        //1e30 / 1e-6 saturates the grid cell, inf can't be put on the grid
+        let inf = f32::INFINITY;
+        let positions = vec![
+            0.0, 0.0, 0.0,
+            1e30, 0.0, 0.0,
+            1e30, 0.0, 0.0,
+            inf, 0.0, 0.0,
+            inf, 0.0, 0.0,
+            1e15, -1e30, 0.0,
+        ];
+        let mesh = Mesh::new("far", positions, vec![0, 1, 3, 0, 2, 4, 0, 5, 1]);
+        for &epsilon in [0.0, 1e-6, 1.0].iter() {
+            let mut welded = mesh.clone();
+            //Only the two copies at 1e30 are close, inf - inf is no distance at all
+            assert_eq!(weld(&mut welded, epsilon), 1, "{}", epsilon);
+            assert_eq!(welded.indices, vec![0, 1, 2, 0, 1, 3, 0, 4, 1], "{}", epsilon);
+        }
+    }
+
+    #[test]
+    fn removes_degenerate_and_duplicate_triangles_per_submesh() {
+        let positions = vec![
+            0.0, 0.0, 0.0,  1.0, 0.0, 0.0,  0.0, 1.0, 0.0,  2.0, 0.0, 0.0,
+        ];
+        let indices = vec![
+            0, 1, 2,  1, 2, 0,  0, 0, 2,  0, 1, 3,
+            0, 2, 1,  0, 1, 2,
+        ];
+        let mut mesh = Mesh::new("tris", positions, indices);
+        let submesh = |start, count| Submesh { material_name: None, material: None, start, count };
+        mesh.submeshes = vec![submesh(0, 12), submesh(12, 6)];
+
+        //The rotation, the repeated vertex and the collinear one go, the
+        //flipped copy and the copy in the other submesh stay
+        assert_eq!(remove_degenerate_triangles(&mut mesh), 3);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 1, 0, 1, 2]);
+        assert_eq!(mesh.submeshes, vec![submesh(0, 3), submesh(3, 6)]);
+    }
+
+    #[test]
+    fn reordering_lowers_acmr_and_keeps_the_triangles() {
+        let mut mesh = shuffled_grid(24);
+        let expected = triangle_set(&mesh);
+        let report = optimize(&mut mesh, 0.0);
+
+        assert_eq!(report.before.vertices, report.after.vertices);
+        assert_eq!(report.before.triangles, report.after.triangles);
+        assert!(report.before.acmr > 2.0, "{}", report);
+        assert!(report.after.acmr < 0.8, "{}", report);
+        assert_eq!(triangle_set(&mesh), expected);
+
+        //Every vertex is first used right after the one before it
+        let mut next = 0;
+        for &idx in mesh.indices.iter() {
+            assert!(idx <= next);
+            if idx == next {
+                next += 1;
+            }
+        }
+        assert_eq!(next as usize, mesh.vertex_count());
+    }
+
+    #[test]
+    fn smoothing_groups_follow_their_triangles() {
+        //Sum of the corner positions, which survives any renumbering
+        let key = |mesh: &Mesh, t: &[u32]| {
+            let p = mesh.position(t[0]) + mesh.position(t[1]) + mesh.position(t[2]);
+            [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()]
+        };
+        let mut mesh = shuffled_grid(8);
+        mesh.smoothing_groups = (1..=mesh.triangle_count() as u32).collect();
+        let expected: HashMap<[u32;3], u32> = mesh.indices.chunks(3).map(|t| key(&mesh, t))
+            .zip(mesh.smoothing_groups.iter().cloned())
+            .collect();
+        //A degenerate triangle up front gets dropped along with its group
+        mesh.indices.splice(0..0, vec![0, 0, 1]);
+        mesh.smoothing_groups.insert(0, 0);
+
+        optimize(&mut mesh, 0.0);
+        assert_eq!(mesh.smoothing_groups.len(), mesh.triangle_count());
+        for (t, group) in mesh.indices.chunks(3).zip(mesh.smoothing_groups.iter()) {
+            assert_eq!(expected[&key(&mesh, t)], *group);
+        }
+    }
+
+    #[test]
+    fn counts_fifo_misses() {
+        //Two triangles sharing an edge miss 4 times, a single entry cache only
+        //catches the vertex repeated right away
+        assert_eq!(cache_misses(&[0, 1, 2, 2, 1, 3], 16), 4);
+        assert_eq!(cache_misses(&[0, 1, 2, 2, 1, 3], 1), 5);
+        assert_eq!(stats(&Mesh::new("empty", Vec::new(), Vec::new())).acmr, 0.0);
+    }
+}
@@ -0,0 +1,562 @@
+use crate::renderer::model::mesh::{Mesh, Semantic, VertexStream};
+use crate::renderer::model::triangulate::triangulate;
+use crate::math::Vec3;
+use std::io::{self, BufReader, BufRead, Read};
+use std::fs::File;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(io::Error),
+    //One based header line
+    Header { line: usize, reason: String },
+    //Zero based element index within its element block
+    Body { element: String, index: usize, reason: String },
+    //The vertex element lacks x, y or z
+    MissingPosition,
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "{}", e),
+            PlyError::Header { line, reason } => write!(f, "header line {}: {}", line, reason),
+            PlyError::Body { element, index, reason } => write!(f, "{} {}: {}", element, index, reason),
+            PlyError::MissingPosition => write!(f, "vertex element has no x, y and z properties"),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlyError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlyError {
+    fn from(e: io::Error) -> Self {
+        PlyError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ScalarType {
+    I8, U8, I16, U16, I32, U32, F32, F64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => ScalarType::I8,
+            "uchar" | "uint8" => ScalarType::U8,
+            "short" | "int16" => ScalarType::I16,
+            "ushort" | "uint16" => ScalarType::U16,
+            "int" | "int32" => ScalarType::I32,
+            "uint" | "uint32" => ScalarType::U32,
+            "float" | "float32" => ScalarType::F32,
+            "double" | "float64" => ScalarType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ScalarType::I8 | ScalarType::U8 => 1,
+            ScalarType::I16 | ScalarType::U16 => 2,
+            ScalarType::I32 | ScalarType::U32 | ScalarType::F32 => 4,
+            ScalarType::F64 => 8,
+        }
+    }
+
+    //Largest value of an integer type, colors are stored as fractions of it
+    fn max_int(self) -> Option<f64> {
+        match self {
+            ScalarType::U8 => Some(u8::MAX as f64),
+            ScalarType::U16 => Some(u16::MAX as f64),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    //Count type, item type
+    List(ScalarType, ScalarType),
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name : String,
+    ty   : PropertyType,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name       : String,
+    count      : usize,
+    properties : Vec<Property>,
+}
+
+struct Header {
+    format   : Format,
+    elements : Vec<Element>,
+}
+
+//The mesh is named after the file
+pub fn load(path: &Path) -> Result<Mesh, PlyError> {
+    let ply_file = File::open(path)?;
+    let mut mesh = parse(BufReader::new(ply_file))?;
+    mesh.name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+    trace!("{}: {} vertices, {} indices", path.display(), mesh.vertex_count(), mesh.indices.len());
+
+    Ok(mesh)
+}
+
+//Vertices get a position stream, then a texture coordinate, normal and
+//color if the file has them, then one Semantic::Custom stream per remaining
+//vertex property in header order
+pub fn parse<R: BufRead>(mut ply_file: R) -> Result<Mesh, PlyError> {
+    let header = read_header(&mut ply_file)?;
+    let mut body = Vec::new();
+    ply_file.read_to_end(&mut body)?;
+    //Invalid utf-8 turns into tokens that fail to parse with a useful position
+    let text = match header.format {
+        Format::Ascii => String::from_utf8_lossy(&body),
+        _ => "".into(),
+    };
+    let mut reader = match header.format {
+        Format::Ascii => ValueReader::Ascii { text: &text, pos: 0 },
+        Format::BinaryLittleEndian => ValueReader::Binary { bytes: &body, pos: 0, big_endian: false },
+        Format::BinaryBigEndian => ValueReader::Binary { bytes: &body, pos: 0, big_endian: true },
+    };
+
+    //Vertex properties as columns, faces as polygons
+    let mut columns = Vec::<(Property, Vec<f64>)>::new();
+    let mut faces = Vec::<Vec<u32>>::new();
+    for element in header.elements.iter() {
+        let body_error = |index: usize, reason: String| PlyError::Body {
+            element: element.name.clone(),
+            index,
+            reason,
+        };
+        match element.name.as_str() {
+            "vertex" => {
+                //The count comes from the file, it only gets as much room
+                //as the rest of the file could fill
+                let capacity = element.count.min(reader.bytes_left());
+                columns = element.properties.iter()
+                    .map(|p| (p.clone(), Vec::with_capacity(capacity)))
+                    .collect();
+                for i in 0..element.count {
+                    for (property, column) in columns.iter_mut() {
+                        match property.ty {
+                            PropertyType::Scalar(ty) => column.push(reader.read(ty).map_err(|r| body_error(i, r))?),
+                            //Lists on vertices have no attribute to go to
+                            PropertyType::List(count_ty, item_ty) => {
+                                reader.skip_list(count_ty, item_ty).map_err(|r| body_error(i, r))?;
+                                column.push(0.0);
+                            },
+                        }
+                    }
+                }
+            },
+            "face" => {
+                faces.reserve(element.count.min(reader.bytes_left()));
+                for i in 0..element.count {
+                    for property in element.properties.iter() {
+                        match property.ty {
+                            PropertyType::List(count_ty, item_ty)
+                                if property.name == "vertex_indices" || property.name == "vertex_index" => {
+                                let cnt = reader.read_count(count_ty, item_ty).map_err(|r| body_error(i, r))?;
+                                let mut face = Vec::with_capacity(cnt);
+                                for _ in 0..cnt {
+                                    face.push(reader.read_index(item_ty).map_err(|r| body_error(i, r))?);
+                                }
+                                faces.push(face);
+                            },
+                            PropertyType::List(count_ty, item_ty) => {
+                                reader.skip_list(count_ty, item_ty).map_err(|r| body_error(i, r))?;
+                            },
+                            PropertyType::Scalar(ty) => {
+                                reader.read(ty).map_err(|r| body_error(i, r))?;
+                            },
+                        }
+                    }
+                }
+            },
+            _ => {
+                for i in 0..element.count {
+                    for property in element.properties.iter() {
+                        match property.ty {
+                            PropertyType::Scalar(ty) => { reader.read(ty).map_err(|r| body_error(i, r))?; },
+                            PropertyType::List(count_ty, item_ty) => {
+                                reader.skip_list(count_ty, item_ty).map_err(|r| body_error(i, r))?;
+                            },
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    build(columns, faces)
+}
+
+//Groups the vertex columns into streams
+fn build(columns: Vec<(Property, Vec<f64>)>, faces: Vec<Vec<u32>>) -> Result<Mesh, PlyError> {
+    let find = |names: &[&str]| -> Option<Vec<usize>> {
+        names.iter()
+            .map(|name| columns.iter().position(|(p, _)| p.name == *name))
+            .collect()
+    };
+    let position = find(&["x", "y", "z"]).ok_or(PlyError::MissingPosition)?;
+    let texcoord = find(&["u", "v"])
+        .or_else(|| find(&["s", "t"]))
+        .or_else(|| find(&["texture_u", "texture_v"]))
+        .or_else(|| find(&["texture_s", "texture_t"]));
+    let normal = find(&["nx", "ny", "nz"]);
+    let color = find(&["red", "green", "blue", "alpha"])
+        .or_else(|| find(&["red", "green", "blue"]))
+        .or_else(|| find(&["diffuse_red", "diffuse_green", "diffuse_blue"]));
+
+    let mut groups = vec![(Semantic::Position, position)];
+    for (semantic, group) in [(Semantic::TexCoord(0), texcoord), (Semantic::Normal, normal), (Semantic::Color(0), color)].iter() {
+        if let Some(group) = group {
+            groups.push((semantic.clone(), group.clone()));
+        }
+    }
+    let used: Vec<usize> = groups.iter().flat_map(|(_, g)| g.iter().cloned()).collect();
+    for (i, (property, _)) in columns.iter().enumerate() {
+        if !used.contains(&i) {
+            if let PropertyType::Scalar(_) = property.ty {
+                groups.push((Semantic::Custom(property.name.clone()), vec![i]));
+            }
+        }
+    }
+
+    let vertex_cnt = columns.first().map_or(0, |(_, column)| column.len());
+    let stream_data = |semantic: &Semantic, group: &[usize]| {
+        let mut data = Vec::<f32>::with_capacity(vertex_cnt * group.len());
+        for v in 0..vertex_cnt {
+            for &c in group.iter() {
+                let (property, column) = &columns[c];
+                let mut value = column[v];
+                //uchar colors become 0 to 1 like float ones
+                if let (Semantic::Color(_), PropertyType::Scalar(ty)) = (semantic, &property.ty) {
+                    if let Some(max) = ty.max_int() {
+                        value /= max;
+                    }
+                }
+                data.push(value as f32);
+            }
+        }
+        data
+    };
+    let mut mesh = Mesh::new("", stream_data(&groups[0].0, &groups[0].1), Vec::new());
+    for (semantic, group) in groups.iter().skip(1) {
+        let data = stream_data(semantic, group);
+        mesh.streams.push(VertexStream { semantic: semantic.clone(), width: group.len(), data });
+    }
+
+    for (i, face) in faces.iter().enumerate() {
+        if let Some(idx) = face.iter().find(|idx| **idx as usize >= vertex_cnt) {
+            return Err(PlyError::Body {
+                element: "face".to_string(),
+                index: i,
+                reason: format!("vertex {} is out of range, there are {} vertices", idx, vertex_cnt),
+            });
+        }
+        if face.len() <= 3 {
+            if face.len() == 3 {
+                mesh.indices.extend_from_slice(face);
+            }
+            continue;
+        }
+        let points: Vec<Vec3> = face.iter().map(|idx| mesh.position(*idx)).collect();
+        for tri in triangulate(&points) {
+            mesh.indices.extend_from_slice(&[face[tri[0]], face[tri[1]], face[tri[2]]]);
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn read_header<R: BufRead>(ply_file: &mut R) -> Result<Header, PlyError> {
+    let mut format = None;
+    let mut elements = Vec::<Element>::new();
+    let mut line = String::new();
+    let mut line_num = 0;
+    loop {
+        line.clear();
+        if ply_file.read_line(&mut line)? == 0 {
+            return Err(PlyError::Header { line: line_num, reason: "missing end_header".to_string() });
+        }
+        line_num += 1;
+        let header_error = |reason: String| PlyError::Header { line: line_num, reason };
+        let mut components = line.split_whitespace();
+        let keyword = components.next();
+        if line_num == 1 {
+            if keyword != Some("ply") {
+                return Err(header_error("not a ply file".to_string()));
+            }
+            continue;
+        }
+        match keyword {
+            Some("format") => {
+                format = Some(match components.next() {
+                    Some("ascii") => Format::Ascii,
+                    Some("binary_little_endian") => Format::BinaryLittleEndian,
+                    Some("binary_big_endian") => Format::BinaryBigEndian,
+                    other => return Err(header_error(format!("unknown format {:?}", other))),
+                });
+            },
+            Some("element") => {
+                let name = components.next().ok_or_else(|| header_error("element without a name".to_string()))?;
+                let count = components.next()
+                    .and_then(|c| c.parse::<usize>().ok())
+                    .ok_or_else(|| header_error(format!("element {} has no count", name)))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            },
+            Some("property") => {
+                let element = elements.last_mut()
+                    .ok_or_else(|| header_error("property before the first element".to_string()))?;
+                let parts: Vec<&str> = components.collect();
+                let scalar = |name: &str| ScalarType::from_name(name)
+                    .ok_or_else(|| header_error(format!("unknown type {}", name)));
+                let property = match parts.as_slice() {
+                    ["list", count_ty, item_ty, name] => Property {
+                        name : name.to_string(),
+                        ty   : PropertyType::List(scalar(count_ty)?, scalar(item_ty)?),
+                    },
+                    [ty, name] => Property {
+                        name : name.to_string(),
+                        ty   : PropertyType::Scalar(scalar(ty)?),
+                    },
+                    _ => return Err(header_error(format!("malformed property '{}'", line.trim()))),
+                };
+                element.properties.push(property);
+            },
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => (),
+            Some(other) => return Err(header_error(format!("unexpected '{}'", other))),
+        }
+    }
+    let format = format.ok_or_else(|| PlyError::Header { line: line_num, reason: "missing format".to_string() })?;
+    Ok(Header { format, elements })
+}
+
+enum ValueReader<'a> {
+    Ascii { text: &'a str, pos: usize },
+    Binary { bytes: &'a [u8], pos: usize, big_endian: bool },
+}
+
+impl<'a> ValueReader<'a> {
+    fn read(&mut self, ty: ScalarType) -> Result<f64, String> {
+        match self {
+            ValueReader::Ascii { text, pos } => {
+                let rest = &text[*pos..];
+                let start = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                let len = rest[start..].find(char::is_whitespace).unwrap_or(rest.len() - start);
+                *pos += start + len;
+                let token = &rest[start..start + len];
+                if token.is_empty() {
+                    return Err("unexpected end of file".to_string());
+                }
+                token.parse::<f64>().map_err(|_| format!("could not parse '{}'", token))
+            },
+            ValueReader::Binary { bytes, pos, big_endian } => {
+                let size = ty.size();
+                if *pos + size > bytes.len() {
+                    return Err("unexpected end of file".to_string());
+                }
+                let mut raw = [0u8;8];
+                raw[..size].copy_from_slice(&bytes[*pos..*pos + size]);
+                *pos += size;
+                //Work in little endian from here on
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                Ok(match ty {
+                    ScalarType::I8 => raw[0] as i8 as f64,
+                    ScalarType::U8 => raw[0] as f64,
+                    ScalarType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    ScalarType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    ScalarType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    ScalarType::F64 => f64::from_le_bytes(raw),
+                })
+            },
+        }
+    }
+
+    //Upper bound on how many more values can be read
+    fn bytes_left(&self) -> usize {
+        match self {
+            ValueReader::Ascii { text, pos } => text.len() - pos,
+            ValueReader::Binary { bytes, pos, .. } => bytes.len() - pos,
+        }
+    }
+
+    //List lengths are checked against what is left of the file before
+    //anything is allocated for them
+    fn read_count(&mut self, count_ty: ScalarType, item_ty: ScalarType) -> Result<usize, String> {
+        let value = self.read(count_ty)?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(format!("{} is not a valid list length", value));
+        }
+        let item_size = match self {
+            ValueReader::Ascii { .. } => 1,
+            ValueReader::Binary { .. } => item_ty.size(),
+        };
+        if value > (self.bytes_left() / item_size) as f64 {
+            return Err(format!("a list of {} items runs past the end of the file", value));
+        }
+        Ok(value as usize)
+    }
+
+    fn read_index(&mut self, ty: ScalarType) -> Result<u32, String> {
+        let value = self.read(ty)?;
+        if value < 0.0 || value.fract() != 0.0 || value > u32::MAX as f64 {
+            return Err(format!("{} is not a valid vertex index", value));
+        }
+        Ok(value as u32)
+    }
+
+    fn skip_list(&mut self, count_ty: ScalarType, item_ty: ScalarType) -> Result<(), String> {
+        let cnt = self.read_count(count_ty, item_ty)?;
+        for _ in 0..cnt {
+            self.read(item_ty)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture_bytes(name: &str) -> Vec<u8> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/test/ply").join(name);
+        std::fs::read(path).unwrap()
+    }
+
+    fn fixture(name: &str) -> Mesh {
+        parse(fixture_bytes(name).as_slice()).unwrap()
+    }
+
+    #[test]
+    fn maps_properties_onto_layout() {
+        let mesh = fixture("cube_ascii.ply");
+        let semantics: Vec<Semantic> = mesh.streams.iter().map(|s| s.semantic.clone()).collect();
+        assert_eq!(semantics, vec![
+            Semantic::Position,
+            Semantic::TexCoord(0),
+            Semantic::Normal,
+            Semantic::Color(0),
+            Semantic::Custom("quality".to_string()),
+        ]);
+        let layout = mesh.layout();
+        let widths: Vec<u8> = layout.iter().map(|a| a.width).collect();
+        assert_eq!(widths, vec![3, 2, 3, 3, 1]);
+        let starts: Vec<usize> = layout.iter().map(|a| a.start_idx / 4).collect();
+        assert_eq!(starts, vec![0, 3, 5, 8, 11]);
+        assert!(layout.iter().all(|a| a.stride == 12 * 4));
+
+        assert_eq!(mesh.vertex_count(), 8);
+        //Six quads, the edge element is skipped
+        assert_eq!(mesh.indices.len(), 6 * 2 * 3);
+        assert!(mesh.indices.iter().all(|idx| *idx < 8));
+
+        //Corner 3 is (1, 1, 0), uchar colors come out as 0 to 1
+        let v = &mesh.interleaved()[3 * 12..4 * 12];
+        assert_eq!(v, &[1.0, 1.0, 0.0, 0.5, 0.25, 0.5, 0.5, -0.5, 1.0, 1.0, 0.0, 0.375]);
+    }
+
+    #[test]
+    fn binary_matches_ascii() {
+        let ascii = fixture("cube_ascii.ply");
+        for name in ["cube_binary_le.ply", "cube_binary_be.ply"].iter() {
+            let binary = fixture(name);
+            assert_eq!(binary.streams, ascii.streams, "{}", name);
+            assert_eq!(binary.indices, ascii.indices, "{}", name);
+        }
+    }
+
+    #[test]
+    fn reports_truncated_and_malformed_files() {
+        let mut bytes = fixture_bytes("cube_binary_le.ply");
+        let header_len = bytes.windows(11).position(|w| w == b"end_header\n").unwrap() + 11;
+        //Vertices are 39 bytes, cut the file in the middle of the sixth one
+        bytes.truncate(header_len + 5 * 39 + 10);
+        match parse(bytes.as_slice()) {
+            Err(PlyError::Body { element, index, .. }) => assert_eq!((element.as_str(), index), ("vertex", 5)),
+            other => panic!("expected a body error, got {:?}", other.err()),
+        }
+
+        let src = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nend_header\n0 0\n";
+        match parse(src.as_bytes()) {
+            Err(PlyError::MissingPosition) => (),
+            other => panic!("expected a missing position, got {:?}", other.err()),
+        }
+
+        let src = "ply\nformat ascii 1.0\nelement vertex 1\nproperty vec3 x\nend_header\n";
+        match parse(src.as_bytes()) {
+            Err(PlyError::Header { line, .. }) => assert_eq!(line, 4),
+            other => panic!("expected a header error, got {:?}", other.err()),
+        }
+
+        let src = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n";
+        match parse(src.as_bytes()) {
+            Err(PlyError::Body { element, index, .. }) => assert_eq!((element.as_str(), index), ("face", 0)),
+            other => panic!("expected an out of range face, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_list_lengths_and_indices() {
+        let head = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                    element face 2\nproperty list uint int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        for face in ["1e30 0 1 2", "-3 0 1 2", "2.5 0 1 2", "3 0 -1 2", "3 0 1.5 2", "3 0 1 4294967296", "4 0 1 2"].iter() {
+            let src = format!("{}{}\n", head, face);
+            match parse(src.as_bytes()) {
+                Err(PlyError::Body { element, index, .. }) => assert_eq!((element.as_str(), index), ("face", 1), "{}", face),
+                other => panic!("expected a body error for '{}', got {:?}", face, other.err()),
+            }
+        }
+        assert_eq!(parse(format!("{}3 2 1 0\n", head).as_bytes()).unwrap().indices, vec![0, 1, 2, 2, 1, 0]);
+
+        //A binary list claiming four billion entries fails without trying to allocate them
+        let mut bytes = b"ply\nformat binary_little_endian 1.0\nelement vertex 0\nproperty float x\nproperty float y\n\
+                          property float z\nelement face 1\nproperty list uint int vertex_indices\nend_header\n".to_vec();
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[0; 12]);
+        match parse(bytes.as_slice()) {
+            Err(PlyError::Body { element, index, .. }) => assert_eq!((element.as_str(), index), ("face", 0)),
+            other => panic!("expected a body error, got {:?}", other.err()),
+        }
+
+        //So does an element count far past the end of the file
+        let src = "ply\nformat ascii 1.0\nelement vertex 18446744073709551615\nproperty float x\nproperty float y\n\
+                   property float z\nend_header\n0 0 0\n";
+        match parse(src.as_bytes()) {
+            Err(PlyError::Body { element, index, .. }) => assert_eq!((element.as_str(), index), ("vertex", 1)),
+            other => panic!("expected a body error, got {:?}", other.err()),
+        }
+    }
+}
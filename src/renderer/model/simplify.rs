@@ -0,0 +1,620 @@
+use crate::renderer::model::Submesh;
+use crate::renderer::model::mesh::{key, Mesh};
+use crate::renderer::model::optimize;
+use crate::renderer::model::obj::FxHasher;
+use crate::math::{Aabb, Mat4, Vec3};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasherDefault;
+
+type FxHashMap<K, V> = HashMap<K, V, BuildHasherDefault<FxHasher>>;
+type FxHashSet<K> = HashSet<K, BuildHasherDefault<FxHasher>>;
+
+//Moving a border or UV seam off its line costs this much more than moving
+//the same distance off a face of the same size
+const BOUNDARY_WEIGHT: f64 = 10.0;
+//Collapses may turn a triangle by at most about 75 degrees
+const MIN_NORMAL_DOT: f32 = 0.25;
+
+//A coarser index buffer over the vertices of the mesh it was made from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lod {
+    pub indices   : Vec<u32>,
+    pub submeshes : Vec<Submesh>,
+    //Area weighted RMS distance from the worst collapsed vertex to the
+    //surface it replaced, as a fraction of the mesh's bounding box diagonal
+    pub error     : f32,
+}
+
+//How a position may move when it is collapsed into a neighbour
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Interior,
+    //Only along the border, so open edges keep their outline
+    Border,
+    //Only along the seam, with both sides of the UV split moving together
+    Seam,
+    //Non-manifold spots, seam ends and where borders and seams meet
+    Locked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edge {
+    Interior,
+    Border,
+    Seam,
+}
+
+//Weighted sum of squared distances to a set of planes (a, b, c, d), kept as
+//the upper triangle of the symmetric matrix sum of w(a, b, c, d)(a, b, c, d)^T
+//followed by the sum of the weights
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64;11]);
+
+impl Quadric {
+    fn from_plane(normal: Vec3, point: Vec3, weight: f64) -> Self {
+        let (a, b, c) = (f64::from(normal.x()), f64::from(normal.y()), f64::from(normal.z()));
+        let d = -f64::from(normal.dot(point));
+        let w = weight;
+        Quadric([
+            a * a * w, a * b * w, a * c * w, a * d * w,
+            b * b * w, b * c * w, b * d * w,
+            c * c * w, c * d * w,
+            d * d * w,
+            w,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) -> () {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    //Weighted mean of the squared distances, so it stays a squared length
+    //however many planes have been merged in
+    fn error(&self, p: Vec3) -> f64 {
+        let q = &self.0;
+        if q[10] <= 0.0 {
+            return 0.0;
+        }
+        let (x, y, z) = (f64::from(p.x()), f64::from(p.y()), f64::from(p.z()));
+        let e = q[0] * x * x + q[4] * y * y + q[7] * z * z
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[5] * y * z + q[3] * x + q[6] * y + q[8] * z)
+            + q[9];
+        //Rounding can push a perfect fit just below zero
+        (e / q[10]).max(0.0)
+    }
+}
+
+//Cost, the position to move onto and the vertex each moved vertex becomes
+type Collapse = (f64, usize, Vec<(u32, u32)>);
+
+//Connectivity of the current triangles, by position rather than by vertex
+struct Topology {
+    around   : Vec<Vec<usize>>,
+    kinds    : Vec<Kind>,
+    //Keyed by (low, high) position
+    edges    : FxHashMap<(u32, u32), Edge>,
+    //Border and seam edges with one of their triangles, in triangle order
+    boundary : Vec<(u32, u32, usize)>,
+}
+
+//Collapses edges in order of quadric error until the mesh is down to
+//target_triangles or the next collapse would move the surface more than
+//max_error, a fraction of the bounding box diagonal. Vertices only ever
+//move onto a neighbour, so the result indexes the mesh's own vertices and
+//no attribute has to be interpolated. Borders and UV seams only collapse
+//along themselves and submeshes keep their triangles.
+pub fn simplify(mesh: &Mesh, target_triangles: usize, max_error: f32) -> Lod {
+    //Vertices that were only split for their attributes share a position
+    let mut positions = Vec::new();
+    let mut lookup = FxHashMap::<[u32;3], u32>::default();
+    let group: Vec<u32> = (0..mesh.vertex_count() as u32).map(|v| {
+        let p = mesh.position(v);
+        *lookup.entry(key(p)).or_insert_with(|| {
+            positions.push(p);
+            positions.len() as u32 - 1
+        })
+    }).collect();
+
+    let mut triangles = Vec::with_capacity(mesh.triangle_count());
+    let mut owners = Vec::with_capacity(mesh.triangle_count());
+    for (submesh, (start, count)) in optimize::ranges(&mesh.submeshes, mesh.indices.len()).into_iter().enumerate() {
+        for t in mesh.indices[start..start + count].chunks(3).filter(|t| t.len() == 3) {
+            triangles.push([t[0], t[1], t[2]]);
+            owners.push(submesh);
+        }
+    }
+
+    let corners = |tri: &[u32;3], positions: &[Vec3]| {
+        [positions[group[tri[0] as usize] as usize], positions[group[tri[1] as usize] as usize], positions[group[tri[2] as usize] as usize]]
+    };
+    //Planes are weighted by area so the mean follows the surface rather
+    //than how finely it happens to be tessellated
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    for tri in triangles.iter() {
+        let p = corners(tri, &positions);
+        let face = (p[1] - p[0]).cross(p[2] - p[0]);
+        let normal = face.normalize();
+        if normal != Vec3::zero() {
+            let q = Quadric::from_plane(normal, p[0], f64::from(face.length()) * 0.5);
+            for &v in tri {
+                quadrics[group[v as usize] as usize].add(&q);
+            }
+        }
+    }
+    //Planes through border and seam edges, standing up from their face
+    for &(a, b, t) in topology(&triangles, &group, positions.len()).boundary.iter() {
+        let p = corners(&triangles[t], &positions);
+        let face = (p[1] - p[0]).cross(p[2] - p[0]);
+        let edge = positions[b as usize] - positions[a as usize];
+        let normal = edge.cross(face).normalize();
+        if normal != Vec3::zero() {
+            let q = Quadric::from_plane(normal, positions[a as usize], BOUNDARY_WEIGHT * f64::from(edge.length_squared()));
+            quadrics[a as usize].add(&q);
+            quadrics[b as usize].add(&q);
+        }
+    }
+
+    let diagonal = if mesh.bounds.is_empty() { 0.0 } else { f64::from((mesh.bounds.max - mesh.bounds.min).length()) };
+    let limit = (f64::from(max_error) * diagonal).powi(2);
+    let mut error = 0.0f64;
+
+    //Every pass collapses a set of edges whose neighbourhoods don't overlap,
+    //so the topology it was planned on stays valid while applying it
+    while triangles.len() > target_triangles {
+        let topo = topology(&triangles, &group, positions.len());
+        let mut candidates: Vec<(usize, Collapse)> = (0..positions.len()).into_par_iter().filter_map(|p| {
+            best_collapse(p, &topo, &triangles, &group, &positions, &quadrics).map(|collapse| (p, collapse))
+        }).collect();
+        candidates.sort_by(|a, b| (a.1).0.partial_cmp(&(b.1).0).unwrap());
+        //Only the cheapest quarter goes per pass, otherwise pricey collapses
+        //get in while cheaper ones wait for their locked neighbours
+        let pass_limit = match candidates.get((candidates.len().max(1) - 1) / 4) {
+            Some((_, (cost, _, _))) => cost.min(limit),
+            None => break,
+        };
+
+        let mut touched = vec![false; positions.len()];
+        let mut remap = FxHashMap::<u32, u32>::default();
+        let mut removed = 0;
+        for (p, (cost, q, wedges)) in candidates {
+            if cost > pass_limit || triangles.len() - removed <= target_triangles {
+                break;
+            }
+            if touched[p] || touched[q] {
+                continue;
+            }
+            remap.extend(wedges);
+            let merged = quadrics[p];
+            quadrics[q].add(&merged);
+            error = error.max(cost);
+            for &t in topo.around[p].iter() {
+                if triangles[t].iter().any(|&v| group[v as usize] as usize == q) {
+                    removed += 1;
+                }
+                for &v in triangles[t].iter() {
+                    touched[group[v as usize] as usize] = true;
+                }
+            }
+        }
+        if remap.is_empty() {
+            break;
+        }
+
+        let moved = |v: u32| *remap.get(&v).unwrap_or(&v);
+        let mut kept = 0;
+        for t in 0..triangles.len() {
+            let tri = [moved(triangles[t][0]), moved(triangles[t][1]), moved(triangles[t][2])];
+            let g = [group[tri[0] as usize], group[tri[1] as usize], group[tri[2] as usize]];
+            if g[0] != g[1] && g[1] != g[2] && g[0] != g[2] {
+                triangles[kept] = tri;
+                owners[kept] = owners[t];
+                kept += 1;
+            }
+        }
+        triangles.truncate(kept);
+        owners.truncate(kept);
+    }
+
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+    let mut submeshes = mesh.submeshes.clone();
+    if submeshes.is_empty() {
+        indices.extend(triangles.iter().flat_map(|t| t.iter().cloned()));
+    }
+    for (s, submesh) in submeshes.iter_mut().enumerate() {
+        submesh.start = indices.len();
+        for (tri, _) in triangles.iter().zip(owners.iter()).filter(|(_, &owner)| owner == s) {
+            indices.extend_from_slice(tri);
+        }
+        submesh.count = indices.len() - submesh.start;
+    }
+    let error = if diagonal > 0.0 { (error.sqrt() / diagonal) as f32 } else { 0.0 };
+    Lod { indices, submeshes, error }
+}
+
+//Each level aims for half the triangles of the one before, simplified from
+//the full mesh so errors don't pile up. Stops early once max_error keeps a
+//level from getting meaningfully smaller. Finest level first.
+pub fn lod_chain(mesh: &Mesh, levels: usize, max_error: f32) -> Vec<Lod> {
+    let mut lods: Vec<Lod> = Vec::with_capacity(levels);
+    let mut triangle_cnt = mesh.triangle_count();
+    for _ in 0..levels {
+        let mut lod = simplify(mesh, triangle_cnt / 2, max_error);
+        let simplified_cnt = lod.indices.len() / 3;
+        if simplified_cnt == 0 || simplified_cnt * 10 > triangle_cnt * 9 {
+            break;
+        }
+        optimize::optimize_index_order(&mut lod.indices, &lod.submeshes, mesh.vertex_count());
+        triangle_cnt = simplified_cnt;
+        lods.push(lod);
+    }
+    lods
+}
+
+//Coarsest level whose error covers at most `threshold` of the screen
+//height, None for the full model. `errors` are Lod::error finest first and
+//`bounds` the model space box they are relative to.
+pub fn select_lod(
+    errors: impl IntoIterator<Item = f32>,
+    bounds: &Aabb,
+    model: &Mat4,
+    view: &Mat4,
+    projection: &Mat4,
+    threshold: f32,
+) -> Option<usize> {
+    if bounds.is_empty() {
+        return None;
+    }
+    let world = bounds.transform(model);
+    let center = view * world.center().extend(1.0);
+    let radius = world.extents().length();
+    //Inside the bounding sphere or behind the camera
+    let distance = -center.z();
+    if distance <= radius {
+        return None;
+    }
+    //Diameter of the bounding sphere over the screen height. Errors are
+    //relative to the box diagonal, which is that same diameter.
+    let screen_size = radius * projection.at(1, 1) / distance;
+    errors.into_iter()
+        .enumerate()
+        .filter(|(_, error)| error * screen_size <= threshold)
+        .map(|(level, _)| level)
+        .last()
+}
+
+fn topology(triangles: &[[u32;3]], group: &[u32], group_cnt: usize) -> Topology {
+    let mut around = vec![Vec::new(); group_cnt];
+    let mut directed = FxHashMap::<(u32, u32), u32>::default();
+    let mut wedge_edges = FxHashSet::<(u32, u32)>::default();
+    for (t, tri) in triangles.iter().enumerate() {
+        for k in 0..3 {
+            let (u, v) = (tri[k], tri[(k + 1) % 3]);
+            around[group[u as usize] as usize].push(t);
+            *directed.entry((group[u as usize], group[v as usize])).or_insert(0) += 1;
+            wedge_edges.insert((u, v));
+        }
+    }
+
+    let mut locked = vec![false; group_cnt];
+    let mut borders = vec![0; group_cnt];
+    let mut seams = vec![0; group_cnt];
+    let mut edges = FxHashMap::default();
+    let mut boundary = Vec::new();
+    for (t, tri) in triangles.iter().enumerate() {
+        for k in 0..3 {
+            let (u, v) = (tri[k], tri[(k + 1) % 3]);
+            let (a, b) = (group[u as usize], group[v as usize]);
+            let forward = directed[&(a, b)];
+            let backward = directed.get(&(b, a)).cloned().unwrap_or(0);
+            if forward > 1 || backward > 1 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+                continue;
+            }
+            let edge = if backward == 0 {
+                Edge::Border
+            } else if !wedge_edges.contains(&(v, u)) {
+                Edge::Seam
+            } else {
+                Edge::Interior
+            };
+            let key = (a.min(b), a.max(b));
+            if edges.contains_key(&key) {
+                continue;
+            }
+            edges.insert(key, edge);
+            let counts = match edge {
+                Edge::Border => &mut borders,
+                Edge::Seam => &mut seams,
+                Edge::Interior => continue,
+            };
+            counts[a as usize] += 1;
+            counts[b as usize] += 1;
+            boundary.push((a, b, t));
+        }
+    }
+
+    let kinds = (0..group_cnt).map(|g| {
+        let mut wedges: Vec<u32> = around[g].iter()
+            .flat_map(|&t| triangles[t].iter().cloned())
+            .filter(|&v| group[v as usize] as usize == g)
+            .collect();
+        wedges.sort();
+        wedges.dedup();
+        match (locked[g], borders[g], seams[g], wedges.len()) {
+            (false, 0, 0, 1) => Kind::Interior,
+            (false, 2, 0, 1) => Kind::Border,
+            (false, 0, 2, 2) => Kind::Seam,
+            _ => Kind::Locked,
+        }
+    }).collect();
+    Topology { around, kinds, edges, boundary }
+}
+
+//Cheapest valid neighbour to move position p onto, with the vertex each of
+//p's vertices turns into
+fn best_collapse(
+    p: usize,
+    topo: &Topology,
+    triangles: &[[u32;3]],
+    group: &[u32],
+    positions: &[Vec3],
+    quadrics: &[Quadric],
+) -> Option<Collapse> {
+    if topo.kinds[p] == Kind::Locked {
+        return None;
+    }
+    let group_of = |v: u32| group[v as usize] as usize;
+    let neighbours = |g: usize| {
+        let mut n: Vec<usize> = topo.around[g].iter()
+            .flat_map(|&t| triangles[t].iter().map(|&v| group_of(v)))
+            .filter(|&other| other != g)
+            .collect();
+        n.sort();
+        n.dedup();
+        n
+    };
+
+    let around_p = neighbours(p);
+    let mut options: Vec<(f64, usize)> = around_p.iter().filter(|&&q| {
+        let edge = topo.edges.get(&(p.min(q) as u32, p.max(q) as u32)).cloned();
+        match topo.kinds[p] {
+            Kind::Interior => true,
+            Kind::Border => edge == Some(Edge::Border) && (topo.kinds[q] == Kind::Border || topo.kinds[q] == Kind::Locked),
+            Kind::Seam => edge == Some(Edge::Seam) && (topo.kinds[q] == Kind::Seam || topo.kinds[q] == Kind::Locked),
+            Kind::Locked => false,
+        }
+    }).map(|&q| {
+        let mut merged = quadrics[p];
+        merged.add(&quadrics[q]);
+        (merged.error(positions[q]), q)
+    }).collect();
+    options.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    //Cheapest first, so usually only one option has to be checked in full
+    'options: for (cost, q) in options {
+        //Only the triangles on the edge may go, a third shared neighbour
+        //would leave a fin behind
+        let shared_triangles = topo.around[p].iter().filter(|&&t| triangles[t].iter().any(|&v| group_of(v) == q)).count();
+        let around_q = neighbours(q);
+        let shared_neighbours = around_p.iter().filter(|n| around_q.contains(n)).count();
+        if shared_neighbours != shared_triangles {
+            continue;
+        }
+
+        //Each vertex at p has to land on exactly one vertex at q, otherwise
+        //the collapse would tear or mix UV charts
+        let mut wedges: Vec<(u32, u32)> = Vec::new();
+        for &t in topo.around[p].iter() {
+            let from = *triangles[t].iter().find(|&&v| group_of(v) == p).unwrap();
+            if wedges.iter().any(|&(w, _)| w == from) {
+                continue;
+            }
+            let mut targets: Vec<u32> = topo.around[p].iter()
+                .filter(|&&other| triangles[other].contains(&from))
+                .flat_map(|&other| triangles[other].iter().cloned())
+                .filter(|&v| group_of(v) == q)
+                .collect();
+            targets.sort();
+            targets.dedup();
+            if targets.len() != 1 {
+                continue 'options;
+            }
+            wedges.push((from, targets[0]));
+        }
+
+        let flips = topo.around[p].iter().filter(|&&t| !triangles[t].iter().any(|&v| group_of(v) == q)).any(|&t| {
+            let corners: Vec<usize> = triangles[t].iter().map(|&v| group_of(v)).collect();
+            let normal = |moved: bool| {
+                let at = |g: usize| if moved && g == p { positions[q] } else { positions[g] };
+                let (a, b, c) = (at(corners[0]), at(corners[1]), at(corners[2]));
+                (b - a).cross(c - a).normalize()
+            };
+            let (before, after) = (normal(false), normal(true));
+            after == Vec3::zero() || before.dot(after) < MIN_NORMAL_DOT
+        });
+        if !flips {
+            return Some((cost, q, wedges));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::model::mesh::Semantic;
+
+    //n by n unit quads over x and y. With split_at, the column of vertices at
+    //that x is doubled and the two sides get their own UV chart.
+    fn grid(n: u32, split_at: Option<u32>, height: &dyn Fn(f32, f32) -> f32) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut index_of = HashMap::new();
+        for y in 0..=n {
+            for x in 0..=n {
+                let charts: Vec<u32> = match split_at {
+                    Some(s) if x == s => vec![0, 1],
+                    Some(s) if x > s => vec![1],
+                    _ => vec![0],
+                };
+                for chart in charts {
+                    let (fx, fy) = (x as f32, y as f32);
+                    let u = if chart == 0 { fx } else { fx - split_at.unwrap() as f32 + 100.0 };
+                    index_of.insert((x, y, chart), vertices.len() as u32 / 5);
+                    vertices.extend_from_slice(&[fx, fy, height(fx, fy), u, fy]);
+                }
+            }
+        }
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let c = match split_at {
+                    Some(s) if x >= s => 1,
+                    _ => 0,
+                };
+                let v = |dx: u32, dy: u32| index_of[&(x + dx, y + dy, c)];
+                indices.extend_from_slice(&[v(0, 0), v(1, 0), v(1, 1), v(0, 0), v(1, 1), v(0, 1)]);
+            }
+        }
+        let semantics = [(Semantic::Position, 3), (Semantic::TexCoord(0), 2)];
+        Mesh::from_interleaved("grid", &vertices, &semantics, indices)
+    }
+
+    fn flat(_: f32, _: f32) -> f32 {
+        0.0
+    }
+
+    fn area(mesh: &Mesh, indices: &[u32]) -> f32 {
+        indices.chunks(3).map(|t| {
+            let (a, b, c) = (mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2]));
+            //Signed along +z so folded triangles would show up
+            (b - a).cross(c - a).z() * 0.5
+        }).sum()
+    }
+
+    #[test]
+    fn flat_grid_collapses_but_keeps_its_outline() {
+        let mesh = grid(16, None, &flat);
+        let lod = simplify(&mesh, 0, 0.0001);
+        assert!(lod.indices.len() / 3 <= 8, "{} triangles left", lod.indices.len() / 3);
+        assert_eq!(lod.error, 0.0);
+        assert_approx_eq!(area(&mesh, &lod.indices), 256.0);
+        //All four corners are still in use
+        for corner in [(0.0, 0.0), (16.0, 0.0), (0.0, 16.0), (16.0, 16.0)].iter() {
+            assert!(lod.indices.iter().any(|&i| mesh.position(i) == Vec3::new(corner.0, corner.1, 0.0)));
+        }
+    }
+
+    #[test]
+    fn stops_at_the_target_or_the_error_limit() {
+        let bumpy = |x: f32, y: f32| (x * 0.7).sin() * (y * 0.5).cos();
+        let mesh = grid(24, None, &bumpy);
+        let half = simplify(&mesh, mesh.triangle_count() / 2, 1.0);
+        assert!(half.indices.len() / 3 <= mesh.triangle_count() / 2);
+        assert!(half.indices.len() / 3 > mesh.triangle_count() / 4);
+        assert!(half.error > 0.0);
+
+        //Flattening the bumps takes much more than this
+        let strict = simplify(&mesh, 0, half.error * 0.5);
+        assert!(strict.error <= half.error * 0.5);
+        assert!(strict.indices.len() / 3 > 100, "{} triangles left", strict.indices.len() / 3);
+    }
+
+    #[test]
+    fn uv_seams_survive_and_keep_their_charts() {
+        let mut mesh = grid(16, Some(8), &flat);
+        let quad = |start, count| Submesh { material_name: None, material: None, start, count };
+        let half = mesh.indices.len() / 2;
+        mesh.submeshes = vec![quad(0, half), quad(half, half)];
+
+        let lod = simplify(&mesh, 0, 0.0001);
+        assert!(lod.indices.len() < mesh.indices.len() / 8);
+        assert_eq!(lod.submeshes[1].start, lod.submeshes[0].count);
+        assert_eq!(lod.submeshes[0].count + lod.submeshes[1].count, lod.indices.len());
+
+        let uvs = &mesh.stream(&Semantic::TexCoord(0)).unwrap().data;
+        for t in lod.indices.chunks(3) {
+            //Every triangle stays on one side of the seam and uses that side's UVs
+            let xs: Vec<f32> = t.iter().map(|&i| mesh.position(i).x()).collect();
+            let right = xs.iter().any(|&x| x > 8.0);
+            assert!(xs.iter().all(|&x| if right { x >= 8.0 } else { x <= 8.0 }));
+            for &i in t {
+                let u = uvs[i as usize * 2];
+                let expected = if right { mesh.position(i).x() + 92.0 } else { mesh.position(i).x() };
+                assert_eq!(u, expected);
+            }
+        }
+        assert_approx_eq!(area(&mesh, &lod.indices), 256.0);
+    }
+
+    #[test]
+    fn error_is_a_distance_whatever_the_tessellation() {
+        //The same bump, one unit high on a 16 by 16 square, at two densities
+        let errors: Vec<Vec<f32>> = [16, 32].iter().map(|&n| {
+            let scale = 16.0 / n as f32;
+            let bump = |x: f32, y: f32| {
+                let (dx, dy) = (x * scale - 8.0, y * scale - 8.0);
+                (-(dx * dx + dy * dy) / 8.0).exp()
+            };
+            let mut mesh = grid(n, None, &bump);
+            let positions: Vec<f32> = mesh.positions().chunks(3)
+                .flat_map(|p| vec![p[0] * scale, p[1] * scale, p[2]])
+                .collect();
+            mesh.set_stream(Semantic::Position, 3, positions);
+            let diagonal = (mesh.bounds.max - mesh.bounds.min).length();
+            [32, 8].iter().map(|&target| simplify(&mesh, target, 1.0).error * diagonal).collect()
+        }).collect();
+        for (coarse, fine) in errors[0].iter().zip(errors[1].iter()) {
+            //Never more than the bump is high, and not much more for finer input
+            assert!(*coarse > 0.0 && *coarse < 1.0, "{}", coarse);
+            assert!(*fine > 0.0 && *fine < coarse * 2.0, "{} against {}", fine, coarse);
+        }
+    }
+
+    #[test]
+    fn selects_coarser_levels_further_away() {
+        use std::f32::consts::FRAC_PI_2;
+        let bounds = Aabb { min: Vec3::splat(-1.0), max: Vec3::splat(1.0) };
+        let projection = Mat4::perspective(FRAC_PI_2, 1.0, 0.1, 1000.0);
+        let errors = [0.001, 0.01, 0.1];
+        let at = |z: f32| select_lod(errors.iter().cloned(), &bounds, &Mat4::from_translation(0.0, 0.0, z),
+                                     &Mat4::identity(), &projection, 0.001);
+
+        //The sphere's radius is sqrt(3) and its diameter covers sqrt(3) / distance
+        //of the screen height, so level i is fine from 10^i * sqrt(3) units away
+        assert_eq!(at(-1.5), None);
+        assert_eq!(at(-5.0), Some(0));
+        assert_eq!(at(-50.0), Some(1));
+        assert_eq!(at(-500.0), Some(2));
+        //A near model gets no level at all, and neither does one behind the camera
+        assert_eq!(select_lod(errors.iter().cloned(), &bounds, &Mat4::from_translation(0.0, 0.0, -1.7),
+                              &Mat4::identity(), &projection, 1.0), None);
+        assert_eq!(at(500.0), None);
+
+        //The model and view matrices both move the box
+        let view = Mat4::from_translation(0.0, 0.0, -50.0);
+        assert_eq!(select_lod(errors.iter().cloned(), &bounds, &Mat4::identity(), &view, &projection, 0.001), Some(1));
+        let scaled = Mat4::from_translation(0.0, 0.0, -50.0) * Mat4::from_scale(10.0, 10.0, 10.0);
+        assert_eq!(select_lod(errors.iter().cloned(), &bounds, &scaled, &Mat4::identity(), &projection, 0.001), Some(0));
+        assert_eq!(select_lod(errors.iter().cloned(), &Aabb::empty(), &scaled, &Mat4::identity(), &projection, 1.0), None);
+    }
+
+    #[test]
+    fn lod_chain_gets_coarser_with_growing_error() {
+        let bumpy = |x: f32, y: f32| (x * 0.3).sin() + (y * 0.4).cos();
+        let mesh = grid(32, None, &bumpy);
+        let lods = lod_chain(&mesh, 4, 0.05);
+        assert!(lods.len() >= 2, "{} levels", lods.len());
+        let mut triangles = mesh.triangle_count();
+        let mut error = 0.0;
+        for lod in lods.iter() {
+            assert!(lod.indices.len() / 3 < triangles);
+            assert!(lod.error >= error);
+            triangles = lod.indices.len() / 3;
+            error = lod.error;
+        }
+    }
+}
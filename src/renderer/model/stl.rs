@@ -0,0 +1,357 @@
+use crate::renderer::model::mesh::{key, Mesh, Semantic};
+use crate::math::Vec3;
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::fs::File;
+use std::fmt;
+use std::path::Path;
+
+const HEADER_LEN: usize = 80;
+const TRIANGLE_LEN: usize = 50;
+
+//STL repeats every corner once per triangle and only has facet normals
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normals {
+    //Corners are shared between triangles of the same plane only, edges
+    //stay hard
+    Flat,
+    //Corners are shared by position and get the area weighted average of
+    //their triangles' normals
+    Smooth,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Ascii,
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum StlError {
+    Io(io::Error),
+    //One based line of an ascii file
+    Syntax { line: usize, reason: String },
+    //A binary file shorter than its triangle count says
+    Truncated { expected: usize, found: usize },
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::Io(e) => write!(f, "{}", e),
+            StlError::Syntax { line, reason } => write!(f, "line {}: {}", line, reason),
+            StlError::Truncated { expected, found } => {
+                write!(f, "expected {} bytes of triangles, found {}", expected, found)
+            },
+        }
+    }
+}
+
+impl std::error::Error for StlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StlError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StlError {
+    fn from(e: io::Error) -> Self {
+        StlError::Io(e)
+    }
+}
+
+//Facet normal as written in the file, then the three corners
+type Facet = [Vec3;4];
+
+pub fn load(path: &Path, normals: Normals) -> Result<Mesh, StlError> {
+    let bytes = std::fs::read(path)?;
+    let mesh = parse(&bytes, normals)?;
+
+    trace!("{}: {} vertices, {} indices", path.display(), mesh.vertex_count(), mesh.indices.len());
+
+    Ok(mesh)
+}
+
+//Welded, indexed triangles with a position and a normal stream. The mesh
+//is named after "solid", or the 80 byte header of binary files with
+//trailing padding removed.
+pub fn parse(bytes: &[u8], normals: Normals) -> Result<Mesh, StlError> {
+    let (name, facets) = match detect_format(bytes) {
+        Format::Ascii => parse_ascii(bytes)?,
+        Format::Binary => parse_binary(bytes)?,
+    };
+    let (vertices, indices) = weld(&facets, normals);
+    Ok(Mesh::from_interleaved(&name, &vertices, &[(Semantic::Position, 3), (Semantic::Normal, 3)], indices))
+}
+
+//Binary files may start with "solid" too, so the size has the final say
+fn detect_format(bytes: &[u8]) -> Format {
+    if bytes.len() >= HEADER_LEN + 4 {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if HEADER_LEN + 4 + count * TRIANGLE_LEN == bytes.len() {
+            return Format::Binary;
+        }
+    }
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(0);
+    if bytes[start..].starts_with(b"solid") {
+        Format::Ascii
+    } else {
+        Format::Binary
+    }
+}
+
+fn parse_binary(bytes: &[u8]) -> Result<(String, Vec<Facet>), StlError> {
+    if bytes.len() < HEADER_LEN + 4 {
+        return Err(StlError::Truncated { expected: HEADER_LEN + 4, found: bytes.len() });
+    }
+    let name = String::from_utf8_lossy(&bytes[..HEADER_LEN])
+        .trim_end_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string();
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    let triangles = &bytes[HEADER_LEN + 4..];
+    if triangles.len() < count * TRIANGLE_LEN {
+        return Err(StlError::Truncated { expected: count * TRIANGLE_LEN, found: triangles.len() });
+    }
+
+    let float = |b: &[u8], i: usize| f32::from_le_bytes([b[i * 4], b[i * 4 + 1], b[i * 4 + 2], b[i * 4 + 3]]);
+    //The two trailing attribute bytes of every triangle are ignored
+    let facets = triangles.chunks(TRIANGLE_LEN).take(count).map(|t| {
+        let mut facet = [Vec3::zero();4];
+        for (i, v) in facet.iter_mut().enumerate() {
+            *v = Vec3::new(float(t, i * 3), float(t, i * 3 + 1), float(t, i * 3 + 2));
+        }
+        facet
+    }).collect();
+    Ok((name, facets))
+}
+
+fn parse_ascii(bytes: &[u8]) -> Result<(String, Vec<Facet>), StlError> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut name = None;
+    let mut facets = Vec::<Facet>::new();
+    //Corners of the facet being read, None outside of facet/endfacet
+    let mut corners: Option<Vec<Vec3>> = None;
+    let mut normal = Vec3::zero();
+    for (line_idx, line) in text.lines().enumerate() {
+        let syntax_error = |reason: String| StlError::Syntax { line: line_idx + 1, reason };
+        let mut components = line.split_whitespace();
+        let keyword = match components.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let vector = |components: std::str::SplitWhitespace| -> Result<Vec3, StlError> {
+            let values = components
+                .map(|c| c.parse::<f32>().map_err(|_| syntax_error(format!("could not parse '{}'", c))))
+                .collect::<Result<Vec<f32>, StlError>>()?;
+            if values.len() != 3 {
+                return Err(syntax_error(format!("expected 3 values, found {}", values.len())));
+            }
+            Ok(Vec3::new(values[0], values[1], values[2]))
+        };
+        match keyword {
+            "solid" => {
+                if name.is_none() {
+                    name = Some(components.collect::<Vec<&str>>().join(" "));
+                }
+            },
+            "facet" => {
+                if corners.is_some() {
+                    return Err(syntax_error("facet inside of a facet".to_string()));
+                }
+                if components.next() != Some("normal") {
+                    return Err(syntax_error("expected 'facet normal'".to_string()));
+                }
+                normal = vector(components)?;
+                corners = Some(Vec::with_capacity(3));
+            },
+            "vertex" => {
+                let vertex = vector(components)?;
+                match corners.as_mut() {
+                    Some(corners) => corners.push(vertex),
+                    None => return Err(syntax_error("vertex outside of a facet".to_string())),
+                }
+            },
+            "endfacet" => {
+                match corners.take() {
+                    Some(ref c) if c.len() == 3 => facets.push([normal, c[0], c[1], c[2]]),
+                    Some(c) => return Err(syntax_error(format!("facet has {} vertices, expected 3", c.len()))),
+                    None => return Err(syntax_error("endfacet without a facet".to_string())),
+                }
+            },
+            "outer" | "endloop" | "endsolid" => (),
+            other => return Err(syntax_error(format!("unexpected '{}'", other))),
+        }
+    }
+    if corners.is_some() {
+        return Err(StlError::Syntax { line: text.lines().count(), reason: "missing endfacet".to_string() });
+    }
+    Ok((name.unwrap_or_default(), facets))
+}
+
+//The facet normal is recomputed from the corners since exporters often
+//write zeros, the stored one is only used for triangles without area
+fn face_normal(facet: &Facet) -> Vec3 {
+    let cross = (facet[2] - facet[1]).cross(facet[3] - facet[1]);
+    if cross.length_squared() > 0.0 {
+        cross
+    } else {
+        facet[0]
+    }
+}
+
+fn weld(facets: &[Facet], normals: Normals) -> (Vec<gl::types::GLfloat>, Vec<u32>) {
+    let mut vertices = Vec::<gl::types::GLfloat>::new();
+    let mut indices = Vec::<u32>::with_capacity(facets.len() * 3);
+    let mut lookup = HashMap::<([u32;3], [u32;3]), u32>::new();
+    //Unnormalized sum per vertex for Normals::Smooth
+    let mut sums = Vec::<Vec3>::new();
+    for facet in facets.iter() {
+        //Its length is twice the area, which is the weight for smooth normals
+        let area_normal = face_normal(facet);
+        let normal = area_normal.normalize();
+        for corner in facet[1..].iter() {
+            let normal_key = match normals {
+                Normals::Flat => key(normal),
+                Normals::Smooth => [0;3],
+            };
+            let next = lookup.len() as u32;
+            let idx = *lookup.entry((key(*corner), normal_key)).or_insert_with(|| {
+                vertices.extend_from_slice(&corner.to_array());
+                vertices.extend_from_slice(&normal.to_array());
+                sums.push(Vec3::zero());
+                next
+            });
+            sums[idx as usize] += area_normal;
+            indices.push(idx);
+        }
+    }
+    if normals == Normals::Smooth {
+        for (i, sum) in sums.iter().enumerate() {
+            vertices[i * 6 + 3..i * 6 + 6].copy_from_slice(&sum.normalize().to_array());
+        }
+    }
+    (vertices, indices)
+}
+
+//Only positions are written, normals are recomputed from the triangles
+pub fn save(path: &Path, mesh: &Mesh, format: Format) -> io::Result<()> {
+    let name = mesh.name.as_str();
+    let indices = &mesh.indices;
+    let triangles = indices.chunks(3).filter(|t| t.len() == 3).map(|t| {
+        let corners = [mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2])];
+        let normal = (corners[1] - corners[0]).cross(corners[2] - corners[0]).normalize();
+        (normal, corners)
+    });
+
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Ascii => {
+            writeln!(out, "solid {}", name)?;
+            for (normal, corners) in triangles {
+                let n = normal.to_array();
+                writeln!(out, "  facet normal {:e} {:e} {:e}", n[0], n[1], n[2])?;
+                writeln!(out, "    outer loop")?;
+                for corner in corners.iter() {
+                    let c = corner.to_array();
+                    writeln!(out, "      vertex {:e} {:e} {:e}", c[0], c[1], c[2])?;
+                }
+                writeln!(out, "    endloop")?;
+                writeln!(out, "  endfacet")?;
+            }
+            writeln!(out, "endsolid {}", name)?;
+        },
+        Format::Binary => {
+            //A header starting with "solid" would make readers take it for ascii
+            let mut header = [0u8;HEADER_LEN];
+            let name = if name.starts_with("solid") { "" } else { name };
+            let len = name.len().min(HEADER_LEN);
+            header[..len].copy_from_slice(&name.as_bytes()[..len]);
+            out.write_all(&header)?;
+            out.write_all(&((indices.len() / 3) as u32).to_le_bytes())?;
+            for (normal, corners) in triangles {
+                for v in [normal, corners[0], corners[1], corners[2]].iter() {
+                    for f in v.to_array().iter() {
+                        out.write_all(&f.to_le_bytes())?;
+                    }
+                }
+                out.write_all(&[0, 0])?;
+            }
+        },
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture_bytes(name: &str) -> Vec<u8> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/test/stl").join(name);
+        std::fs::read(path).unwrap()
+    }
+
+    fn normal(mesh: &Mesh, idx: u32) -> [f32;3] {
+        let v = &mesh.stream(&Semantic::Normal).unwrap().data[idx as usize * 3..];
+        [v[0], v[1], v[2]]
+    }
+
+    #[test]
+    fn welds_a_cube_in_both_formats() {
+        for name in ["cube_ascii.stl", "cube_binary.stl"].iter() {
+            let bytes = fixture_bytes(name);
+            let flat = parse(&bytes, Normals::Flat).unwrap();
+            //Four corners per side, every triangle keeps its side's normal
+            assert_eq!(flat.vertex_count(), 24, "{}", name);
+            assert_eq!(flat.indices.len(), 36, "{}", name);
+            for t in flat.indices.chunks(3) {
+                assert_eq!(normal(&flat, t[0]), normal(&flat, t[1]), "{}", name);
+                assert_eq!(normal(&flat, t[0]), normal(&flat, t[2]), "{}", name);
+            }
+
+            let smooth = parse(&bytes, Normals::Smooth).unwrap();
+            assert_eq!(smooth.name, "cube", "{}", name);
+            assert_eq!(smooth.vertex_count(), 8, "{}", name);
+            assert_eq!(smooth.indices.len(), 36, "{}", name);
+            //Corners point away from the center
+            for v in smooth.interleaved().chunks(6) {
+                let outward = Vec3::new(v[0] - 0.5, v[1] - 0.5, v[2] - 0.5);
+                assert!(Vec3::new(v[3], v[4], v[5]).dot(outward) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn saved_files_load_back_the_same() {
+        let original = parse(&fixture_bytes("cube_ascii.stl"), Normals::Flat).unwrap();
+        let dir = std::env::temp_dir().join(format!("stl_round_trip_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for format in [Format::Ascii, Format::Binary].iter() {
+            let path = dir.join(format!("{:?}.stl", format));
+            save(&path, &original, *format).unwrap();
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(detect_format(&bytes), *format);
+            let saved = parse(&bytes, Normals::Flat).unwrap();
+            assert_eq!(saved.streams, original.streams, "{:?}", format);
+            assert_eq!(saved.indices, original.indices, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn reports_malformed_files() {
+        let mut bytes = fixture_bytes("cube_binary.stl");
+        bytes.truncate(bytes.len() - 20);
+        match parse(&bytes, Normals::Flat) {
+            Err(StlError::Truncated { expected, found }) => assert_eq!((expected, found), (12 * 50, 12 * 50 - 20)),
+            other => panic!("expected a truncated file, got {:?}", other.err()),
+        }
+
+        let src = "solid bad\n facet normal 0 0 1\n  outer loop\n   vertex 0 0 0\n   vertex 1 0\n";
+        match parse(src.as_bytes(), Normals::Flat) {
+            Err(StlError::Syntax { line, .. }) => assert_eq!(line, 5),
+            other => panic!("expected a syntax error, got {:?}", other.err()),
+        }
+    }
+}
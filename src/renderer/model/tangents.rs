@@ -0,0 +1,234 @@
+use crate::renderer::model::mesh::{key, Mesh, Semantic};
+use crate::renderer::model::normals::corner_angles;
+use crate::math::Vec3;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum TangentError {
+    //Tangents follow the normals and the first UV set, run normals first
+    MissingStream(Semantic),
+}
+
+impl fmt::Display for TangentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TangentError::MissingStream(semantic) => write!(f, "mesh has no {:?} stream to build tangents from", semantic),
+        }
+    }
+}
+
+impl std::error::Error for TangentError {}
+
+//Adds a 4 float Tangent stream following the MikkTSpace conventions, so
+//normal maps baked by other tools line up. xyz is the tangent, made
+//orthogonal to the vertex normal, and w is the sign of the bitangent:
+//bitangent = w * cross(normal, tangent). Per face tangents are projected
+//onto the normal and averaged weighted by the corner angle over every corner
+//with the same position, normal, UV and sign, even when the mesh keeps them
+//as separate vertices. Vertices where mirrored UVs meet are split so each
+//side keeps its own sign.
+pub fn generate(mesh: &mut Mesh) -> Result<(), TangentError> {
+    for semantic in [Semantic::Normal, Semantic::TexCoord(0)].iter() {
+        if mesh.stream(semantic).is_none() {
+            return Err(TangentError::MissingStream(semantic.clone()));
+        }
+    }
+    let angles = corner_angles(mesh);
+    let faces: Vec<Option<(Vec3, bool)>> = mesh.indices.chunks(3).map(|t| face_tangent(mesh, t)).collect();
+
+    //Corners of faces with degenerate UVs join whichever side their vertex is on
+    let mut vertex_sign = vec![None; mesh.vertex_count()];
+    for (corner, &idx) in mesh.indices.iter().enumerate() {
+        if let Some((_, positive)) = faces[corner / 3] {
+            vertex_sign[idx as usize].get_or_insert(positive);
+        }
+    }
+    let signs: Vec<bool> = mesh.indices.iter().enumerate().map(|(corner, &idx)| {
+        match faces[corner / 3] {
+            Some((_, positive)) => positive,
+            None => vertex_sign[idx as usize].unwrap_or(true),
+        }
+    }).collect();
+    let owners = mesh.split_vertices(&signs);
+    let positive: Vec<bool> = owners.iter().map(|owner| match owner {
+        Some(corner) => signs[*corner],
+        None => true,
+    }).collect();
+
+    //Vertices only told apart by other streams, like colors, share a tangent
+    let uvs = &mesh.stream(&Semantic::TexCoord(0)).unwrap().data;
+    let mut ids = HashMap::<([u32;3], [u32;3], [u32;3], bool), usize>::new();
+    let groups: Vec<usize> = (0..owners.len()).map(|idx| {
+        let uv = Vec3::new(uvs[idx * 2], uvs[idx * 2 + 1], 0.0);
+        let id = (key(mesh.position(idx as u32)), key(normal(mesh, idx as u32)), key(uv), positive[idx]);
+        let next = ids.len();
+        *ids.entry(id).or_insert(next)
+    }).collect();
+
+    let mut sums = vec![Vec3::zero(); ids.len()];
+    for (corner, &idx) in mesh.indices.iter().enumerate() {
+        if let Some((tangent, _)) = faces[corner / 3] {
+            let n = normal(mesh, idx);
+            let projected = (tangent - n * n.dot(tangent)).normalize();
+            sums[groups[idx as usize]] += projected * angles[corner];
+        }
+    }
+
+    let mut data = Vec::with_capacity(owners.len() * 4);
+    for (idx, group) in groups.iter().enumerate() {
+        let n = normal(mesh, idx as u32);
+        let mut tangent = (sums[*group] - n * n.dot(sums[*group])).normalize();
+        if tangent == Vec3::zero() {
+            tangent = any_perpendicular(n);
+        }
+        let sign = if positive[idx] { 1.0 } else { -1.0 };
+        data.extend_from_slice(&tangent.extend(sign).to_array());
+    }
+    mesh.set_stream(Semantic::Tangent, 4, data);
+    Ok(())
+}
+
+//Direction of increasing u across the triangle, and whether its UVs keep
+//the triangle's winding. None if the UVs don't span an area.
+fn face_tangent(mesh: &Mesh, t: &[u32]) -> Option<(Vec3, bool)> {
+    let uvs = &mesh.stream(&Semantic::TexCoord(0)).unwrap().data;
+    let uv = |idx: u32| (uvs[idx as usize * 2], uvs[idx as usize * 2 + 1]);
+    let (p0, p1, p2) = (mesh.position(t[0]), mesh.position(t[1]), mesh.position(t[2]));
+    let ((u0, v0), (u1, v1), (u2, v2)) = (uv(t[0]), uv(t[1]), uv(t[2]));
+    let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+
+    let signed_area = du1 * dv2 - du2 * dv1;
+    if signed_area == 0.0 {
+        return None;
+    }
+    let tangent = ((p1 - p0) * dv2 - (p2 - p0) * dv1) / signed_area;
+    if tangent.length_squared() == 0.0 {
+        return None;
+    }
+    Some((tangent, signed_area > 0.0))
+}
+
+fn normal(mesh: &Mesh, idx: u32) -> Vec3 {
+    let n = &mesh.stream(&Semantic::Normal).unwrap().data[idx as usize * 3..];
+    Vec3::new(n[0], n[1], n[2]).normalize()
+}
+
+fn any_perpendicular(n: Vec3) -> Vec3 {
+    let axis = if n.x().abs() < 0.9 { Vec3::unit_x() } else { Vec3::unit_y() };
+    (axis - n * n.dot(axis)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::model::normals;
+    use crate::math::Vec4;
+
+    //Two quads side by side in the xy plane, facing +z. The right one has
+    //its u running backwards like a mirrored half of a character.
+    fn mirrored_strip() -> Mesh {
+        let vertices = [
+            -1.0, 0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,  0.0, 0.0,
+            0.0, 1.0, 0.0,  0.0, 1.0,
+            -1.0, 1.0, 0.0, 1.0, 1.0,
+            1.0, 0.0, 0.0,  1.0, 0.0,
+            1.0, 1.0, 0.0,  1.0, 1.0,
+        ];
+        let semantics = [(Semantic::Position, 3), (Semantic::TexCoord(0), 2)];
+        let indices = vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2];
+        Mesh::from_interleaved("strip", &vertices, &semantics, indices)
+    }
+
+    fn tangent(mesh: &Mesh, idx: u32) -> Vec4 {
+        let t = &mesh.stream(&Semantic::Tangent).unwrap().data[idx as usize * 4..];
+        Vec4::new(t[0], t[1], t[2], t[3])
+    }
+
+    #[test]
+    fn follows_u_and_splits_mirrored_seams() {
+        let mut mesh = mirrored_strip();
+        normals::flat(&mut mesh);
+        generate(&mut mesh).unwrap();
+        //The two middle vertices are shared by both halves
+        assert_eq!(mesh.vertex_count(), 8);
+        assert_eq!(mesh.layout().iter().map(|a| a.width).collect::<Vec<_>>(), vec![3, 2, 3, 4]);
+
+        for (tri, t) in mesh.indices.chunks(3).enumerate() {
+            //u runs towards -x on the left quad and towards +x on the right one
+            let expected = if tri < 2 {
+                Vec4::new(-1.0, 0.0, 0.0, -1.0)
+            } else {
+                Vec4::new(1.0, 0.0, 0.0, 1.0)
+            };
+            for &idx in t {
+                assert_approx_eq!(tangent(&mesh, idx), expected);
+            }
+        }
+        //w * cross(n, t) points along increasing v
+        let t = tangent(&mesh, 0);
+        let bitangent = Vec3::unit_z().cross(t.truncate()) * t.w();
+        assert_approx_eq!(bitangent, Vec3::unit_y());
+    }
+
+    #[test]
+    fn tangents_stay_orthogonal_to_smooth_normals() {
+        let mut mesh = mirrored_strip();
+        //Bend the strip so the normals aren't all +z
+        let mut positions = mesh.positions().to_vec();
+        positions[4 * 3 + 2] = 1.0;
+        positions[5 * 3 + 2] = 1.0;
+        mesh.set_stream(Semantic::Position, 3, positions);
+        normals::smooth(&mut mesh, std::f32::consts::PI);
+        generate(&mut mesh).unwrap();
+
+        for idx in 0..mesh.vertex_count() as u32 {
+            let n = &mesh.stream(&Semantic::Normal).unwrap().data[idx as usize * 3..];
+            let t = tangent(&mesh, idx);
+            assert_approx_eq!(Vec3::new(n[0], n[1], n[2]).dot(t.truncate()), 0.0);
+            assert_approx_eq!(t.truncate().length(), 1.0);
+        }
+    }
+
+    #[test]
+    fn averages_vertices_split_by_other_streams() {
+        //Two triangles meeting at the origin at right angles, the corner at
+        //the origin is stored twice because the colors differ. Their face
+        //tangents are +x and (1, 1, 0), MikkTSpace gives both copies the
+        //angle weighted average, 22.5 degrees up from +x.
+        let vertices = [
+            0.0, 0.0, 0.0,  0.0, 0.0, 1.0,  0.0, 0.0,  1.0,
+            1.0, 0.0, 0.0,  0.0, 0.0, 1.0,  1.0, 0.0,  1.0,
+            0.0, 1.0, 0.0,  0.0, 0.0, 1.0,  0.0, 1.0,  1.0,
+            0.0, 0.0, 0.0,  0.0, 0.0, 1.0,  0.0, 0.0,  0.0,
+            -1.0, 0.0, 0.0, 0.0, 0.0, 1.0,  -1.0, 1.0, 0.0,
+        ];
+        let semantics = [(Semantic::Position, 3), (Semantic::Normal, 3), (Semantic::TexCoord(0), 2), (Semantic::Color(0), 1)];
+        let mut mesh = Mesh::from_interleaved("fan", &vertices, &semantics, vec![0, 1, 2, 3, 2, 4]);
+        generate(&mut mesh).unwrap();
+
+        assert_eq!(mesh.vertex_count(), 5);
+        let (sin, cos) = std::f32::consts::FRAC_PI_8.sin_cos();
+        assert_approx_eq!(tangent(&mesh, 0), Vec4::new(cos, sin, 0.0, 1.0));
+        assert_approx_eq!(tangent(&mesh, 3), Vec4::new(cos, sin, 0.0, 1.0));
+        assert_approx_eq!(tangent(&mesh, 1), Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let (sin, cos) = std::f32::consts::FRAC_PI_4.sin_cos();
+        assert_approx_eq!(tangent(&mesh, 4), Vec4::new(cos, sin, 0.0, 1.0));
+    }
+
+    #[test]
+    fn needs_normals_and_uvs() {
+        let mut mesh = mirrored_strip();
+        match generate(&mut mesh) {
+            Err(TangentError::MissingStream(Semantic::Normal)) => {},
+            other => panic!("expected a missing normal stream, got {:?}", other.err()),
+        }
+        mesh.streams.truncate(1);
+        normals::flat(&mut mesh);
+        match generate(&mut mesh) {
+            Err(TangentError::MissingStream(Semantic::TexCoord(0))) => {},
+            other => panic!("expected a missing uv stream, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::math::{Vec2, Vec3};
+
+//Splits a planar polygon into triangles. Returns indices into `points`
+//with the polygon's winding preserved. Convex polygons are fanned from the
+//first vertex, concave ones go through ear clipping.
+pub fn triangulate(points: &[Vec3]) -> Vec<[usize;3]> {
+    let n = points.len();
+    if n < 3 {
+        return Vec::new();
+    }
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+    let projected = project(points);
+    //Work on a counter-clockwise polygon so convex corners have a positive cross
+    let ccw = signed_area(&projected) >= 0.0;
+    let corner = |a: usize, b: usize, c: usize| {
+        let turn = (projected[b] - projected[a]).cross(projected[c] - projected[b]);
+        if ccw { turn } else { -turn }
+    };
+
+    if (0..n).all(|i| corner(i, (i + 1) % n, (i + 2) % n) >= 0.0) {
+        return fan(n);
+    }
+    ear_clip(&projected, &corner)
+}
+
+fn fan(n: usize) -> Vec<[usize;3]> {
+    (1..n - 1).map(|i| [0, i, i + 1]).collect()
+}
+
+fn ear_clip(projected: &[Vec2], corner: &dyn Fn(usize, usize, usize) -> f32) -> Vec<[usize;3]> {
+    let mut remaining: Vec<usize> = (0..projected.len()).collect();
+    let mut triangles = Vec::with_capacity(projected.len() - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            if corner(a, b, c) <= 0.0 {
+                return false;
+            }
+            !remaining.iter()
+                .filter(|&&p| p != a && p != b && p != c)
+                .any(|&p| in_triangle(projected[p], projected[a], projected[b], projected[c]))
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            },
+            None => {
+                //Self-intersecting or degenerate input, fan whatever is left
+                break;
+            },
+        }
+    }
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+//Drops the axis the polygon's (Newell) normal points along the most
+fn project(points: &[Vec3]) -> Vec<Vec2> {
+    let mut normal = Vec3::zero();
+    for i in 0..points.len() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        normal += Vec3::new(
+            (a.y() - b.y()) * (a.z() + b.z()),
+            (a.z() - b.z()) * (a.x() + b.x()),
+            (a.x() - b.x()) * (a.y() + b.y()),
+        );
+    }
+    let n = normal.abs();
+    points.iter().map(|p| {
+        if n.x() >= n.y() && n.x() >= n.z() {
+            Vec2::new(p.y(), p.z())
+        } else if n.y() >= n.z() {
+            Vec2::new(p.z(), p.x())
+        } else {
+            Vec2::new(p.x(), p.y())
+        }
+    }).collect()
+}
+
+fn signed_area(points: &[Vec2]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..points.len() {
+        area += points[i].cross(points[(i + 1) % points.len()]);
+    }
+    area * 0.5
+}
+
+//Points on an edge count as inside so collinear vertices cannot form ears
+fn in_triangle(p: Vec2, a: Vec2, b: Vec2, c: Vec2) -> bool {
+    let d0 = (b - a).cross(p - a);
+    let d1 = (c - b).cross(p - b);
+    let d2 = (a - c).cross(p - c);
+    let has_neg = d0 < 0.0 || d1 < 0.0 || d2 < 0.0;
+    let has_pos = d0 > 0.0 || d1 > 0.0 || d2 > 0.0;
+    !(has_neg && has_pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(points: &[Vec3], tris: &[[usize;3]]) -> f32 {
+        tris.iter()
+            .map(|t| (points[t[1]] - points[t[0]]).cross(points[t[2]] - points[t[0]]).length() * 0.5)
+            .sum()
+    }
+
+    fn winding(points: &[Vec3], tris: &[[usize;3]]) -> Vec<f32> {
+        tris.iter()
+            .map(|t| (points[t[1]] - points[t[0]]).cross(points[t[2]] - points[t[0]]).z())
+            .collect()
+    }
+
+    #[test]
+    fn convex_polygons_fan() {
+        let quad = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        assert_eq!(triangulate(&quad), vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn concave_polygon_is_ear_clipped() {
+        //An arrow head pointing right with the notch at index 4
+        let arrow = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.5, 1.5, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.5, 0.5, 0.0),
+        ];
+        let tris = triangulate(&arrow);
+        assert_eq!(tris.len(), 4);
+        //Shoelace area of the outline
+        assert!((area(&arrow, &tris) - 1.0).abs() < 1e-5);
+        assert!(winding(&arrow, &tris).iter().all(|w| *w > 0.0));
+    }
+
+    #[test]
+    fn clockwise_concave_polygon_keeps_winding() {
+        let mut arrow = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ];
+        arrow.reverse();
+        let tris = triangulate(&arrow);
+        assert_eq!(tris.len(), 2);
+        assert!((area(&arrow, &tris) - 1.0).abs() < 1e-5);
+        assert!(winding(&arrow, &tris).iter().all(|w| *w < 0.0));
+    }
+
+    #[test]
+    fn polygon_in_a_vertical_plane() {
+        //L shape in the xz plane
+        let l = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 1.0),
+            Vec3::new(1.0, 0.0, 2.0),
+            Vec3::new(0.0, 0.0, 2.0),
+        ];
+        let tris = triangulate(&l);
+        assert_eq!(tris.len(), 4);
+        assert!((area(&l, &tris) - 3.0).abs() < 1e-5);
+    }
+}
@@ -1,14 +1,15 @@
 #version 420 core
 layout (location = 0) in vec3 pos;
-layout (location = 1) in vec3 color;
 
 out vec4 ourColor;
 
 uniform mat4 model;
-//uniform vec4 x;
-uniform float c;
+uniform mat4 view;
+uniform mat4 projection;
+//Material diffuse color and dissolve
+uniform vec4 diffuse;
 
 void main() {
-    gl_Position = model * vec4(pos.x, pos.y, pos.z, 1.0);
-    ourColor = c* vec4(color, 1.0);
+    gl_Position = projection * view * model * vec4(pos.x, pos.y, pos.z, 1.0);
+    ourColor = diffuse;
 }